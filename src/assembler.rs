@@ -0,0 +1,1395 @@
+//! A Jasmin-style text format for classes, so test classes can be written without javac.
+//!
+//! ```text
+//! .bytecode 49.0
+//! .class public Sub
+//! .super java/lang/Object
+//!
+//! .field private static count I = 3
+//!
+//! .method public static main()I
+//!     .limit stack 2
+//!     .limit locals 2
+//!     .line 4
+//!     iconst_1
+//!     jsr Twice
+//!     ireturn
+//! Twice:
+//!     astore_1
+//!     iconst_2
+//!     imul
+//!     ret 1
+//! .end method
+//! ```
+//!
+//! Comments start with a `;` at the start of a word. Every instruction is written with its javap mnemonic.
+//! Branches take labels, which name the instruction after them. The encoder picks
+//! the short and wide forms itself, so `bipush`, `sipush` and `iconst_<n>` are all
+//! the same instruction, and `wide` is never needed.
+//!
+//! Operands that refer to the constant pool are written out and added to the pool
+//! as needed:
+//! - classes are internal names: `new java/lang/StringBuilder`
+//! - fields are a class, name and descriptor: `getstatic java/lang/System/out Ljava/io/PrintStream;`
+//! - methods are a class, name and descriptor in one: `invokestatic Sub/f(I)I`.
+//!   `invokeinterface` takes an optional argument slot count after that
+//! - `ldc` takes a quoted string, an int or a float (`1.5`, `2f`). `ldc2_w` takes
+//!   a long (`5`, `5L`) or a double (`1.5`, `2d`)
+//! - `#<n>` is constant pool entry `n` as-is, for anything else
+//!
+//! `.const <kind> <values>` adds a constant pool entry right away, even if an equal one
+//! exists. Entries are numbered in the order they are added, so these can lay out
+//! the pool. Kinds are `Utf8`, `Integer`, `Float`, `Long`, `Double`, and `Class`,
+//! `String`, `Fieldref`, `Methodref`, `InterfaceMethodref`, `NameAndType`,
+//! `MethodType` and `MethodHandle` with `#<n>` indices.
+//!
+//! Inside a method, `.limit stack` is required. `.limit locals` defaults to the
+//! argument slots. `.line <n>` starts a source line at the next instruction,
+//! `.var <n> is <name> <descriptor> from <label> to <label>` adds a local variable,
+//! `.catch <class or all> from <label> to <label> using <label>` adds an
+//! exception handler and `.throws <class>` adds to the `Exceptions` attribute.
+//!
+//! Classes of version 50 and up need `StackMapTable` frames to pass verification.
+//! A frame is written as
+//!
+//! ```text
+//! .stack
+//!     offset Loop
+//!     locals Object java/lang/String
+//!     locals Integer
+//!     stack Uninitialized New
+//! .end stack
+//! ```
+//!
+//! with one `locals` or `stack` line for each entry, in order. The types are `Top`,
+//! `Integer`, `Float`, `Long`, `Double`, `Null`, `UninitializedThis`, `Object <class>`
+//! and `Uninitialized <label of the new>`. Without an `offset`, the frame is at the
+//! next instruction.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use crate::attributes::attribute::{Attribute, LineNumber, LocalVariable, StackMapFrame, VerificationType};
+use crate::attributes::attribute_info::AttributeInfo;
+use crate::attributes::code::{Code, ExceptionTableItem};
+use crate::bytecode::{Instruction, SwitchTable};
+use crate::class_file::ClassFile;
+use crate::class_version::{ClassVersion, MAGIC};
+use crate::constant_pool::representations::*;
+use crate::constant_pool::{ConstantPool, ConstantPoolInfo};
+use crate::descriptor;
+use crate::field_info::FieldInfo;
+use crate::method_info::MethodInfo;
+
+/// what a class is assembled as when there is no `.bytecode`
+const DEFAULT_VERSION: ClassVersion = ClassVersion::new(52, 0);
+
+const CLASS_FLAGS: &[(&str, u16)] = &[
+    ("public", 0x0001),
+    ("final", 0x0010),
+    ("super", 0x0020),
+    ("interface", 0x0200),
+    ("abstract", 0x0400),
+    ("synthetic", 0x1000),
+    ("annotation", 0x2000),
+    ("enum", 0x4000),
+];
+
+const FIELD_FLAGS: &[(&str, u16)] = &[
+    ("public", 0x0001),
+    ("private", 0x0002),
+    ("protected", 0x0004),
+    ("static", 0x0008),
+    ("final", 0x0010),
+    ("volatile", 0x0040),
+    ("transient", 0x0080),
+    ("synthetic", 0x1000),
+    ("enum", 0x4000),
+];
+
+const METHOD_FLAGS: &[(&str, u16)] = &[
+    ("public", 0x0001),
+    ("private", 0x0002),
+    ("protected", 0x0004),
+    ("static", 0x0008),
+    ("final", 0x0010),
+    ("synchronized", 0x0020),
+    ("bridge", 0x0040),
+    ("varargs", 0x0080),
+    ("native", 0x0100),
+    ("abstract", 0x0400),
+    ("strict", 0x0800),
+    ("synthetic", 0x1000),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    /// the line the error is on, counting from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for AssembleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+type Result<T> = std::result::Result<T, AssembleError>;
+
+/// assemble the text of a class into a `ClassFile`
+pub fn assemble(source: &str) -> Result<ClassFile> {
+    Assembler::new(source).assemble()
+}
+
+/// a constant pool entry the assembler adds at most once
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Constant {
+    Class(String),
+    String(String),
+    Integer(i32),
+    Float(u32),
+    Long(i64),
+    Double(u64),
+    NameAndType(String, String),
+    Fieldref(String, String, String),
+    Methodref(String, String, String),
+    InterfaceMethodref(String, String, String),
+}
+
+/// a branch target or code range that is waiting for its labels
+enum Pending {
+    Ready(Instruction),
+    Branch(fn(u16) -> Instruction, String),
+}
+
+enum PendingSwitch {
+    Table { low: i32, targets: Vec<String>, default: String },
+    Lookup { pairs: Vec<(i32, String)>, default: String },
+}
+
+/// a switch case's key, which `tableswitch` doesn't need, and its label
+type SwitchEntry = (Option<String>, String);
+
+/// a `.stack` entry, which may be waiting for the label of its `new`
+enum PendingType {
+    Ready(VerificationType),
+    Uninitialized(String),
+}
+
+/// where a `.stack` frame goes
+enum FrameAt {
+    Label(String),
+    /// the instruction the `.stack` was written before
+    Index(u16),
+}
+
+struct PendingFrame {
+    at: FrameAt,
+    locals: Vec<PendingType>,
+    stack: Vec<PendingType>,
+}
+
+/// a method body as it is read, before its labels are known
+#[derive(Default)]
+struct MethodBody {
+    code: Vec<Pending>,
+    switches: Vec<PendingSwitch>,
+    labels: HashMap<String, u16>,
+    /// (start, end, handler, catch type)
+    catches: Vec<(String, String, String, u16)>,
+    lines: Vec<LineNumber>,
+    /// (index, name, descriptor, start, end)
+    vars: Vec<(u16, u16, u16, String, String)>,
+    throws: Vec<u16>,
+    frames: Vec<PendingFrame>,
+    max_stack: Option<u16>,
+    max_locals: Option<u16>,
+}
+
+pub struct Assembler<'a> {
+    lines: Vec<&'a str>,
+    /// the line being read, counting from 0
+    line: usize,
+    cp: ConstantPool,
+    constants: HashMap<Constant, u16>,
+}
+
+impl<'a> Assembler<'a> {
+    pub fn new(source: &'a str) -> Assembler<'a> {
+        Assembler {
+            lines: source.lines().collect(),
+            line: 0,
+            cp: ConstantPool::new(),
+            constants: HashMap::new(),
+        }
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T> {
+        Err(AssembleError { line: self.line + 1, message: message.into() })
+    }
+
+    /// the tokens of the next line that has any, or `None` at the end
+    fn next_line(&mut self) -> Result<Option<Vec<String>>> {
+        while self.line < self.lines.len() {
+            let tokens = self.tokens(self.lines[self.line])?;
+            if !tokens.is_empty() {
+                return Ok(Some(tokens));
+            }
+            self.line += 1;
+        }
+        Ok(None)
+    }
+
+    /// split a line into words, quoted strings and `:`s, dropping comments.
+    /// strings keep their quotes so they can be told apart from other words
+    fn tokens(&self, line: &str) -> Result<Vec<String>> {
+        let mut tokens = vec![];
+        let mut chars = line.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            match c {
+                ';' => break,
+                ':' => {
+                    chars.next();
+                    tokens.push(":".to_string());
+                }
+                '"' => {
+                    chars.next();
+                    let mut s = "\"".to_string();
+                    loop {
+                        match chars.next() {
+                            None => return self.error("unterminated string"),
+                            Some('"') => break,
+                            Some('\\') => s.push(match chars.next() {
+                                Some('n') => '\n',
+                                Some('t') => '\t',
+                                Some('r') => '\r',
+                                Some('0') => '\0',
+                                Some('"') => '"',
+                                Some('\\') => '\\',
+                                Some('u') => {
+                                    let hex: String = chars.by_ref().take(4).collect();
+                                    match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                                        Some(c) => c,
+                                        None => return self.error(format!("bad escape \\u{hex}")),
+                                    }
+                                }
+                                other => return self.error(format!("bad escape {other:?}")),
+                            }),
+                            Some(c) => s.push(c),
+                        }
+                    }
+                    tokens.push(s);
+                }
+                c if c.is_whitespace() => {
+                    chars.next();
+                }
+                _ => {
+                    let mut word = String::new();
+                    while let Some(&c) = chars.peek() {
+                        // descriptors have `;`s in them, so only a `;` after a space is a comment
+                        if c.is_whitespace() || c == ':' || c == '"' {
+                            break;
+                        }
+                        word.push(c);
+                        chars.next();
+                    }
+                    tokens.push(word);
+                }
+            }
+        }
+        Ok(tokens)
+    }
+
+    fn int(&self, token: &str) -> Result<i64> {
+        let (negative, digits) = match token.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, token),
+        };
+        let x = match digits.strip_prefix("0x") {
+            Some(hex) => i64::from_str_radix(hex, 16),
+            None => digits.parse(),
+        };
+        match x {
+            Ok(x) if negative => Ok(-x),
+            Ok(x) => Ok(x),
+            Err(_) => self.error(format!("expected a number, not {token}")),
+        }
+    }
+
+    fn number<T: TryFrom<i64>>(&self, token: &str) -> Result<T> {
+        match T::try_from(self.int(token)?) {
+            Ok(x) => Ok(x),
+            Err(_) => self.error(format!("{token} is out of range")),
+        }
+    }
+
+    fn float(&self, token: &str) -> Result<f64> {
+        match token.parse() {
+            Ok(x) => Ok(x),
+            Err(_) => self.error(format!("expected a floating point number, not {token}")),
+        }
+    }
+
+    fn string(&self, token: &str) -> Result<String> {
+        match token.strip_prefix('"') {
+            Some(s) => Ok(s.to_string()),
+            None => self.error(format!("expected a string, not {token}")),
+        }
+    }
+
+    /// the operand at `i`, which must exist
+    fn operand<'t>(&self, tokens: &'t [String], i: usize) -> Result<&'t str> {
+        match tokens.get(i) {
+            Some(t) => Ok(t),
+            None => self.error(format!("{} needs more operands", tokens[0])),
+        }
+    }
+
+    /// check that nothing comes after the first `n` tokens
+    fn end(&self, tokens: &[String], n: usize) -> Result<()> {
+        match tokens.get(n) {
+            Some(t) => self.error(format!("unexpected {t}")),
+            None => Ok(()),
+        }
+    }
+
+    fn flags(&self, tokens: &[String], table: &[(&str, u16)]) -> Result<u16> {
+        let mut flags = 0;
+        for t in tokens {
+            match table.iter().find(|(name, _)| name == t) {
+                Some((_, flag)) => flags |= flag,
+                None => return self.error(format!("unknown flag {t}")),
+            }
+        }
+        Ok(flags)
+    }
+
+    /// `#<n>`, an index into the constant pool as it is
+    fn raw_index(&self, token: &str) -> Result<Option<u16>> {
+        match token.strip_prefix('#') {
+            Some(n) => Ok(Some(self.number(n)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn constant(&mut self, c: Constant) -> u16 {
+        if let Some(index) = self.constants.get(&c) {
+            return *index;
+        }
+        let info = match &c {
+            Constant::Class(name) => ConstantPoolInfo::Class(Class { name_index: self.cp.utf8_index(name) }),
+            Constant::String(s) => ConstantPoolInfo::JString(JString { string_index: self.cp.utf8_index(s) }),
+            Constant::Integer(x) => ConstantPoolInfo::Integer(Integer { bytes: *x as u32 }),
+            Constant::Float(bits) => ConstantPoolInfo::Float(Float { bytes: *bits }),
+            Constant::Long(x) => ConstantPoolInfo::Long(Long {
+                high_bytes: (*x as u64 >> 32) as u32,
+                low_bytes: *x as u32,
+            }),
+            Constant::Double(bits) => ConstantPoolInfo::Double(Double {
+                high_bytes: (bits >> 32) as u32,
+                low_bytes: *bits as u32,
+            }),
+            Constant::NameAndType(name, descriptor) => ConstantPoolInfo::NameAndType(NameAndType {
+                name_index: self.cp.utf8_index(name),
+                descriptor_index: self.cp.utf8_index(descriptor),
+            }),
+            Constant::Fieldref(class, name, descriptor) |
+            Constant::Methodref(class, name, descriptor) |
+            Constant::InterfaceMethodref(class, name, descriptor) => {
+                let class_index = self.constant(Constant::Class(class.clone()));
+                let name_and_type_index = self.constant(Constant::NameAndType(name.clone(), descriptor.clone()));
+                match c {
+                    Constant::Fieldref(..) => ConstantPoolInfo::Fieldref(Fieldref { class_index, name_and_type_index }),
+                    Constant::Methodref(..) => ConstantPoolInfo::Methodref(Methodref { class_index, name_and_type_index }),
+                    _ => ConstantPoolInfo::InterfaceMethodref(InterfaceMethodref { class_index, name_and_type_index }),
+                }
+            }
+        };
+        let index = self.cp.push(info);
+        self.constants.insert(c, index);
+        index
+    }
+
+    fn class(&mut self, token: &str) -> Result<u16> {
+        Ok(match self.raw_index(token)? {
+            Some(n) => n,
+            None => self.constant(Constant::Class(token.to_string())),
+        })
+    }
+
+    /// `class/name descriptor`
+    fn field_ref(&mut self, tokens: &[String]) -> Result<u16> {
+        let reference = self.operand(tokens, 1)?;
+        if let Some(n) = self.raw_index(reference)? {
+            self.end(tokens, 2)?;
+            return Ok(n);
+        }
+        let descriptor = self.operand(tokens, 2)?.to_string();
+        self.end(tokens, 3)?;
+        match reference.rsplit_once('/') {
+            Some((class, name)) =>
+                Ok(self.constant(Constant::Fieldref(class.to_string(), name.to_string(), descriptor))),
+            None => self.error(format!("expected class/field, not {reference}")),
+        }
+    }
+
+    /// `class/name(descriptor)`, returning the entry and the descriptor
+    fn method_ref(&mut self, token: &str, interface: bool) -> Result<(u16, Option<String>)> {
+        if let Some(n) = self.raw_index(token)? {
+            return Ok((n, None));
+        }
+        let (class, name, descriptor) = match token.find('(') {
+            Some(i) => match token[..i].rsplit_once('/') {
+                Some((class, name)) => (class.to_string(), name.to_string(), token[i..].to_string()),
+                None => return self.error(format!("expected class/method(descriptor), not {token}")),
+            },
+            None => return self.error(format!("expected class/method(descriptor), not {token}")),
+        };
+        let c = if interface {
+            Constant::InterfaceMethodref(class, name, descriptor.clone())
+        } else {
+            Constant::Methodref(class, name, descriptor.clone())
+        };
+        Ok((self.constant(c), Some(descriptor)))
+    }
+
+    /// the operand of `ldc`, `ldc_w` or `ldc2_w`
+    fn loadable(&mut self, token: &str, wide: bool) -> Result<u16> {
+        if let Some(n) = self.raw_index(token)? {
+            return Ok(n);
+        }
+        if token.starts_with('"') {
+            let s = self.string(token)?;
+            return Ok(self.constant(Constant::String(s)));
+        }
+        let is_float = |t: &str| !t.trim_start_matches('-').starts_with("0x") && (
+            t.contains(['.', 'e', 'E']) || t.ends_with(['f', 'F', 'd', 'D']) ||
+            t.ends_with("NaN") || t.ends_with("Infinity")
+        );
+        let c = match (wide, is_float(token)) {
+            (false, false) => Constant::Integer(self.number(token)?),
+            (false, true) => {
+                let x = self.float(token.trim_end_matches(['f', 'F']))?;
+                Constant::Float((x as f32).to_bits())
+            }
+            (true, false) => Constant::Long(self.int(token.trim_end_matches(['l', 'L']))?),
+            (true, true) => Constant::Double(self.float(token.trim_end_matches(['d', 'D']))?.to_bits()),
+        };
+        Ok(self.constant(c))
+    }
+
+    /// `.const <kind> <values>`, which is always added as a new entry
+    fn raw_constant(&mut self, tokens: &[String]) -> Result<()> {
+        let kind = self.operand(tokens, 1)?;
+        let args = &tokens[2..];
+        let index = |i: usize| -> Result<u16> {
+            let t = self.operand(tokens, i + 2)?;
+            match self.raw_index(t)? {
+                Some(n) => Ok(n),
+                None => self.error(format!("expected #<index>, not {t}")),
+            }
+        };
+        let (info, count) = match kind {
+            "Utf8" => {
+                let bytes = crate::mutf8::encode(&self.string(self.operand(tokens, 2)?)?);
+                (ConstantPoolInfo::Utf8(Utf8 { length: bytes.len() as u16, bytes }), 1)
+            }
+            "Integer" => (ConstantPoolInfo::Integer(Integer { bytes: self.number::<i32>(self.operand(tokens, 2)?)? as u32 }), 1),
+            "Float" => (ConstantPoolInfo::Float(Float { bytes: (self.float(self.operand(tokens, 2)?)? as f32).to_bits() }), 1),
+            "Long" | "Double" => {
+                let bits = match kind {
+                    "Long" => self.int(self.operand(tokens, 2)?)? as u64,
+                    _ => self.float(self.operand(tokens, 2)?)?.to_bits(),
+                };
+                let (high_bytes, low_bytes) = ((bits >> 32) as u32, bits as u32);
+                (match kind {
+                    "Long" => ConstantPoolInfo::Long(Long { high_bytes, low_bytes }),
+                    _ => ConstantPoolInfo::Double(Double { high_bytes, low_bytes }),
+                }, 1)
+            }
+            "Class" => (ConstantPoolInfo::Class(Class { name_index: index(0)? }), 1),
+            "String" => (ConstantPoolInfo::JString(JString { string_index: index(0)? }), 1),
+            "MethodType" => (ConstantPoolInfo::MethodType(MethodType { descriptor_index: index(0)? }), 1),
+            "NameAndType" => (ConstantPoolInfo::NameAndType(NameAndType {
+                name_index: index(0)?,
+                descriptor_index: index(1)?,
+            }), 2),
+            "Fieldref" => (ConstantPoolInfo::Fieldref(Fieldref {
+                class_index: index(0)?,
+                name_and_type_index: index(1)?,
+            }), 2),
+            "Methodref" => (ConstantPoolInfo::Methodref(Methodref {
+                class_index: index(0)?,
+                name_and_type_index: index(1)?,
+            }), 2),
+            "InterfaceMethodref" => (ConstantPoolInfo::InterfaceMethodref(InterfaceMethodref {
+                class_index: index(0)?,
+                name_and_type_index: index(1)?,
+            }), 2),
+            "MethodHandle" => (ConstantPoolInfo::MethodHandle(MethodHandle {
+                reference_kind: self.number(self.operand(tokens, 2)?)?,
+                reference_index: index(1)?,
+            }), 2),
+            _ => return self.error(format!("unknown constant kind {kind}")),
+        };
+        if args.len() != count {
+            return self.error(format!("{kind} takes {count} values"));
+        }
+        self.cp.push(info);
+        Ok(())
+    }
+
+    pub fn assemble(mut self) -> Result<ClassFile> {
+        let mut version = DEFAULT_VERSION;
+        let mut access_flags = None;
+        let mut this_class = None;
+        let mut super_class = None;
+        let mut interfaces = vec![];
+        let mut fields = vec![];
+        let mut methods = vec![];
+        let mut attributes = vec![];
+
+        while let Some(tokens) = self.next_line()? {
+            match tokens[0].as_str() {
+                ".bytecode" => {
+                    let v = self.operand(&tokens, 1)?;
+                    let (major, minor) = v.split_once('.').unwrap_or((v, "0"));
+                    version = ClassVersion::new(self.number(major)?, self.number(minor)?);
+                    self.end(&tokens, 2)?;
+                }
+                ".source" => {
+                    let name = self.operand(&tokens, 1)?.to_string();
+                    self.end(&tokens, 2)?;
+                    attributes.push(Attribute::SourceFile(self.cp.utf8_index(&name)));
+                }
+                ".const" => self.raw_constant(&tokens)?,
+                directive @ (".class" | ".interface") => {
+                    if this_class.is_some() {
+                        return self.error("there is already a .class");
+                    }
+                    let name = self.operand(&tokens, tokens.len() - 1)?;
+                    let mut flags = self.flags(&tokens[1..tokens.len() - 1], CLASS_FLAGS)?;
+                    flags |= match directive {
+                        ".class" => 0x0020,
+                        _ => 0x0200 | 0x0400,
+                    };
+                    access_flags = Some(flags);
+                    this_class = Some((self.class(name)?, name.to_string()));
+                }
+                ".super" => {
+                    let name = self.operand(&tokens, 1)?;
+                    super_class = Some(self.class(name)?);
+                    self.end(&tokens, 2)?;
+                }
+                ".implements" => {
+                    let name = self.operand(&tokens, 1)?;
+                    interfaces.push(self.class(name)?);
+                    self.end(&tokens, 2)?;
+                }
+                ".field" => fields.push(self.field(&tokens)?),
+                ".method" => methods.push(self.method(&tokens)?),
+                t => return self.error(format!("unexpected {t}")),
+            }
+            self.line += 1;
+        }
+
+        let (this_class, name) = match this_class {
+            Some(c) => c,
+            None => return self.error("missing .class"),
+        };
+        let super_class = match super_class {
+            Some(c) => c,
+            None if name == "java/lang/Object" => 0,
+            None => self.constant(Constant::Class("java/lang/Object".to_string())),
+        };
+        let attributes = self.attributes(&attributes)?;
+
+        Ok(ClassFile {
+            magic: MAGIC,
+            minor_version: version.minor,
+            major_version: version.major,
+            constant_pool_count: self.cp.count() as u16,
+            constant_pool: self.cp,
+            access_flags: access_flags.unwrap_or(0),
+            this_class,
+            super_class,
+            interfaces_count: interfaces.len() as u16,
+            interfaces,
+            fields_count: fields.len() as u16,
+            fields,
+            methods_count: methods.len() as u16,
+            methods,
+            attributes_count: attributes.len() as u16,
+            attributes,
+        })
+    }
+
+    fn attributes(&mut self, attributes: &[Attribute]) -> Result<Vec<AttributeInfo>> {
+        let mut infos = vec![];
+        for a in attributes {
+            match a.to_info(&mut self.cp) {
+                Ok(ai) => infos.push(ai),
+                Err(e) => return self.error(format!("could not write {}: {e:?}", a.name())),
+            }
+        }
+        Ok(infos)
+    }
+
+    /// `.field <flags> <name> <descriptor> [= <value>]`
+    fn field(&mut self, tokens: &[String]) -> Result<FieldInfo> {
+        let (tokens, value) = match tokens.iter().position(|t| t == "=") {
+            Some(i) => (&tokens[..i], Some(self.operand(tokens, i + 1)?.to_string())),
+            None => (tokens, None),
+        };
+        if tokens.len() < 3 {
+            return self.error(".field needs a name and a descriptor");
+        }
+        let access_flags = self.flags(&tokens[1..tokens.len() - 2], FIELD_FLAGS)?;
+        let name = &tokens[tokens.len() - 2];
+        let descriptor = &tokens[tokens.len() - 1];
+
+        let mut attributes = vec![];
+        if let Some(value) = value {
+            let c = match descriptor.as_str() {
+                "I" | "S" | "C" | "B" | "Z" => Constant::Integer(self.number(&value)?),
+                "J" => Constant::Long(self.int(value.trim_end_matches(['l', 'L']))?),
+                "F" => Constant::Float((self.float(value.trim_end_matches(['f', 'F']))? as f32).to_bits()),
+                "D" => Constant::Double(self.float(value.trim_end_matches(['d', 'D']))?.to_bits()),
+                "Ljava/lang/String;" => Constant::String(self.string(&value)?),
+                _ => return self.error(format!("a {descriptor} field can't have a constant value")),
+            };
+            attributes.push(Attribute::ConstantValue(self.constant(c)));
+        }
+        let attributes = self.attributes(&attributes)?;
+
+        Ok(FieldInfo {
+            access_flags,
+            name_index: self.cp.utf8_index(name),
+            descriptor_index: self.cp.utf8_index(descriptor),
+            attributes_count: attributes.len() as u16,
+            attributes,
+        })
+    }
+
+    /// `.method <flags> <name><descriptor>`, up to `.end method`
+    fn method(&mut self, tokens: &[String]) -> Result<MethodInfo> {
+        let signature = self.operand(tokens, tokens.len().max(2) - 1)?;
+        let (name, descriptor) = match signature.find('(') {
+            Some(i) => signature.split_at(i),
+            None => return self.error(format!("expected name(descriptor), not {signature}")),
+        };
+        let (name, descriptor) = (name.to_string(), descriptor.to_string());
+        let access_flags = self.flags(&tokens[1..tokens.len() - 1], METHOD_FLAGS)?;
+        let start = self.line;
+
+        let mut body = MethodBody::default();
+        loop {
+            self.line += 1;
+            let tokens = match self.next_line()? {
+                Some(tokens) => tokens,
+                None => {
+                    self.line = start;
+                    return self.error(format!("{name} has no .end method"));
+                }
+            };
+            if tokens[0] == ".end" {
+                if tokens.get(1).map(String::as_str) != Some("method") {
+                    return self.error("expected .end method");
+                }
+                break;
+            }
+            self.method_line(&tokens, &mut body)?;
+        }
+
+        let mut attributes = vec![];
+        if !body.throws.is_empty() {
+            attributes.push(Attribute::Exceptions(body.throws.clone()));
+        }
+        let mut attributes = self.attributes(&attributes)?;
+
+        if !body.code.is_empty() {
+            let arg_slots = match descriptor::arg_slots(&descriptor) {
+                Some(slots) => slots as u16,
+                None => return self.error(format!("bad method descriptor {descriptor}")),
+            };
+            let max_locals = body.max_locals.unwrap_or(arg_slots + (access_flags & 0x0008 == 0) as u16);
+            let code = self.code(body, max_locals)?;
+            match code.to_info(&mut self.cp) {
+                Ok(ai) => attributes.insert(0, ai),
+                Err(e) => return self.error(format!("could not write the code of {name}: {e:?}")),
+            }
+        }
+
+        Ok(MethodInfo {
+            access_flags,
+            name_index: self.cp.utf8_index(&name),
+            descriptor_index: self.cp.utf8_index(&descriptor),
+            attributes_count: attributes.len() as u16,
+            attributes,
+        })
+    }
+
+    /// resolve the labels of a method body
+    fn code(&self, body: MethodBody, max_locals: u16) -> Result<Code> {
+        let label = |name: &String| -> Result<u16> {
+            match body.labels.get(name) {
+                Some(index) => Ok(*index),
+                None => self.error(format!("no label {name}")),
+            }
+        };
+        let max_stack = match body.max_stack {
+            Some(n) => n,
+            None => return self.error("missing .limit stack"),
+        };
+
+        let mut code = Vec::with_capacity(body.code.len());
+        for pending in &body.code {
+            code.push(match pending {
+                Pending::Ready(instruction) => *instruction,
+                Pending::Branch(instruction, target) => instruction(label(target)?),
+            });
+        }
+
+        let mut switches = vec![];
+        for switch in &body.switches {
+            switches.push(match switch {
+                PendingSwitch::Table { low, targets, default } => SwitchTable::Table {
+                    default: label(default)?,
+                    low: *low,
+                    targets: targets.iter().map(label).collect::<Result<_>>()?,
+                },
+                PendingSwitch::Lookup { pairs, default } => {
+                    let mut resolved = vec![];
+                    for (key, target) in pairs {
+                        resolved.push((*key, label(target)?));
+                    }
+                    resolved.sort_by_key(|(key, _)| *key);
+                    SwitchTable::Lookup { default: label(default)?, pairs: resolved }
+                }
+            });
+        }
+
+        let mut exception_table = vec![];
+        for (start, end, handler, catch_type) in &body.catches {
+            exception_table.push(ExceptionTableItem {
+                start_pc: label(start)?,
+                end_pc: label(end)?,
+                handler_pc: label(handler)?,
+                catch_type: *catch_type,
+            });
+        }
+
+        let mut attributes = vec![];
+        if !body.frames.is_empty() {
+            attributes.push(Attribute::StackMapTable(self.stack_map(&body, &label)?));
+        }
+        if !body.lines.is_empty() {
+            attributes.push(Attribute::LineNumberTable(body.lines.clone()));
+        }
+        if !body.vars.is_empty() {
+            let mut vars = vec![];
+            for (index, name_index, descriptor_index, start, end) in &body.vars {
+                let (start, end) = (label(start)?, label(end)?);
+                if end < start {
+                    return self.error(format!("variable {index} ends before it starts"));
+                }
+                vars.push(LocalVariable { start_pc: start, length: end - start, name_index: *name_index, descriptor_index: *descriptor_index, index: *index });
+            }
+            attributes.push(Attribute::LocalVariableTable(vars));
+        }
+
+        Ok(Code {
+            max_stack,
+            max_locals,
+            code: Code::cells(code),
+            switches,
+            byte_offsets: vec![],
+            exception_table,
+            attributes,
+        })
+    }
+
+    /// the `.stack` frames in order, as full frames
+    fn stack_map(&self, body: &MethodBody, label: &dyn Fn(&String) -> Result<u16>) -> Result<Vec<StackMapFrame>> {
+        let resolve = |types: &[PendingType]| -> Result<Vec<VerificationType>> {
+            types.iter().map(|t| match t {
+                PendingType::Ready(t) => Ok(*t),
+                PendingType::Uninitialized(new) => Ok(VerificationType::Uninitialized(label(new)?)),
+            }).collect()
+        };
+        let mut frames = vec![];
+        for frame in &body.frames {
+            let at = match &frame.at {
+                FrameAt::Label(name) => label(name)?,
+                FrameAt::Index(index) => *index,
+            };
+            frames.push((at, resolve(&frame.locals)?, resolve(&frame.stack)?));
+        }
+        frames.sort_by_key(|(at, ..)| *at);
+
+        let mut previous: Option<u16> = None;
+        let mut table = vec![];
+        for (at, locals, stack) in frames {
+            let offset_delta = match previous {
+                None => at,
+                Some(p) if p == at => return self.error(format!("two stack map frames at instruction {at}")),
+                Some(p) => at - p - 1,
+            };
+            previous = Some(at);
+            table.push(StackMapFrame::Full { offset_delta, locals, stack });
+        }
+        Ok(table)
+    }
+
+    /// the lines of a `.stack`, up to and including `.end stack`
+    fn stack_frame(&mut self, body: &MethodBody) -> Result<PendingFrame> {
+        let mut frame = PendingFrame { at: FrameAt::Index(body.code.len() as u16), locals: vec![], stack: vec![] };
+        loop {
+            self.line += 1;
+            let tokens = match self.next_line()? {
+                Some(tokens) => tokens,
+                None => return self.error(".stack has no .end stack"),
+            };
+            let entries = match tokens[0].as_str() {
+                ".end" if tokens.get(1).map(String::as_str) == Some("stack") => return Ok(frame),
+                "offset" => {
+                    frame.at = FrameAt::Label(self.operand(&tokens, 1)?.to_string());
+                    self.end(&tokens, 2)?;
+                    continue;
+                }
+                "locals" => &mut frame.locals,
+                "stack" => &mut frame.stack,
+                t => return self.error(format!("expected offset, locals, stack or .end stack, not {t}")),
+            };
+            let (t, n) = match self.operand(&tokens, 1)? {
+                "Top" => (PendingType::Ready(VerificationType::Top), 2),
+                "Integer" => (PendingType::Ready(VerificationType::Integer), 2),
+                "Float" => (PendingType::Ready(VerificationType::Float), 2),
+                "Long" => (PendingType::Ready(VerificationType::Long), 2),
+                "Double" => (PendingType::Ready(VerificationType::Double), 2),
+                "Null" => (PendingType::Ready(VerificationType::Null), 2),
+                "UninitializedThis" => (PendingType::Ready(VerificationType::UninitializedThis), 2),
+                "Object" => {
+                    let class = self.operand(&tokens, 2)?.to_string();
+                    (PendingType::Ready(VerificationType::Object(self.class(&class)?)), 3)
+                }
+                "Uninitialized" => (PendingType::Uninitialized(self.operand(&tokens, 2)?.to_string()), 3),
+                t => return self.error(format!("unknown verification type {t}")),
+            };
+            self.end(&tokens, n)?;
+            entries.push(t);
+        }
+    }
+
+    fn method_line(&mut self, tokens: &[String], body: &mut MethodBody) -> Result<()> {
+        let mut tokens = tokens;
+        // labels, which may come before an instruction on the same line
+        while tokens.len() >= 2 && tokens[1] == ":" {
+            let here = body.code.len() as u16;
+            if body.labels.insert(tokens[0].clone(), here).is_some() {
+                return self.error(format!("label {} is already defined", tokens[0]));
+            }
+            tokens = &tokens[2..];
+        }
+        if tokens.is_empty() {
+            return Ok(());
+        }
+
+        match tokens[0].as_str() {
+            ".limit" => {
+                let n = self.number(self.operand(tokens, 2)?)?;
+                match self.operand(tokens, 1)? {
+                    "stack" => body.max_stack = Some(n),
+                    "locals" => body.max_locals = Some(n),
+                    what => return self.error(format!("unknown limit {what}")),
+                }
+                self.end(tokens, 3)
+            }
+            ".line" => {
+                body.lines.push(LineNumber {
+                    start_pc: body.code.len() as u16,
+                    line_number: self.number(self.operand(tokens, 1)?)?,
+                });
+                self.end(tokens, 2)
+            }
+            ".stack" => {
+                self.end(tokens, 1)?;
+                let frame = self.stack_frame(body)?;
+                body.frames.push(frame);
+                Ok(())
+            }
+            ".throws" => {
+                let class = self.class(self.operand(tokens, 1)?)?;
+                body.throws.push(class);
+                self.end(tokens, 2)
+            }
+            ".catch" => {
+                // .catch <class> from <start> to <end> using <handler>
+                let words = [self.operand(tokens, 2)?, self.operand(tokens, 4)?, self.operand(tokens, 6)?];
+                if words != ["from", "to", "using"] {
+                    return self.error("expected .catch <class> from <label> to <label> using <label>");
+                }
+                let catch_type = match self.operand(tokens, 1)? {
+                    "all" => 0,
+                    class => self.class(class)?,
+                };
+                body.catches.push((tokens[3].clone(), tokens[5].clone(), self.operand(tokens, 7)?.to_string(), catch_type));
+                self.end(tokens, 8)
+            }
+            ".var" => {
+                // .var <index> is <name> <descriptor> from <start> to <end>
+                let words = [self.operand(tokens, 2)?, self.operand(tokens, 5)?, self.operand(tokens, 7)?];
+                if words != ["is", "from", "to"] {
+                    return self.error("expected .var <index> is <name> <descriptor> from <label> to <label>");
+                }
+                let index = self.number(&tokens[1])?;
+                let name_index = self.cp.utf8_index(&tokens[3]);
+                let descriptor_index = self.cp.utf8_index(&tokens[4]);
+                body.vars.push((index, name_index, descriptor_index, tokens[6].clone(), self.operand(tokens, 8)?.to_string()));
+                self.end(tokens, 9)
+            }
+            "tableswitch" => {
+                let low = self.number(self.operand(tokens, 1)?)?;
+                self.end(tokens, 2)?;
+                let (entries, default) = self.switch_entries()?;
+                let targets = entries.into_iter().map(|(_, target)| target).collect();
+                body.code.push(Pending::Ready(Instruction::Tableswitch(body.switches.len() as u16)));
+                body.switches.push(PendingSwitch::Table { low, targets, default });
+                Ok(())
+            }
+            "lookupswitch" => {
+                self.end(tokens, 1)?;
+                let (entries, default) = self.switch_entries()?;
+                let mut pairs = vec![];
+                for (key, target) in entries {
+                    match key {
+                        Some(key) => pairs.push((self.number(&key)?, target)),
+                        None => return self.error("lookupswitch entries need a key"),
+                    }
+                }
+                body.code.push(Pending::Ready(Instruction::Lookupswitch(body.switches.len() as u16)));
+                body.switches.push(PendingSwitch::Lookup { pairs, default });
+                Ok(())
+            }
+            _ => {
+                let instruction = self.instruction(tokens)?;
+                body.code.push(instruction);
+                Ok(())
+            }
+        }
+    }
+
+    /// the lines after a switch, up to and including `default : <label>`.
+    /// entries are `<key> : <label>`, or just `<label>` in a `tableswitch`
+    fn switch_entries(&mut self) -> Result<(Vec<SwitchEntry>, String)> {
+        let mut entries = vec![];
+        loop {
+            self.line += 1;
+            let tokens = match self.next_line()? {
+                Some(tokens) => tokens,
+                None => return self.error("switch has no default"),
+            };
+            match tokens.as_slice() {
+                [default, colon, target] if default == "default" && colon == ":" =>
+                    return Ok((entries, target.clone())),
+                [key, colon, target] if colon == ":" => entries.push((Some(key.clone()), target.clone())),
+                [target] => entries.push((None, target.clone())),
+                _ => return self.error("expected <key> : <label> or default : <label>"),
+            }
+        }
+    }
+
+    fn instruction(&mut self, tokens: &[String]) -> Result<Pending> {
+        use crate::bytecode::Instruction::*;
+
+        let mnemonic = tokens[0].as_str();
+
+        // instructions without operands, and the ones with the operand in their name
+        let simple = match mnemonic {
+            "nop" => Some(Nop),
+            "aconst_null" => Some(AconstNull),
+            "iconst_m1" => Some(Ipush(-1)),
+            "lconst_0" | "lconst_1" => Some(Lconst(mnemonic.as_bytes()[7] - b'0')),
+            "fconst_0" | "fconst_1" | "fconst_2" => Some(Fconst(mnemonic.as_bytes()[7] - b'0')),
+            "dconst_0" | "dconst_1" => Some(Dconst(mnemonic.as_bytes()[7] - b'0')),
+            "iaload" => Some(Iaload),
+            "laload" => Some(Laload),
+            "faload" => Some(Faload),
+            "daload" => Some(Daload),
+            "aaload" => Some(Aaload),
+            "baload" => Some(Baload),
+            "caload" => Some(Caload),
+            "saload" => Some(Saload),
+            "iastore" => Some(Iastore),
+            "lastore" => Some(Lastore),
+            "fastore" => Some(Fastore),
+            "dastore" => Some(Dastore),
+            "aastore" => Some(Aastore),
+            "bastore" => Some(Bastore),
+            "castore" => Some(Castore),
+            "sastore" => Some(Sastore),
+            "pop" => Some(Pop),
+            "pop2" => Some(Pop2),
+            "dup" => Some(Dup),
+            "dup_x1" => Some(DupX1),
+            "dup_x2" => Some(DupX2),
+            "dup2" => Some(Dup2),
+            "dup2_x1" => Some(Dup2X1),
+            "dup2_x2" => Some(Dup2X2),
+            "swap" => Some(Swap),
+            "iadd" => Some(Iadd),
+            "ladd" => Some(Ladd),
+            "fadd" => Some(Fadd),
+            "dadd" => Some(Dadd),
+            "isub" => Some(Isub),
+            "lsub" => Some(Lsub),
+            "fsub" => Some(Fsub),
+            "dsub" => Some(Dsub),
+            "imul" => Some(Imul),
+            "lmul" => Some(Lmul),
+            "fmul" => Some(Fmul),
+            "dmul" => Some(Dmul),
+            "idiv" => Some(Idiv),
+            "ldiv" => Some(Ldiv),
+            "fdiv" => Some(Fdiv),
+            "ddiv" => Some(Ddiv),
+            "irem" => Some(Irem),
+            "lrem" => Some(Lrem),
+            "frem" => Some(Frem),
+            "drem" => Some(Drem),
+            "ineg" => Some(Ineg),
+            "lneg" => Some(Lneg),
+            "fneg" => Some(Fneg),
+            "dneg" => Some(Dneg),
+            "ishl" => Some(Ishl),
+            "lshl" => Some(Lshl),
+            "ishr" => Some(Ishr),
+            "lshr" => Some(Lshr),
+            "iushr" => Some(Iushr),
+            "lushr" => Some(Lushr),
+            "iand" => Some(Iand),
+            "land" => Some(Land),
+            "ior" => Some(Ior),
+            "lor" => Some(Lor),
+            "ixor" => Some(Ixor),
+            "lxor" => Some(Lxor),
+            "i2l" => Some(I2l),
+            "i2f" => Some(I2f),
+            "i2d" => Some(I2d),
+            "l2i" => Some(L2i),
+            "l2f" => Some(L2f),
+            "l2d" => Some(L2d),
+            "f2i" => Some(F2i),
+            "f2l" => Some(F2l),
+            "f2d" => Some(F2d),
+            "d2i" => Some(D2i),
+            "d2l" => Some(D2l),
+            "d2f" => Some(D2f),
+            "i2b" => Some(I2b),
+            "i2c" => Some(I2c),
+            "i2s" => Some(I2s),
+            "lcmp" => Some(Lcmp),
+            "fcmpl" => Some(Fcmpl),
+            "fcmpg" => Some(Fcmpg),
+            "dcmpl" => Some(Dcmpl),
+            "dcmpg" => Some(Dcmpg),
+            "ireturn" => Some(Ireturn),
+            "lreturn" => Some(Lreturn),
+            "freturn" => Some(Freturn),
+            "dreturn" => Some(Dreturn),
+            "areturn" => Some(Areturn),
+            "return" => Some(Return),
+            "arraylength" => Some(Arraylength),
+            "athrow" => Some(Athrow),
+            "monitorenter" => Some(Monitorenter),
+            "monitorexit" => Some(Monitorexit),
+            "breakpoint" => Some(Breakpoint),
+            "impdep1" => Some(Impdep1),
+            "impdep2" => Some(Impdep2),
+            _ => None,
+        };
+        let simple = simple.or_else(|| {
+            // iconst_<n>, and the <x>load_<n> and <x>store_<n> forms
+            let (base, n) = mnemonic.rsplit_once('_')?;
+            let n = match n {
+                "0" | "1" | "2" | "3" => n.parse::<u16>().ok()?,
+                "4" | "5" if base == "iconst" => n.parse::<u16>().ok()?,
+                _ => return None,
+            };
+            Some(match base {
+                "iconst" => Ipush(n as i16),
+                "iload" => Iload(n),
+                "lload" => Lload(n),
+                "fload" => Fload(n),
+                "dload" => Dload(n),
+                "aload" => Aload(n),
+                "istore" => Istore(n),
+                "lstore" => Lstore(n),
+                "fstore" => Fstore(n),
+                "dstore" => Dstore(n),
+                "astore" => Astore(n),
+                _ => return None,
+            })
+        });
+        if let Some(instruction) = simple {
+            self.end(tokens, 1)?;
+            return Ok(Pending::Ready(instruction));
+        }
+
+        let branch: Option<fn(u16) -> Instruction> = match mnemonic {
+            "ifeq" => Some(Ifeq),
+            "ifne" => Some(Ifne),
+            "iflt" => Some(Iflt),
+            "ifge" => Some(Ifge),
+            "ifgt" => Some(Ifgt),
+            "ifle" => Some(Ifle),
+            "if_icmpeq" => Some(IfIcmpeq),
+            "if_icmpne" => Some(IfIcmpne),
+            "if_icmplt" => Some(IfIcmplt),
+            "if_icmpge" => Some(IfIcmpge),
+            "if_icmpgt" => Some(IfIcmpgt),
+            "if_icmple" => Some(IfIcmple),
+            "if_acmpeq" => Some(IfAcmpeq),
+            "if_acmpne" => Some(IfAcmpne),
+            "goto" => Some(Goto),
+            "jsr" => Some(Jsr),
+            "ifnull" => Some(Ifnull),
+            "ifnonnull" => Some(Ifnonnull),
+            "goto_w" => Some(GotoW),
+            "jsr_w" => Some(JsrW),
+            _ => None,
+        };
+        if let Some(branch) = branch {
+            let target = self.operand(tokens, 1)?.to_string();
+            self.end(tokens, 2)?;
+            return Ok(Pending::Branch(branch, target));
+        }
+
+        let local: Option<fn(u16) -> Instruction> = match mnemonic {
+            "iload" => Some(Iload),
+            "lload" => Some(Lload),
+            "fload" => Some(Fload),
+            "dload" => Some(Dload),
+            "aload" => Some(Aload),
+            "istore" => Some(Istore),
+            "lstore" => Some(Lstore),
+            "fstore" => Some(Fstore),
+            "dstore" => Some(Dstore),
+            "astore" => Some(Astore),
+            "ret" => Some(Ret),
+            _ => None,
+        };
+        if let Some(local) = local {
+            let n = self.number(self.operand(tokens, 1)?)?;
+            self.end(tokens, 2)?;
+            return Ok(Pending::Ready(local(n)));
+        }
+
+        let class: Option<fn(u16) -> Instruction> = match mnemonic {
+            "new" => Some(New),
+            "anewarray" => Some(Anewarray),
+            "checkcast" => Some(Checkcast),
+            "instanceof" => Some(Instanceof),
+            _ => None,
+        };
+        if let Some(class) = class {
+            let n = self.class(self.operand(tokens, 1)?)?;
+            self.end(tokens, 2)?;
+            return Ok(Pending::Ready(class(n)));
+        }
+
+        let field: Option<fn(u16) -> Instruction> = match mnemonic {
+            "getstatic" => Some(Getstatic),
+            "putstatic" => Some(Putstatic),
+            "getfield" => Some(Getfield),
+            "putfield" => Some(Putfield),
+            _ => None,
+        };
+        if let Some(field) = field {
+            return Ok(Pending::Ready(field(self.field_ref(tokens)?)));
+        }
+
+        let instruction = match mnemonic {
+            "bipush" | "sipush" => {
+                let x = self.number(self.operand(tokens, 1)?)?;
+                self.end(tokens, 2)?;
+                Ipush(x)
+            }
+            "ldc" | "ldc_w" => {
+                let n = self.loadable(self.operand(tokens, 1)?, false)?;
+                self.end(tokens, 2)?;
+                Ldc(n as u32)
+            }
+            "ldc2_w" => {
+                let n = self.loadable(self.operand(tokens, 1)?, true)?;
+                self.end(tokens, 2)?;
+                Ldc2W(n)
+            }
+            "iinc" => {
+                let n = self.number(self.operand(tokens, 1)?)?;
+                let by = self.number(self.operand(tokens, 2)?)?;
+                self.end(tokens, 3)?;
+                Iinc(n, by)
+            }
+            "invokevirtual" | "invokespecial" | "invokestatic" => {
+                let (n, _) = self.method_ref(self.operand(tokens, 1)?, false)?;
+                self.end(tokens, 2)?;
+                match mnemonic {
+                    "invokevirtual" => Invokevirtual(n),
+                    "invokespecial" => Invokespecial(n),
+                    _ => Invokestatic(n),
+                }
+            }
+            "invokeinterface" => {
+                let (n, descriptor) = self.method_ref(self.operand(tokens, 1)?, true)?;
+                let count = match (tokens.get(2), descriptor) {
+                    (Some(count), _) => self.number(count)?,
+                    (None, Some(descriptor)) => match descriptor::arg_slots(&descriptor) {
+                        // the receiver takes a slot too
+                        Some(slots) => (slots + 1) as u8,
+                        None => return self.error(format!("bad method descriptor {descriptor}")),
+                    },
+                    (None, None) => return self.error("invokeinterface #<n> needs a count"),
+                };
+                self.end(tokens, 3)?;
+                Invokeinterface(n, count)
+            }
+            "invokedynamic" => {
+                let t = self.operand(tokens, 1)?;
+                let n = match self.raw_index(t)? {
+                    Some(n) => n,
+                    None => return self.error("invokedynamic takes #<index>"),
+                };
+                self.end(tokens, 2)?;
+                Invokedynamic(n)
+            }
+            "newarray" => {
+                let atype = match self.operand(tokens, 1)? {
+                    "boolean" => 4,
+                    "char" => 5,
+                    "float" => 6,
+                    "double" => 7,
+                    "byte" => 8,
+                    "short" => 9,
+                    "int" => 10,
+                    "long" => 11,
+                    t => return self.error(format!("unknown array type {t}")),
+                };
+                self.end(tokens, 2)?;
+                Newarray(atype)
+            }
+            "multianewarray" => {
+                let n = self.class(self.operand(tokens, 1)?)?;
+                let dimensions = self.number(self.operand(tokens, 2)?)?;
+                self.end(tokens, 3)?;
+                Multianewarray(n, dimensions)
+            }
+            _ => return self.error(format!("unknown instruction {mnemonic}")),
+        };
+        Ok(Pending::Ready(instruction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attributes::code_reader::CodeReader;
+    use crate::bytecode::Instruction::*;
+    use crate::format_check;
+
+    fn code(cf: &ClassFile, method: usize) -> Code {
+        let ai = &cf.methods[method].attributes[0];
+        assert_eq!(cf.constant_pool.utf8(ai.name_index).unwrap(), "Code");
+        CodeReader::new(&ai.info, &cf.constant_pool, cf.version()).read_code().unwrap()
+    }
+
+    #[test]
+    fn test_files() {
+        let mut paths: Vec<_> = std::fs::read_dir("test/jasmin").unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|e| e == "j"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+        for path in paths {
+            let source = std::fs::read_to_string(&path).unwrap();
+            let cf = assemble(&source).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+            format_check::check(&cf).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+            for i in 0..cf.methods.len() {
+                code(&cf, i);
+            }
+        }
+    }
+
+    #[test]
+    fn method() {
+        let cf = assemble(r#"
+            .class public A
+            .method static f(J)I ; a comment
+                .limit stack 4
+                .catch java/lang/Exception from Start to End using Handler
+            Start:
+                .line 7
+                lload_0
+                ldc2_w 300
+                lcmp
+                ifle Small
+                ldc "big"
+                invokevirtual java/lang/String/length()I
+                ireturn
+            Small: iconst_m1
+            End:
+                ireturn
+            Handler:
+                tableswitch 1
+                    Small
+                    End
+                    default : Start
+            .end method
+        "#).unwrap();
+
+        assert_eq!(cf.constant_pool.class_name(cf.this_class).unwrap(), "A");
+        assert_eq!(cf.constant_pool.class_name(cf.super_class).unwrap(), "java/lang/Object");
+        assert_eq!(cf.access_flags, 0x0021);
+
+        let code = code(&cf, 0);
+        assert_eq!((code.max_stack, code.max_locals), (4, 2));
+        assert!(matches!(code.instructions()[..], [
+            Lload(0), Ldc2W(_), Lcmp, Ifle(7), Ldc(_), Invokevirtual(_), Ireturn,
+            Ipush(-1), Ireturn, Tableswitch(0),
+        ]));
+        assert_eq!(code.switches[0], SwitchTable::Table { default: 0, low: 1, targets: vec![7, 8] });
+        let item = &code.exception_table[0];
+        assert_eq!((item.start_pc, item.end_pc, item.handler_pc), (0, 8, 9));
+        assert_eq!(cf.constant_pool.class_name(item.catch_type).unwrap(), "java/lang/Exception");
+        assert_eq!(code.line_number(3), Some(7));
+    }
+
+    #[test]
+    fn constant_pool_layout() {
+        let cf = assemble(r#"
+            .const Utf8 "B"
+            .const Utf8 "B"
+            .const Class #2
+            .class B
+            .field static final X J = 5
+        "#).unwrap();
+        // the class uses a new entry, whose name is the first utf8
+        assert_eq!(cf.this_class, 4);
+        assert_eq!(cf.constant_pool.utf8(1).unwrap(), "B");
+        assert_eq!(cf.constant_pool.utf8(2).unwrap(), "B");
+        assert_eq!(cf.constant_pool.class(3).unwrap().name_index, 2);
+        assert_eq!(cf.constant_pool.class(4).unwrap().name_index, 1);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source| assemble(source).unwrap_err();
+        assert_eq!(error(".class A\n.method f()V\n  return\n.end method").message, "missing .limit stack");
+        assert_eq!(error(".class A\n.method f()V\n.limit stack 1\n  goto Nowhere\n.end method").message, "no label Nowhere");
+        assert_eq!(error(".class A\n\n.frobnicate").line, 3);
+        assert_eq!(error(".class A\n.method f()V\n.limit stack 1\n  iload_4\n.end method").line, 4);
+    }
+}
@@ -0,0 +1,215 @@
+/// A decoded class, field, method, record component or `Code` attribute.
+///
+/// Constant pool references are kept as indices, the same as in `Code`.
+/// `Code` itself is not listed here; methods keep it in `JavaMethod::code`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Attribute {
+    ConstantValue(u16),
+    SourceFile(u16),
+    Signature(u16),
+    Synthetic,
+    Deprecated,
+    LineNumberTable(Vec<LineNumber>),
+    LocalVariableTable(Vec<LocalVariable>),
+    /// same shape as `LocalVariableTable`, but `descriptor_index` points at a signature
+    LocalVariableTypeTable(Vec<LocalVariable>),
+    StackMapTable(Vec<StackMapFrame>),
+    Exceptions(Vec<u16>),
+    InnerClasses(Vec<InnerClass>),
+    EnclosingMethod {
+        class_index: u16,
+        /// zero if the class is not enclosed by a method
+        method_index: u16,
+    },
+    BootstrapMethods(Vec<BootstrapMethod>),
+    NestHost(u16),
+    NestMembers(Vec<u16>),
+    Record(Vec<RecordComponent>),
+    PermittedSubclasses(Vec<u16>),
+    MethodParameters(Vec<MethodParameter>),
+    RuntimeVisibleAnnotations(Vec<Annotation>),
+    RuntimeInvisibleAnnotations(Vec<Annotation>),
+    RuntimeVisibleParameterAnnotations(Vec<Vec<Annotation>>),
+    RuntimeInvisibleParameterAnnotations(Vec<Vec<Annotation>>),
+    RuntimeVisibleTypeAnnotations(Vec<TypeAnnotation>),
+    RuntimeInvisibleTypeAnnotations(Vec<TypeAnnotation>),
+    AnnotationDefault(ElementValue),
+    /// an attribute we don't know how to decode
+    Unknown {
+        name: String,
+        info: Vec<u8>,
+    },
+}
+
+impl Attribute {
+    /// the name this attribute is stored under in a class file
+    pub fn name(&self) -> &str {
+        use self::Attribute::*;
+
+        match self {
+            ConstantValue(_) => "ConstantValue",
+            SourceFile(_) => "SourceFile",
+            Signature(_) => "Signature",
+            Synthetic => "Synthetic",
+            Deprecated => "Deprecated",
+            LineNumberTable(_) => "LineNumberTable",
+            LocalVariableTable(_) => "LocalVariableTable",
+            LocalVariableTypeTable(_) => "LocalVariableTypeTable",
+            StackMapTable(_) => "StackMapTable",
+            Exceptions(_) => "Exceptions",
+            InnerClasses(_) => "InnerClasses",
+            EnclosingMethod { .. } => "EnclosingMethod",
+            BootstrapMethods(_) => "BootstrapMethods",
+            NestHost(_) => "NestHost",
+            NestMembers(_) => "NestMembers",
+            Record(_) => "Record",
+            PermittedSubclasses(_) => "PermittedSubclasses",
+            MethodParameters(_) => "MethodParameters",
+            RuntimeVisibleAnnotations(_) => "RuntimeVisibleAnnotations",
+            RuntimeInvisibleAnnotations(_) => "RuntimeInvisibleAnnotations",
+            RuntimeVisibleParameterAnnotations(_) => "RuntimeVisibleParameterAnnotations",
+            RuntimeInvisibleParameterAnnotations(_) => "RuntimeInvisibleParameterAnnotations",
+            RuntimeVisibleTypeAnnotations(_) => "RuntimeVisibleTypeAnnotations",
+            RuntimeInvisibleTypeAnnotations(_) => "RuntimeInvisibleTypeAnnotations",
+            AnnotationDefault(_) => "AnnotationDefault",
+            Unknown { name, .. } => name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineNumber {
+    pub start_pc: u16,
+    pub line_number: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalVariable {
+    pub start_pc: u16,
+    pub length: u16,
+    pub name_index: u16,
+    pub descriptor_index: u16,
+    pub index: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationType {
+    Top,
+    Integer,
+    Float,
+    Double,
+    Long,
+    Null,
+    UninitializedThis,
+    /// a class constant pool index
+    Object(u16),
+    /// offset of the `new` instruction that made the value
+    Uninitialized(u16),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StackMapFrame {
+    /// `same_frame` and `same_frame_extended`
+    Same { offset_delta: u16 },
+    /// `same_locals_1_stack_item_frame` and its extended form
+    SameLocals1StackItem { offset_delta: u16, stack: VerificationType },
+    /// drop the last `k` locals
+    Chop { offset_delta: u16, k: u8 },
+    Append { offset_delta: u16, locals: Vec<VerificationType> },
+    Full {
+        offset_delta: u16,
+        locals: Vec<VerificationType>,
+        stack: Vec<VerificationType>,
+    },
+}
+
+impl StackMapFrame {
+    pub fn offset_delta(&self) -> u16 {
+        match self {
+            StackMapFrame::Same { offset_delta } |
+            StackMapFrame::SameLocals1StackItem { offset_delta, .. } |
+            StackMapFrame::Chop { offset_delta, .. } |
+            StackMapFrame::Append { offset_delta, .. } |
+            StackMapFrame::Full { offset_delta, .. } => *offset_delta,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InnerClass {
+    pub inner_class_info_index: u16,
+    pub outer_class_info_index: u16,
+    pub inner_name_index: u16,
+    pub inner_class_access_flags: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootstrapMethod {
+    pub bootstrap_method_ref: u16,
+    pub bootstrap_arguments: Vec<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordComponent {
+    pub name_index: u16,
+    pub descriptor_index: u16,
+    pub attributes: Vec<Attribute>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MethodParameter {
+    pub name_index: u16,
+    pub access_flags: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    pub type_index: u16,
+    /// (element name index, value)
+    pub elements: Vec<(u16, ElementValue)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ElementValue {
+    /// a primitive or string constant; `tag` is one of `BCDFIJSZs`
+    Const { tag: u8, const_value_index: u16 },
+    Enum { type_name_index: u16, const_name_index: u16 },
+    Class(u16),
+    Annotation(Annotation),
+    Array(Vec<ElementValue>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeAnnotation {
+    pub target_type: u8,
+    pub target_info: TargetInfo,
+    pub target_path: Vec<TypePathEntry>,
+    pub annotation: Annotation,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetInfo {
+    TypeParameter(u8),
+    Supertype(u16),
+    TypeParameterBound { type_parameter_index: u8, bound_index: u8 },
+    Empty,
+    FormalParameter(u8),
+    Throws(u16),
+    Localvar(Vec<LocalvarTarget>),
+    Catch(u16),
+    Offset(u16),
+    TypeArgument { offset: u16, type_argument_index: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalvarTarget {
+    pub start_pc: u16,
+    pub length: u16,
+    pub index: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypePathEntry {
+    pub type_path_kind: u8,
+    pub type_argument_index: u8,
+}
@@ -0,0 +1,353 @@
+use crate::attributes::attribute::*;
+use crate::attributes::attribute_info::AttributeInfo;
+use crate::attributes::attribute_reader::AttributeParseError::{EarlyEnd, InvalidFormat, Nested};
+use crate::class_reader::{ClassFormatError, Reason, Structure};
+use crate::constant_pool::ConstantPool;
+use std::slice::Iter;
+
+pub struct AttributeReader<'a> {
+    name: &'a str,
+    bytes: Iter<'a, u8>,
+    cp: &'a ConstantPool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeParseError {
+    /// the attribute ended early while reading the named part
+    EarlyEnd(String),
+    InvalidFormat(String),
+    /// an attribute stored inside of this one was bad
+    Nested(Box<ClassFormatError>),
+}
+
+type Result<T> = std::result::Result<T, AttributeParseError>;
+
+impl Attribute {
+    /// decode an attribute, looking its name up in `cp`
+    pub fn from_info(cp: &ConstantPool, ai: &AttributeInfo) -> std::result::Result<Attribute, ClassFormatError> {
+        let name = cp.utf8(ai.name_index)?;
+        AttributeReader::new(&name, &ai.info, cp)
+            .read_attribute()
+            .map_err(|e| match e {
+                Nested(e) => *e,
+                e => ClassFormatError::new(Reason::BadAttribute(e)),
+            }.within(Structure::Attribute(name.clone())))
+    }
+}
+
+impl<'a> AttributeReader<'a> {
+    pub fn new(name: &'a str, bytes: &'a [u8], cp: &'a ConstantPool) -> AttributeReader<'a> {
+        AttributeReader {
+            name,
+            bytes: bytes.iter(),
+            cp,
+        }
+    }
+
+    fn read_u1(&mut self) -> Result<u8> {
+        self.bytes.next().copied().ok_or_else(|| EarlyEnd(self.name.to_string()))
+    }
+
+    fn read_u2(&mut self) -> Result<u16> {
+        let hi = self.read_u1()? as u16;
+        let lo = self.read_u1()? as u16;
+        Ok(hi << 8 | lo)
+    }
+
+    fn read_u4(&mut self) -> Result<u32> {
+        let hi = self.read_u2()? as u32;
+        let lo = self.read_u2()? as u32;
+        Ok(hi << 16 | lo)
+    }
+
+    /// read a u2 count followed by that many items
+    fn read_table<T>(&mut self, read: fn(&mut Self) -> Result<T>) -> Result<Vec<T>> {
+        let count = self.read_u2()?;
+        let mut v = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            v.push(read(self)?);
+        }
+        Ok(v)
+    }
+
+    pub fn read_attribute(&mut self) -> Result<Attribute> {
+        let attribute = match self.name {
+            "ConstantValue" => Attribute::ConstantValue(self.read_u2()?),
+            "SourceFile" => Attribute::SourceFile(self.read_u2()?),
+            "Signature" => Attribute::Signature(self.read_u2()?),
+            "Synthetic" => Attribute::Synthetic,
+            "Deprecated" => Attribute::Deprecated,
+            "LineNumberTable" => Attribute::LineNumberTable(self.read_table(|r| Ok(LineNumber {
+                start_pc: r.read_u2()?,
+                line_number: r.read_u2()?,
+            }))?),
+            "LocalVariableTable" =>
+                Attribute::LocalVariableTable(self.read_table(Self::read_local_variable)?),
+            "LocalVariableTypeTable" =>
+                Attribute::LocalVariableTypeTable(self.read_table(Self::read_local_variable)?),
+            "StackMapTable" => Attribute::StackMapTable(self.read_table(Self::read_stack_map_frame)?),
+            "Exceptions" => Attribute::Exceptions(self.read_table(Self::read_u2)?),
+            "InnerClasses" => Attribute::InnerClasses(self.read_table(|r| Ok(InnerClass {
+                inner_class_info_index: r.read_u2()?,
+                outer_class_info_index: r.read_u2()?,
+                inner_name_index: r.read_u2()?,
+                inner_class_access_flags: r.read_u2()?,
+            }))?),
+            "EnclosingMethod" => Attribute::EnclosingMethod {
+                class_index: self.read_u2()?,
+                method_index: self.read_u2()?,
+            },
+            "BootstrapMethods" => Attribute::BootstrapMethods(self.read_table(|r| Ok(BootstrapMethod {
+                bootstrap_method_ref: r.read_u2()?,
+                bootstrap_arguments: r.read_table(Self::read_u2)?,
+            }))?),
+            "NestHost" => Attribute::NestHost(self.read_u2()?),
+            "NestMembers" => Attribute::NestMembers(self.read_table(Self::read_u2)?),
+            "Record" => Attribute::Record(self.read_table(Self::read_record_component)?),
+            "PermittedSubclasses" => Attribute::PermittedSubclasses(self.read_table(Self::read_u2)?),
+            "MethodParameters" => {
+                // the only table with a one byte count
+                let count = self.read_u1()?;
+                let mut parameters = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    parameters.push(MethodParameter {
+                        name_index: self.read_u2()?,
+                        access_flags: self.read_u2()?,
+                    });
+                }
+                Attribute::MethodParameters(parameters)
+            }
+            "RuntimeVisibleAnnotations" =>
+                Attribute::RuntimeVisibleAnnotations(self.read_table(Self::read_annotation)?),
+            "RuntimeInvisibleAnnotations" =>
+                Attribute::RuntimeInvisibleAnnotations(self.read_table(Self::read_annotation)?),
+            "RuntimeVisibleParameterAnnotations" =>
+                Attribute::RuntimeVisibleParameterAnnotations(self.read_parameter_annotations()?),
+            "RuntimeInvisibleParameterAnnotations" =>
+                Attribute::RuntimeInvisibleParameterAnnotations(self.read_parameter_annotations()?),
+            "RuntimeVisibleTypeAnnotations" =>
+                Attribute::RuntimeVisibleTypeAnnotations(self.read_table(Self::read_type_annotation)?),
+            "RuntimeInvisibleTypeAnnotations" =>
+                Attribute::RuntimeInvisibleTypeAnnotations(self.read_table(Self::read_type_annotation)?),
+            "AnnotationDefault" => Attribute::AnnotationDefault(self.read_element_value()?),
+            _ => Attribute::Unknown {
+                name: self.name.to_string(),
+                info: self.bytes.as_slice().to_vec(),
+            },
+        };
+
+        if let Attribute::Unknown { .. } = attribute {
+            return Ok(attribute);
+        }
+        if self.bytes.len() != 0 {
+            return Err(InvalidFormat(format!("{} trailing bytes", self.bytes.len())));
+        }
+        Ok(attribute)
+    }
+
+    fn read_local_variable(&mut self) -> Result<LocalVariable> {
+        Ok(LocalVariable {
+            start_pc: self.read_u2()?,
+            length: self.read_u2()?,
+            name_index: self.read_u2()?,
+            descriptor_index: self.read_u2()?,
+            index: self.read_u2()?,
+        })
+    }
+
+    fn read_verification_type(&mut self) -> Result<VerificationType> {
+        Ok(match self.read_u1()? {
+            0 => VerificationType::Top,
+            1 => VerificationType::Integer,
+            2 => VerificationType::Float,
+            3 => VerificationType::Double,
+            4 => VerificationType::Long,
+            5 => VerificationType::Null,
+            6 => VerificationType::UninitializedThis,
+            7 => VerificationType::Object(self.read_u2()?),
+            8 => VerificationType::Uninitialized(self.read_u2()?),
+            x => return Err(InvalidFormat(format!("verification type tag {x}"))),
+        })
+    }
+
+    fn read_stack_map_frame(&mut self) -> Result<StackMapFrame> {
+        let frame_type = self.read_u1()?;
+        Ok(match frame_type {
+            0..=63 => StackMapFrame::Same { offset_delta: frame_type as u16 },
+            64..=127 => StackMapFrame::SameLocals1StackItem {
+                offset_delta: frame_type as u16 - 64,
+                stack: self.read_verification_type()?,
+            },
+            247 => StackMapFrame::SameLocals1StackItem {
+                offset_delta: self.read_u2()?,
+                stack: self.read_verification_type()?,
+            },
+            248..=250 => StackMapFrame::Chop {
+                offset_delta: self.read_u2()?,
+                k: 251 - frame_type,
+            },
+            251 => StackMapFrame::Same { offset_delta: self.read_u2()? },
+            252..=254 => {
+                let offset_delta = self.read_u2()?;
+                let mut locals = Vec::with_capacity((frame_type - 251) as usize);
+                for _ in 0..frame_type - 251 {
+                    locals.push(self.read_verification_type()?);
+                }
+                StackMapFrame::Append { offset_delta, locals }
+            }
+            255 => StackMapFrame::Full {
+                offset_delta: self.read_u2()?,
+                locals: self.read_table(Self::read_verification_type)?,
+                stack: self.read_table(Self::read_verification_type)?,
+            },
+            x => return Err(InvalidFormat(format!("stack map frame type {x}"))),
+        })
+    }
+
+    fn read_record_component(&mut self) -> Result<RecordComponent> {
+        let name_index = self.read_u2()?;
+        let descriptor_index = self.read_u2()?;
+        let count = self.read_u2()?;
+        let mut attributes = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let name_index = self.read_u2()?;
+            let attribute_length = self.read_u4()?;
+            let mut info = Vec::with_capacity(attribute_length as usize);
+            for _ in 0..attribute_length {
+                info.push(self.read_u1()?);
+            }
+            let ai = AttributeInfo { name_index, attribute_length, info };
+            attributes.push(
+                Attribute::from_info(self.cp, &ai).map_err(|e| Nested(Box::new(e)))?
+            );
+        }
+        Ok(RecordComponent { name_index, descriptor_index, attributes })
+    }
+
+    fn read_annotation(&mut self) -> Result<Annotation> {
+        Ok(Annotation {
+            type_index: self.read_u2()?,
+            elements: self.read_table(|r| Ok((r.read_u2()?, r.read_element_value()?)))?,
+        })
+    }
+
+    fn read_element_value(&mut self) -> Result<ElementValue> {
+        let tag = self.read_u1()?;
+        Ok(match tag {
+            b'B' | b'C' | b'D' | b'F' | b'I' | b'J' | b'S' | b'Z' | b's' => ElementValue::Const {
+                tag,
+                const_value_index: self.read_u2()?,
+            },
+            b'e' => ElementValue::Enum {
+                type_name_index: self.read_u2()?,
+                const_name_index: self.read_u2()?,
+            },
+            b'c' => ElementValue::Class(self.read_u2()?),
+            b'@' => ElementValue::Annotation(self.read_annotation()?),
+            b'[' => ElementValue::Array(self.read_table(Self::read_element_value)?),
+            x => return Err(InvalidFormat(format!("element value tag {x}"))),
+        })
+    }
+
+    fn read_parameter_annotations(&mut self) -> Result<Vec<Vec<Annotation>>> {
+        let count = self.read_u1()?;
+        let mut parameters = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            parameters.push(self.read_table(Self::read_annotation)?);
+        }
+        Ok(parameters)
+    }
+
+    fn read_type_annotation(&mut self) -> Result<TypeAnnotation> {
+        let target_type = self.read_u1()?;
+        let target_info = match target_type {
+            0x00 | 0x01 => TargetInfo::TypeParameter(self.read_u1()?),
+            0x10 => TargetInfo::Supertype(self.read_u2()?),
+            0x11 | 0x12 => TargetInfo::TypeParameterBound {
+                type_parameter_index: self.read_u1()?,
+                bound_index: self.read_u1()?,
+            },
+            0x13..=0x15 => TargetInfo::Empty,
+            0x16 => TargetInfo::FormalParameter(self.read_u1()?),
+            0x17 => TargetInfo::Throws(self.read_u2()?),
+            0x40 | 0x41 => TargetInfo::Localvar(self.read_table(|r| Ok(LocalvarTarget {
+                start_pc: r.read_u2()?,
+                length: r.read_u2()?,
+                index: r.read_u2()?,
+            }))?),
+            0x42 => TargetInfo::Catch(self.read_u2()?),
+            0x43..=0x46 => TargetInfo::Offset(self.read_u2()?),
+            0x47..=0x4b => TargetInfo::TypeArgument {
+                offset: self.read_u2()?,
+                type_argument_index: self.read_u1()?,
+            },
+            x => return Err(InvalidFormat(format!("type annotation target type {x:#x}"))),
+        };
+        let path_length = self.read_u1()?;
+        let mut target_path = Vec::with_capacity(path_length as usize);
+        for _ in 0..path_length {
+            target_path.push(TypePathEntry {
+                type_path_kind: self.read_u1()?,
+                type_argument_index: self.read_u1()?,
+            });
+        }
+        Ok(TypeAnnotation {
+            target_type,
+            target_info,
+            target_path,
+            annotation: self.read_annotation()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stack_map_table() {
+        let cp = ConstantPool::new();
+        let bytes = [
+            0x00, 0x04,
+            // same_frame
+            0x05,
+            // same_locals_1_stack_item_frame, Integer
+            0x41, 0x01,
+            // append_frame with two locals
+            0xfd, 0x00, 0x10, 0x04, 0x07, 0x00, 0x03,
+            // full_frame
+            0xff, 0x00, 0x02, 0x00, 0x01, 0x06, 0x00, 0x00,
+        ];
+        let attribute = AttributeReader::new("StackMapTable", &bytes, &cp).read_attribute();
+        assert_eq!(attribute, Ok(Attribute::StackMapTable(vec![
+            StackMapFrame::Same { offset_delta: 5 },
+            StackMapFrame::SameLocals1StackItem { offset_delta: 1, stack: VerificationType::Integer },
+            StackMapFrame::Append {
+                offset_delta: 16,
+                locals: vec![VerificationType::Long, VerificationType::Object(3)],
+            },
+            StackMapFrame::Full {
+                offset_delta: 2,
+                locals: vec![VerificationType::UninitializedThis],
+                stack: vec![],
+            },
+        ])));
+    }
+
+    #[test]
+    fn bad_length() {
+        let cp = ConstantPool::new();
+        assert_eq!(
+            AttributeReader::new("SourceFile", &[0x00], &cp).read_attribute(),
+            Err(EarlyEnd("SourceFile".to_string()))
+        );
+        assert_eq!(
+            AttributeReader::new("SourceFile", &[0x00, 0x01, 0x02], &cp).read_attribute(),
+            Err(InvalidFormat("1 trailing bytes".to_string()))
+        );
+        assert_eq!(
+            AttributeReader::new("Custom", &[0x01, 0x02], &cp).read_attribute(),
+            Ok(Attribute::Unknown { name: "Custom".to_string(), info: vec![0x01, 0x02] })
+        );
+    }
+}
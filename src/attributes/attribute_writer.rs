@@ -0,0 +1,294 @@
+use crate::attributes::attribute::*;
+use crate::attributes::attribute_info::AttributeInfo;
+use crate::class_writer::ClassWriteError;
+use crate::constant_pool::ConstantPool;
+
+/// Encodes an `Attribute` back into the `info` bytes of an `AttributeInfo`.
+pub struct AttributeWriter<'a> {
+    bytes: Vec<u8>,
+    cp: &'a mut ConstantPool,
+}
+
+type Result<T> = std::result::Result<T, ClassWriteError>;
+
+impl Attribute {
+    /// encode an attribute, adding its name to `cp` if it isn't there yet
+    pub fn to_info(&self, cp: &mut ConstantPool) -> Result<AttributeInfo> {
+        let name_index = cp.utf8_index(self.name());
+        let info = AttributeWriter::new(cp).write_attribute(self)?;
+        let attribute_length = u32::try_from(info.len())
+            .map_err(|_| ClassWriteError::TooLong("attribute"))?;
+        Ok(AttributeInfo { name_index, attribute_length, info })
+    }
+}
+
+impl<'a> AttributeWriter<'a> {
+    pub fn new(cp: &'a mut ConstantPool) -> AttributeWriter<'a> {
+        AttributeWriter { bytes: vec![], cp }
+    }
+
+    fn write_u1(&mut self, x: u8) {
+        self.bytes.push(x)
+    }
+
+    fn write_u2(&mut self, x: u16) {
+        self.bytes.extend_from_slice(&x.to_be_bytes())
+    }
+
+    fn write_u4(&mut self, x: u32) {
+        self.bytes.extend_from_slice(&x.to_be_bytes())
+    }
+
+    /// write a u2 count followed by every item
+    fn write_table<T>(&mut self, items: &[T], what: &'static str, write: fn(&mut Self, &T) -> Result<()>) -> Result<()> {
+        self.write_u2(u16::try_from(items.len()).map_err(|_| ClassWriteError::TooMany(what))?);
+        for item in items {
+            write(self, item)?;
+        }
+        Ok(())
+    }
+
+    /// a table with a one byte count
+    fn write_short_count(&mut self, n: usize, what: &'static str) -> Result<()> {
+        self.write_u1(u8::try_from(n).map_err(|_| ClassWriteError::TooMany(what))?);
+        Ok(())
+    }
+
+    fn write_index(&mut self, x: &u16) -> Result<()> {
+        self.write_u2(*x);
+        Ok(())
+    }
+
+    pub fn write_attribute(mut self, attribute: &Attribute) -> Result<Vec<u8>> {
+        match attribute {
+            Attribute::ConstantValue(x) |
+            Attribute::SourceFile(x) |
+            Attribute::Signature(x) |
+            Attribute::NestHost(x) => self.write_u2(*x),
+            Attribute::Synthetic | Attribute::Deprecated => {}
+            Attribute::LineNumberTable(lines) => self.write_table(lines, "line numbers", |w, l| {
+                w.write_u2(l.start_pc);
+                w.write_u2(l.line_number);
+                Ok(())
+            })?,
+            Attribute::LocalVariableTable(vars) | Attribute::LocalVariableTypeTable(vars) =>
+                self.write_table(vars, "local variables", Self::write_local_variable)?,
+            Attribute::StackMapTable(frames) =>
+                self.write_table(frames, "stack map frames", Self::write_stack_map_frame)?,
+            Attribute::Exceptions(xs) => self.write_table(xs, "exceptions", Self::write_index)?,
+            Attribute::InnerClasses(classes) => self.write_table(classes, "inner classes", |w, c| {
+                w.write_u2(c.inner_class_info_index);
+                w.write_u2(c.outer_class_info_index);
+                w.write_u2(c.inner_name_index);
+                w.write_u2(c.inner_class_access_flags);
+                Ok(())
+            })?,
+            Attribute::EnclosingMethod { class_index, method_index } => {
+                self.write_u2(*class_index);
+                self.write_u2(*method_index);
+            }
+            Attribute::BootstrapMethods(methods) => self.write_table(methods, "bootstrap methods", |w, m| {
+                w.write_u2(m.bootstrap_method_ref);
+                w.write_table(&m.bootstrap_arguments, "bootstrap arguments", Self::write_index)
+            })?,
+            Attribute::NestMembers(xs) => self.write_table(xs, "nest members", Self::write_index)?,
+            Attribute::Record(components) =>
+                self.write_table(components, "record components", Self::write_record_component)?,
+            Attribute::PermittedSubclasses(xs) =>
+                self.write_table(xs, "permitted subclasses", Self::write_index)?,
+            Attribute::MethodParameters(parameters) => {
+                self.write_short_count(parameters.len(), "method parameters")?;
+                for p in parameters {
+                    self.write_u2(p.name_index);
+                    self.write_u2(p.access_flags);
+                }
+            }
+            Attribute::RuntimeVisibleAnnotations(annotations) |
+            Attribute::RuntimeInvisibleAnnotations(annotations) =>
+                self.write_table(annotations, "annotations", Self::write_annotation)?,
+            Attribute::RuntimeVisibleParameterAnnotations(parameters) |
+            Attribute::RuntimeInvisibleParameterAnnotations(parameters) => {
+                self.write_short_count(parameters.len(), "parameter annotations")?;
+                for annotations in parameters {
+                    self.write_table(annotations, "annotations", Self::write_annotation)?;
+                }
+            }
+            Attribute::RuntimeVisibleTypeAnnotations(annotations) |
+            Attribute::RuntimeInvisibleTypeAnnotations(annotations) =>
+                self.write_table(annotations, "type annotations", Self::write_type_annotation)?,
+            Attribute::AnnotationDefault(value) => self.write_element_value(value)?,
+            Attribute::Unknown { info, .. } => self.bytes.extend_from_slice(info),
+        }
+        Ok(self.bytes)
+    }
+
+    fn write_local_variable(&mut self, v: &LocalVariable) -> Result<()> {
+        self.write_u2(v.start_pc);
+        self.write_u2(v.length);
+        self.write_u2(v.name_index);
+        self.write_u2(v.descriptor_index);
+        self.write_u2(v.index);
+        Ok(())
+    }
+
+    fn write_verification_type(&mut self, t: &VerificationType) -> Result<()> {
+        match t {
+            VerificationType::Top => self.write_u1(0),
+            VerificationType::Integer => self.write_u1(1),
+            VerificationType::Float => self.write_u1(2),
+            VerificationType::Double => self.write_u1(3),
+            VerificationType::Long => self.write_u1(4),
+            VerificationType::Null => self.write_u1(5),
+            VerificationType::UninitializedThis => self.write_u1(6),
+            VerificationType::Object(x) => {
+                self.write_u1(7);
+                self.write_u2(*x);
+            }
+            VerificationType::Uninitialized(x) => {
+                self.write_u1(8);
+                self.write_u2(*x);
+            }
+        }
+        Ok(())
+    }
+
+    /// frames are written in their shortest form
+    fn write_stack_map_frame(&mut self, frame: &StackMapFrame) -> Result<()> {
+        match frame {
+            StackMapFrame::Same { offset_delta: delta @ 0..=63 } => self.write_u1(*delta as u8),
+            StackMapFrame::Same { offset_delta } => {
+                self.write_u1(251);
+                self.write_u2(*offset_delta);
+            }
+            StackMapFrame::SameLocals1StackItem { offset_delta, stack } => {
+                if *offset_delta <= 63 {
+                    self.write_u1(64 + *offset_delta as u8);
+                } else {
+                    self.write_u1(247);
+                    self.write_u2(*offset_delta);
+                }
+                self.write_verification_type(stack)?;
+            }
+            StackMapFrame::Chop { offset_delta, k } => {
+                self.write_u1(251 - k);
+                self.write_u2(*offset_delta);
+            }
+            StackMapFrame::Append { offset_delta, locals } => {
+                self.write_u1(251 + locals.len() as u8);
+                self.write_u2(*offset_delta);
+                for t in locals {
+                    self.write_verification_type(t)?;
+                }
+            }
+            StackMapFrame::Full { offset_delta, locals, stack } => {
+                self.write_u1(255);
+                self.write_u2(*offset_delta);
+                self.write_table(locals, "stack map locals", Self::write_verification_type)?;
+                self.write_table(stack, "stack map stack", Self::write_verification_type)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_record_component(&mut self, c: &RecordComponent) -> Result<()> {
+        self.write_u2(c.name_index);
+        self.write_u2(c.descriptor_index);
+        self.write_u2(u16::try_from(c.attributes.len()).map_err(|_| ClassWriteError::TooMany("attributes"))?);
+        for a in &c.attributes {
+            let ai = a.to_info(self.cp)?;
+            self.write_u2(ai.name_index);
+            self.write_u4(ai.attribute_length);
+            self.bytes.extend_from_slice(&ai.info);
+        }
+        Ok(())
+    }
+
+    fn write_annotation(&mut self, a: &Annotation) -> Result<()> {
+        self.write_u2(a.type_index);
+        self.write_table(&a.elements, "annotation elements", |w, (name_index, value)| {
+            w.write_u2(*name_index);
+            w.write_element_value(value)
+        })
+    }
+
+    fn write_element_value(&mut self, value: &ElementValue) -> Result<()> {
+        match value {
+            ElementValue::Const { tag, const_value_index } => {
+                self.write_u1(*tag);
+                self.write_u2(*const_value_index);
+            }
+            ElementValue::Enum { type_name_index, const_name_index } => {
+                self.write_u1(b'e');
+                self.write_u2(*type_name_index);
+                self.write_u2(*const_name_index);
+            }
+            ElementValue::Class(x) => {
+                self.write_u1(b'c');
+                self.write_u2(*x);
+            }
+            ElementValue::Annotation(a) => {
+                self.write_u1(b'@');
+                self.write_annotation(a)?;
+            }
+            ElementValue::Array(values) => {
+                self.write_u1(b'[');
+                self.write_table(values, "array elements", Self::write_element_value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_type_annotation(&mut self, a: &TypeAnnotation) -> Result<()> {
+        self.write_u1(a.target_type);
+        match &a.target_info {
+            TargetInfo::TypeParameter(x) | TargetInfo::FormalParameter(x) => self.write_u1(*x),
+            TargetInfo::Supertype(x) | TargetInfo::Throws(x) |
+            TargetInfo::Catch(x) | TargetInfo::Offset(x) => self.write_u2(*x),
+            TargetInfo::TypeParameterBound { type_parameter_index, bound_index } => {
+                self.write_u1(*type_parameter_index);
+                self.write_u1(*bound_index);
+            }
+            TargetInfo::Empty => {}
+            TargetInfo::Localvar(targets) => self.write_table(targets, "local variable targets", |w, t| {
+                w.write_u2(t.start_pc);
+                w.write_u2(t.length);
+                w.write_u2(t.index);
+                Ok(())
+            })?,
+            TargetInfo::TypeArgument { offset, type_argument_index } => {
+                self.write_u2(*offset);
+                self.write_u1(*type_argument_index);
+            }
+        }
+        self.write_short_count(a.target_path.len(), "type path entries")?;
+        for entry in &a.target_path {
+            self.write_u1(entry.type_path_kind);
+            self.write_u1(entry.type_argument_index);
+        }
+        self.write_annotation(&a.annotation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attributes::attribute_reader::AttributeReader;
+
+    #[test]
+    fn round_trip() {
+        let mut cp = ConstantPool::new();
+        let bytes = [
+            0x00, 0x04,
+            0x05,
+            0x41, 0x01,
+            0xfd, 0x00, 0x10, 0x04, 0x07, 0x00, 0x03,
+            0xff, 0x00, 0x02, 0x00, 0x01, 0x06, 0x00, 0x00,
+        ];
+        let attribute = AttributeReader::new("StackMapTable", &bytes, &cp).read_attribute().unwrap();
+        let ai = attribute.to_info(&mut cp).unwrap();
+        assert_eq!(cp.utf8(ai.name_index).unwrap(), "StackMapTable");
+        assert_eq!(ai.info, bytes);
+        // the name is only added once
+        assert_eq!(attribute.to_info(&mut cp).unwrap().name_index, ai.name_index);
+    }
+}
@@ -1,13 +1,23 @@
-use crate::attributes::attribute_info::AttributeInfo;
-use crate::bytecode::Instruction;
+use std::cell::Cell;
+use crate::attributes::attribute::{Attribute, StackMapFrame, TargetInfo, VerificationType};
+use crate::bytecode::{Instruction, SwitchTable};
 
 #[derive(Debug, Clone)]
 pub struct Code {
     pub max_stack: u16,
     pub max_locals: u16,
-    pub code: Vec<Instruction>,
+    /// in cells, since the interpreter quickens instructions while they are running
+    pub code: Box<[Cell<Instruction>]>,
+    /// the tables of the `tableswitch` and `lookupswitch` instructions in `code`
+    pub switches: Vec<SwitchTable>,
+    /// the byte offset each instruction was loaded from, followed by the length of the bytecode.
+    /// code positions everywhere else are instruction indices, so this is only needed to
+    /// talk about the class file, and isn't kept up to date when `code` is changed
+    pub byte_offsets: Vec<u32>,
+    /// `start_pc`, `end_pc` and `handler_pc` are instruction indices
     pub exception_table: Vec<ExceptionTableItem>,
-    pub attributes: Vec<AttributeInfo>, // name:data
+    /// code positions in these are instruction indices
+    pub attributes: Vec<Attribute>,
 }
 
 #[derive(Debug, Clone)]
@@ -17,3 +27,138 @@ pub struct ExceptionTableItem {
     pub catch_type: u16,
     pub handler_pc: u16,
 }
+
+impl Code {
+    /// the index of the instruction that was loaded from `byte_offset`.
+    /// the length of the bytecode gives the length of `code`
+    pub fn instruction_index(&self, byte_offset: u32) -> Option<u16> {
+        self.byte_offsets.binary_search(&byte_offset).ok().map(|i| i as u16)
+    }
+
+    /// the byte offset that the instruction at `index` was loaded from
+    pub fn byte_offset(&self, index: u16) -> Option<u32> {
+        self.byte_offsets.get(index as usize).copied()
+    }
+
+    /// `instructions`, ready to be put in `code`
+    pub fn cells(instructions: Vec<Instruction>) -> Box<[Cell<Instruction>]> {
+        instructions.into_iter().map(Cell::new).collect()
+    }
+
+    /// the instruction at `pc`
+    pub fn instruction(&self, pc: u16) -> Instruction {
+        self.code[pc as usize].get()
+    }
+
+    /// a copy of the instructions
+    pub fn instructions(&self) -> Vec<Instruction> {
+        self.code.iter().map(Cell::get).collect()
+    }
+
+    /// rewrite the instruction at `pc` into its quick form, which the interpreter does
+    /// in place, while the method may be running
+    pub(crate) fn quicken(&self, pc: u16, quick: Instruction) {
+        debug_assert_eq!(self.instruction(pc), quick.unquickened());
+        self.code[pc as usize].set(quick)
+    }
+
+    /// the source line of the instruction at `pc`, if there is a `LineNumberTable`
+    pub fn line_number(&self, pc: u16) -> Option<u16> {
+        self.attributes.iter()
+            .filter_map(|a| match a {
+                Attribute::LineNumberTable(lines) => Some(lines),
+                _ => None,
+            })
+            .flatten()
+            .filter(|line| line.start_pc <= pc)
+            .max_by_key(|line| line.start_pc)
+            .map(|line| line.line_number)
+    }
+
+    /// rewrite every code position in the exception table and in the attributes with `map`.
+    /// this converts between byte offsets and instruction indices.
+    /// `map` also gets the position just past the end of the code, since ranges end there.
+    /// fails with the first position that `map` doesn't know
+    pub fn map_positions(&mut self, map: &dyn Fn(u16) -> Option<u16>) -> Result<(), u16> {
+        let at = |pc: u16| map(pc).ok_or(pc);
+        // a range is mapped by its ends, so it still covers the same instructions
+        let range = |start: u16, length: u16| -> Result<(u16, u16), u16> {
+            let end = start.checked_add(length).ok_or(start)?;
+            let (mapped_start, mapped_end) = (at(start)?, at(end)?);
+            Ok((mapped_start, mapped_end.checked_sub(mapped_start).ok_or(end)?))
+        };
+
+        for item in &mut self.exception_table {
+            item.start_pc = at(item.start_pc)?;
+            item.end_pc = at(item.end_pc)?;
+            item.handler_pc = at(item.handler_pc)?;
+        }
+
+        for attribute in &mut self.attributes {
+            match attribute {
+                Attribute::LineNumberTable(lines) => {
+                    for line in lines {
+                        line.start_pc = at(line.start_pc)?;
+                    }
+                }
+                Attribute::LocalVariableTable(vars) | Attribute::LocalVariableTypeTable(vars) => {
+                    for var in vars {
+                        (var.start_pc, var.length) = range(var.start_pc, var.length)?;
+                    }
+                }
+                Attribute::StackMapTable(frames) => {
+                    // each frame after the first is `offset_delta + 1` past the one before it
+                    let mut old = None;
+                    let mut new: Option<u16> = None;
+                    for frame in frames {
+                        let position = match old {
+                            None => frame.offset_delta(),
+                            Some(p) => frame.offset_delta().checked_add(p + 1).ok_or(p)?,
+                        };
+                        let mapped = at(position)?;
+                        let delta = match new {
+                            None => mapped,
+                            Some(p) => mapped.checked_sub(p + 1).ok_or(position)?,
+                        };
+                        old = Some(position);
+                        new = Some(mapped);
+
+                        let (offset_delta, types) = match frame {
+                            StackMapFrame::Same { offset_delta } |
+                            StackMapFrame::Chop { offset_delta, .. } => (offset_delta, vec![]),
+                            StackMapFrame::SameLocals1StackItem { offset_delta, stack } =>
+                                (offset_delta, vec![stack]),
+                            StackMapFrame::Append { offset_delta, locals } =>
+                                (offset_delta, locals.iter_mut().collect()),
+                            StackMapFrame::Full { offset_delta, locals, stack } =>
+                                (offset_delta, locals.iter_mut().chain(stack.iter_mut()).collect()),
+                        };
+                        *offset_delta = delta;
+                        for t in types {
+                            if let VerificationType::Uninitialized(pc) = t {
+                                *pc = at(*pc)?;
+                            }
+                        }
+                    }
+                }
+                Attribute::RuntimeVisibleTypeAnnotations(annotations) |
+                Attribute::RuntimeInvisibleTypeAnnotations(annotations) => {
+                    for annotation in annotations {
+                        match &mut annotation.target_info {
+                            TargetInfo::Localvar(targets) => {
+                                for t in targets {
+                                    (t.start_pc, t.length) = range(t.start_pc, t.length)?;
+                                }
+                            }
+                            TargetInfo::Offset(pc) |
+                            TargetInfo::TypeArgument { offset: pc, .. } => *pc = at(*pc)?,
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,25 +1,36 @@
 use std::collections::HashMap;
+use crate::attributes::attribute::Attribute;
 use crate::attributes::attribute_info::AttributeInfo;
 use crate::attributes::code::{Code, ExceptionTableItem};
 use crate::attributes::code_reader::CodeParseError::{EarlyEnd, InvalidFormat};
 use crate::bytecode::{BytecodeParseError, Instruction};
+use crate::class_reader::ClassFormatError;
+use crate::class_version::ClassVersion;
+use crate::constant_pool::ConstantPool;
 use std::slice::Iter;
 
 pub struct CodeReader<'a> {
     bytes: Iter<'a, u8>,
+    cp: &'a ConstantPool,
+    version: ClassVersion,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CodeParseError {
     EarlyEnd(String),
     InvalidFormat,
     BytecodeParseError(BytecodeParseError),
+    BadAttribute(Box<ClassFormatError>),
+    /// a pc in the exception table or an attribute that isn't the start of an instruction
+    BadPosition(u16),
 }
 
 impl<'a> CodeReader<'a> {
-    pub fn new(bytes: &'a Vec<u8>) -> CodeReader<'a> {
+    pub fn new(bytes: &'a [u8], cp: &'a ConstantPool, version: ClassVersion) -> CodeReader<'a> {
         CodeReader {
             bytes: bytes.iter(),
+            cp,
+            version,
         }
     }
 
@@ -59,8 +70,8 @@ impl<'a> CodeReader<'a> {
         Some(ExceptionTableItem {
             start_pc: self.read_u2()?,
             end_pc: self.read_u2()?,
-            catch_type: self.read_u2()?,
             handler_pc: self.read_u2()?,
+            catch_type: self.read_u2()?,
         })
     }
 
@@ -68,7 +79,9 @@ impl<'a> CodeReader<'a> {
         let mut code = Code {
             max_stack: self.read_u2().ok_or(EarlyEnd("max stack".to_string()))?,
             max_locals: self.read_u2().ok_or(EarlyEnd("max locals".to_string()))?,
-            code: vec![],
+            code: Box::new([]),
+            switches: vec![],
+            byte_offsets: vec![],
             exception_table: vec![],
             attributes: vec![],
         };
@@ -80,12 +93,14 @@ impl<'a> CodeReader<'a> {
             bytecode.push(self.read_u1().ok_or(EarlyEnd("bytecode".to_string()))?)
         }
 
-        code.code = Instruction::read_from(bytecode.as_slice(), code_length)
+        let instructions;
+        (instructions, code.switches, code.byte_offsets) = Instruction::read_from(bytecode.as_slice(), code_length, self.version)
             .map_err(CodeParseError::BytecodeParseError)?;
 
-        dbg!(code.code.as_slice());
+        dbg!(instructions.as_slice());
+        code.code = Code::cells(instructions);
 
-        let exception_table_length = self.read_u2().unwrap();
+        let exception_table_length = self.read_u2().ok_or(EarlyEnd("exception table length".to_string()))?;
 
         code.exception_table
             .reserve(exception_table_length as usize);
@@ -97,13 +112,22 @@ impl<'a> CodeReader<'a> {
             );
         }
 
-        let attributes_count = self.read_u2().unwrap();
+        let attributes_count = self.read_u2().ok_or(EarlyEnd("code attributes count".to_string()))?;
         code.attributes.reserve(attributes_count as usize);
 
         for _ in 0..attributes_count {
-            code.attributes.push(self.read_attribute()?);
+            let ai = self.read_attribute()?;
+            code.attributes.push(
+                Attribute::from_info(self.cp, &ai)
+                    .map_err(|e| CodeParseError::BadAttribute(Box::new(e)))?
+            );
         }
 
+        // the interpreter's pc is an instruction index, so use those everywhere
+        let byte_offsets = code.byte_offsets.clone();
+        code.map_positions(&|pc| byte_offsets.binary_search(&(pc as u32)).ok().map(|i| i as u16))
+            .map_err(CodeParseError::BadPosition)?;
+
         Ok(code)
     }
 }
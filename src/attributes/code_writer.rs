@@ -0,0 +1,129 @@
+use crate::attributes::attribute_info::AttributeInfo;
+use crate::attributes::code::Code;
+use crate::bytecode::{BytecodeWriteError, Instruction};
+use crate::class_writer::ClassWriteError;
+use crate::constant_pool::ConstantPool;
+
+/// Encodes a `Code` back into the `info` bytes of a `Code` attribute.
+///
+/// Branch targets, the exception table and the code positions in the attributes
+/// are instruction indices, and get turned into byte offsets of the new code.
+pub struct CodeWriter<'a> {
+    bytes: Vec<u8>,
+    cp: &'a mut ConstantPool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodeWriteError {
+    BytecodeWriteError(BytecodeWriteError),
+    /// an instruction index in the exception table or an attribute that isn't in the code
+    BadPosition(u16),
+    BadAttribute(ClassWriteError),
+}
+
+impl Code {
+    /// encode this as a `Code` attribute, adding names to `cp` if they aren't there yet
+    pub fn to_info(&self, cp: &mut ConstantPool) -> Result<AttributeInfo, CodeWriteError> {
+        let name_index = cp.utf8_index("Code");
+        let info = CodeWriter::new(cp).write_code(self)?;
+        let attribute_length = u32::try_from(info.len())
+            .map_err(|_| CodeWriteError::BadAttribute(ClassWriteError::TooLong("attribute")))?;
+        Ok(AttributeInfo { name_index, attribute_length, info })
+    }
+}
+
+impl<'a> CodeWriter<'a> {
+    pub fn new(cp: &'a mut ConstantPool) -> CodeWriter<'a> {
+        CodeWriter { bytes: vec![], cp }
+    }
+
+    fn write_u2(&mut self, x: u16) {
+        self.bytes.extend_from_slice(&x.to_be_bytes())
+    }
+
+    fn write_u4(&mut self, x: u32) {
+        self.bytes.extend_from_slice(&x.to_be_bytes())
+    }
+
+    fn write_count(&mut self, n: usize, what: &'static str) -> Result<(), CodeWriteError> {
+        let n = u16::try_from(n).map_err(|_| CodeWriteError::BadAttribute(ClassWriteError::TooMany(what)))?;
+        self.write_u2(n);
+        Ok(())
+    }
+
+    pub fn write_code(mut self, code: &Code) -> Result<Vec<u8>, CodeWriteError> {
+        let (bytecode, offsets) = Instruction::write_to(&code.instructions(), &code.switches)
+            .map_err(CodeWriteError::BytecodeWriteError)?;
+
+        // the encoder checked that the code, and so every offset, fits in a u16
+        let mut code = code.clone();
+        code.map_positions(&|index| offsets.get(index as usize).map(|x| *x as u16))
+            .map_err(CodeWriteError::BadPosition)?;
+
+        self.write_u2(code.max_stack);
+        self.write_u2(code.max_locals);
+        self.write_u4(bytecode.len() as u32);
+        self.bytes.extend_from_slice(&bytecode);
+
+        self.write_count(code.exception_table.len(), "exception table entries")?;
+        for item in &code.exception_table {
+            self.write_u2(item.start_pc);
+            self.write_u2(item.end_pc);
+            self.write_u2(item.handler_pc);
+            self.write_u2(item.catch_type);
+        }
+
+        self.write_count(code.attributes.len(), "attributes")?;
+        for a in &code.attributes {
+            let ai = a.to_info(self.cp).map_err(CodeWriteError::BadAttribute)?;
+            self.write_u2(ai.name_index);
+            self.write_u4(ai.attribute_length);
+            self.bytes.extend_from_slice(&ai.info);
+        }
+
+        Ok(self.bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attributes::attribute::{Attribute, LineNumber};
+    use crate::attributes::code::ExceptionTableItem;
+    use crate::attributes::code_reader::CodeReader;
+    use crate::class_version::ClassVersion;
+    use Instruction::*;
+
+    #[test]
+    fn positions() {
+        let mut cp = ConstantPool::new();
+        let code = Code {
+            max_stack: 1,
+            max_locals: 1,
+            code: Code::cells(vec![Iload(0), Ifeq(3), Ipush(1000), Return]),
+            switches: vec![],
+            byte_offsets: vec![],
+            exception_table: vec![ExceptionTableItem { start_pc: 0, end_pc: 3, catch_type: 0, handler_pc: 3 }],
+            attributes: vec![Attribute::LineNumberTable(vec![
+                LineNumber { start_pc: 0, line_number: 1 },
+                LineNumber { start_pc: 2, line_number: 2 },
+            ])],
+        };
+        let ai = code.to_info(&mut cp).unwrap();
+        assert_eq!(cp.utf8(ai.name_index).unwrap(), "Code");
+        assert_eq!(ai.info[8..16], [0x1a, 0x99, 0x00, 0x06, 0x11, 0x03, 0xe8, 0xb1]);
+
+        // reading it back turns the byte offsets into the same instruction indices
+        let read = CodeReader::new(&ai.info, &cp, ClassVersion::new(52, 0)).read_code().unwrap();
+        assert_eq!(read.byte_offsets, vec![0, 1, 4, 7, 8]);
+        let item = &read.exception_table[0];
+        assert_eq!((item.start_pc, item.end_pc, item.handler_pc), (0, 3, 3));
+        assert_eq!(read.attributes, code.attributes);
+        assert_eq!(read.line_number(1), Some(1));
+        assert_eq!(read.line_number(3), Some(2));
+
+        let mut bad = code.clone();
+        bad.exception_table[0].handler_pc = 5;
+        assert_eq!(bad.to_info(&mut cp).unwrap_err(), CodeWriteError::BadPosition(5));
+    }
+}
@@ -1,3 +1,7 @@
+pub mod attribute;
 pub mod attribute_info;
+pub mod attribute_reader;
+pub mod attribute_writer;
 pub mod code;
 pub mod code_reader;
+pub mod code_writer;
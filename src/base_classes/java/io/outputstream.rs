@@ -10,6 +10,7 @@ pub fn outputstream(runtime: &mut Runtime) {
         static_fields: Default::default(),
         instance_fields: Default::default(),
         methods: Default::default(),
+        vtable: vec![],
     };
 
     runtime.add_native_class(os);
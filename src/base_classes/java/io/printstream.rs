@@ -13,6 +13,7 @@ pub fn printstream(runtime: &mut Runtime) {
         static_fields: Default::default(),
         instance_fields: Default::default(),
         methods: Default::default(),
+        vtable: vec![],
     };
 
     runtime.add_native_class(ps);
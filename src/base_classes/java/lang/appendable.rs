@@ -1,16 +1,17 @@
 use std::ptr::null_mut;
 use crate::{Runtime};
-use crate::class::NativeClass;
+use crate::class::{NativeClass, access_flags::*};
 
 pub fn appendable(runtime: &mut Runtime) {
     let a = NativeClass {
         name: "java/lang/Appendable".to_string(),
-        access_flags: 0,
+        access_flags: PUBLIC | INTERFACE | ABSTRACT,
         super_class: null_mut(),
         interfaces: vec![],
         static_fields: Default::default(),
         instance_fields: Default::default(),
         methods: Default::default(),
+        vtable: vec![],
     };
 
     runtime.add_native_class(a);
@@ -19,13 +19,13 @@ pub fn object(runtime: &mut Runtime) {
 
                 let object_hash_code = Native(NativeMethod {
                     name: "hashCode".to_string(),
-                    access_flags: 0,
+                    access_flags: PUBLIC,
                     descriptor: "()I".to_string(),
                     parsed_descriptor: DescriptorInfo {
                         ret: Type::Int,
                         args: vec![],
                     },
-                    func: Box::new(|_meth, _runtime, _jclass| {
+                    func: Box::new(|_meth, _runtime, _jclass, _args| {
                         println!("hashCode not implemented, returning default value of 0");
                         Some(Value::nint(0))
 
@@ -34,13 +34,13 @@ pub fn object(runtime: &mut Runtime) {
                 // equals
                 let object_equals = Native(NativeMethod {
                     name: "equals".to_string(),
-                    access_flags: 0,
+                    access_flags: PUBLIC,
                     descriptor: "(Ljava/lang/Object;)Z".to_string(),
                     parsed_descriptor: DescriptorInfo {
                         ret: Type::Bool,
                         args: vec![Type::Object(Box::from("java/lang/Object".to_string()))],
                     },
-                    func: Box::new(|_method, _runtime, _class| {
+                    func: Box::new(|_method, _runtime, _class, _args| {
 
                         Some(Value::FALSE)
                     }),
@@ -51,6 +51,7 @@ pub fn object(runtime: &mut Runtime) {
                 m.insert(("equals".to_string(), "(Ljava/lang/Object;)Z".to_string()), object_equals);
                 m
             },
+            vtable: vec![],
         }
     )
 }
\ No newline at end of file
@@ -25,6 +25,7 @@ pub fn string(runtime: &mut Runtime) {
             m
         },
         methods: Default::default(),
+        vtable: vec![],
     };
 
     runtime.add_native_class(s);
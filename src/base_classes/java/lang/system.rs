@@ -63,6 +63,7 @@ pub fn system(runtime: &mut Runtime) {
         },
         instance_fields: Default::default(),
         methods: Default::default(),
+        vtable: vec![],
     };
 
     runtime.add_native_class(system);
@@ -2,8 +2,8 @@ use std::collections::HashMap;
 use std::mem;
 use std::ops::{ControlFlow, FromResidual, Try};
 use lazy_static::lazy_static;
-use crate::bytecode::BytecodeParseError::{EarlyEnd, InvalidCode, InvalidOpcode};
-use crate::bytecode::Instruction::{AconstNull, Aload, Goto, IfIcmpeq, IfIcmpge, IfIcmpgt, IfIcmple, IfIcmplt, IfIcmpne, Iinc, Iload, Imul, Invokespecial, Ipush, Ireturn, Istore, Nop, Return};
+use crate::class_version::ClassVersion;
+use crate::bytecode::BytecodeParseError::{EarlyEnd, InvalidCode, InvalidOpcode, OpcodeNotAllowed};
 
 #[repr(u8)]
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
@@ -13,7 +13,15 @@ pub enum Instruction {
     Nop,
     AconstNull,
     Ipush(i16),
+    /// lconst_0 and lconst_1
+    Lconst(u8),
+    /// fconst_0 to fconst_2
+    Fconst(u8),
+    /// dconst_0 and dconst_1
+    Dconst(u8),
+    /// ldc and ldc_w
     Ldc(u32),
+    Ldc2W(u16),
     Iload(u16),
     Lload(u16),
     Fload(u16),
@@ -88,7 +96,8 @@ pub enum Instruction {
     Lor,
     Ixor,
     Lxor,
-    Iinc(u8, i8),
+    /// local variable index and increment, both widened by `wide`
+    Iinc(u16, i16),
     I2l,
     I2f,
     I2d,
@@ -124,8 +133,12 @@ pub enum Instruction {
     IfAcmpeq(u16),
     IfAcmpne(u16),
     Goto(u16),
-    Tableswitch,  // TODO: implement
-    Lookupswitch, // TODO: implement
+    Jsr(u16),
+    Ret(u16),
+    /// index into the method's switch tables
+    Tableswitch(u16),
+    /// index into the method's switch tables
+    Lookupswitch(u16),
     Ireturn,
     Lreturn,
     Freturn,
@@ -139,7 +152,8 @@ pub enum Instruction {
     Invokevirtual(u16),
     Invokespecial(u16),
     Invokestatic(u16),
-    Invokeinterface(u16),
+    /// constant pool index, and the number of argument slots including the receiver
+    Invokeinterface(u16, u8),
     Invokedynamic(u16),
     New(u16),
     Newarray(u8),
@@ -153,22 +167,146 @@ pub enum Instruction {
     Multianewarray(u16, u8),
     Ifnull(u16),
     Ifnonnull(u16),
+    GotoW(u16),
+    JsrW(u16),
     Breakpoint,
     Impdep1,
     Impdep2,
+    // quick forms, which the interpreter rewrites the instructions above into once it has
+    // resolved their constant pool entry. they never appear in class files.
+    // the operand is still the constant pool index, which is also where the resolved entry
+    // is kept in the class's `ConstantPoolCache`
+    GetstaticQuick(u16),
+    PutstaticQuick(u16),
+    GetfieldQuick(u16),
+    PutfieldQuick(u16),
+    InvokevirtualQuick(u16),
+    InvokespecialQuick(u16),
+    InvokestaticQuick(u16),
+    InvokeinterfaceQuick(u16, u8),
+    // superinstructions, which the peephole optimizer fuses common sequences into.
+    // they never appear in class files either
+    /// `iload a; iload b; iadd; istore c`
+    IaddLocals(u16, u16, u16),
+    /// `iload a; iload b; isub; istore c`
+    IsubLocals(u16, u16, u16),
+    /// `iload a; iload b; imul; istore c`
+    ImulLocals(u16, u16, u16),
+    /// `iload a; iload b; if_icmp<cond> target`
+    IfIcmpLocals(Condition, u16, u16, u16),
+    /// `iload a; <push k>; if_icmp<cond> target`, and `iload a; if<cond> target` with `k` of 0
+    IfIcmpConst(Condition, u16, i16, u16),
+    /// `aload n; getfield index`
+    AloadGetfield(u16, u16),
+    /// the quick form of `AloadGetfield`
+    AloadGetfieldQuick(u16, u16),
 }
 
-#[derive(Clone, Debug)]
+/// The comparison of a fused compare-and-branch.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Condition {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    Gt,
+    Le,
+}
+
+impl Condition {
+    pub fn test(self, a: i32, b: i32) -> bool {
+        match self {
+            Condition::Eq => a == b,
+            Condition::Ne => a != b,
+            Condition::Lt => a < b,
+            Condition::Ge => a >= b,
+            Condition::Gt => a > b,
+            Condition::Le => a <= b,
+        }
+    }
+}
+
+/// The jump table of a `tableswitch` or `lookupswitch`.
+///
+/// These are kept next to the code rather than in the instruction, so that
+/// `Instruction` stays small. Targets are instruction indices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SwitchTable {
+    /// `targets[i]` is the target for `low + i`
+    Table { default: u16, low: i32, targets: Vec<u16> },
+    /// sorted by key
+    Lookup { default: u16, pairs: Vec<(i32, u16)> },
+}
+
+impl SwitchTable {
+    /// the instruction to jump to for `key`
+    pub fn target(&self, key: i32) -> u16 {
+        match self {
+            SwitchTable::Table { default, low, targets } => {
+                let i = key as i64 - *low as i64;
+                if (0..targets.len() as i64).contains(&i) {
+                    targets[i as usize]
+                } else {
+                    *default
+                }
+            }
+            SwitchTable::Lookup { default, pairs } => {
+                match pairs.binary_search_by_key(&key, |(k, _)| *k) {
+                    Ok(i) => pairs[i].1,
+                    Err(_) => *default,
+                }
+            }
+        }
+    }
+
+    pub fn default(&self) -> u16 {
+        match self {
+            SwitchTable::Table { default, .. } | SwitchTable::Lookup { default, .. } => *default,
+        }
+    }
+
+    /// every target other than the default, in table order
+    pub fn targets(&self) -> Vec<u16> {
+        match self {
+            SwitchTable::Table { targets, .. } => targets.clone(),
+            SwitchTable::Lookup { pairs, .. } => pairs.iter().map(|(_, t)| *t).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum BytecodeParseError {
     EarlyEnd,
     InvalidOpcode(u8),
+    /// an opcode that is not allowed in a class file of this version
+    OpcodeNotAllowed(u8, ClassVersion),
     InvalidCode,
 }
 
+/// the instructions of some bytecode, their switch tables, and the byte offset of every
+/// instruction followed by the length of the bytecode
+pub type DecodedCode = (Vec<Instruction>, Vec<SwitchTable>, Vec<u32>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BytecodeWriteError {
+    /// a branch target past the last instruction
+    BadTarget(u16),
+    /// a switch instruction without a matching table
+    BadSwitch(u16),
+    /// a constant pool index that is too big for its operand
+    BadIndex(u32),
+    /// more than the 65535 bytes of code a method may have
+    TooLong,
+    /// a superinstruction from optimized code, which has no encoding
+    Superinstruction(&'static str),
+}
+
 impl Instruction {
-    /// gets the size of the first instruction stored in `buf`
+    /// gets the size of the first instruction stored in `buf`, which starts at `offset` in the code.
+    /// the offset is needed for the padding in switches.
     /// returns 0 in the case of an invalid opcode or bad formatting
-    pub fn raw_size(buf: &[u8]) -> u32 {
+    pub fn raw_size(buf: &[u8], offset: u32) -> u32 {
         lazy_static! {
             pub static ref TABLE: [u32; 256] = {
                 let mut t = [0u32; 256];
@@ -256,7 +394,9 @@ impl Instruction {
                     0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7,
                     0xb8, 0xb9, 0xba,
                     // new, newarray, checkcast, instanceof
-                    0xbb, 0xbd, 0xc0, 0xc1
+                    0xbb, 0xbd, 0xc0, 0xc1,
+                    // ifnull, ifnonnull
+                    0xc6, 0xc7
                 ];
 
                 for b in ZERO_OPERAND {
@@ -293,20 +433,36 @@ impl Instruction {
         let table_result = TABLE[opcode as usize];
         if table_result == 0 {
             match opcode {
-                0xaa | 0xab => unimplemented!("switches are hard :("),
-                0xc4 => {
-                    // wide
-                    let second_opcode = buf.get(1);
-                    if let Some(val) = second_opcode {
-                        if *val == 0x84 {
-                            // iinc
-                            5
-                        } else {
-                            // other opcode
-                            3
+                0xaa | 0xab => {
+                    // operands are aligned to four bytes from the start of the code
+                    let operands = 1 + (3 - offset % 4) as usize;
+                    let read_i4 = |at: usize| -> Option<i64> {
+                        let b = buf.get(operands + at..operands + at + 4)?;
+                        Some(i32::from_be_bytes(b.try_into().unwrap()) as i64)
+                    };
+                    // sizes past u32::MAX are bad formatting anyway
+                    let size = if opcode == 0xaa {
+                        match (read_i4(4), read_i4(8)) {
+                            (Some(low), Some(high)) if low <= high =>
+                                operands as i64 + 12 + 4 * (high - low + 1),
+                            _ => 0,
                         }
                     } else {
-                        0
+                        match read_i4(4) {
+                            Some(npairs) if npairs >= 0 => operands as i64 + 8 + 8 * npairs,
+                            _ => 0,
+                        }
+                    };
+                    u32::try_from(size).unwrap_or(0)
+                }
+                0xc4 => {
+                    // wide
+                    match buf.get(1) {
+                        // iinc
+                        Some(0x84) => 6,
+                        // Xload, Xstore, ret
+                        Some(0x15..=0x19 | 0x36..=0x3a | 0xa9) => 4,
+                        _ => 0,
                     }
                 }
                 _ => 0
@@ -316,36 +472,238 @@ impl Instruction {
         }
     }
 
-    pub fn read_from(buf: &[u8], length: u32) -> Result<Vec<Instruction>, BytecodeParseError> {
+    /// the name of the instruction, as it is written in assembly
+    pub fn mnemonic(&self) -> &'static str {
+        use self::Instruction::*;
+
+        match self {
+            Nop => "nop",
+            AconstNull => "aconst_null",
+            Ipush(-1) => "iconst_m1",
+            Ipush(0) => "iconst_0",
+            Ipush(1) => "iconst_1",
+            Ipush(2) => "iconst_2",
+            Ipush(3) => "iconst_3",
+            Ipush(4) => "iconst_4",
+            Ipush(5) => "iconst_5",
+            Ipush(-128..=127) => "bipush",
+            Ipush(_) => "sipush",
+            Lconst(0) => "lconst_0",
+            Lconst(_) => "lconst_1",
+            Fconst(0) => "fconst_0",
+            Fconst(1) => "fconst_1",
+            Fconst(_) => "fconst_2",
+            Dconst(0) => "dconst_0",
+            Dconst(_) => "dconst_1",
+            Ldc(0..=255) => "ldc",
+            Ldc(_) => "ldc_w",
+            Ldc2W(..) => "ldc2_w",
+            Iload(..) => "iload",
+            Lload(..) => "lload",
+            Fload(..) => "fload",
+            Dload(..) => "dload",
+            Aload(..) => "aload",
+            Iaload => "iaload",
+            Laload => "laload",
+            Faload => "faload",
+            Daload => "daload",
+            Aaload => "aaload",
+            Baload => "baload",
+            Caload => "caload",
+            Saload => "saload",
+            Istore(..) => "istore",
+            Lstore(..) => "lstore",
+            Fstore(..) => "fstore",
+            Dstore(..) => "dstore",
+            Astore(..) => "astore",
+            Iastore => "iastore",
+            Lastore => "lastore",
+            Fastore => "fastore",
+            Dastore => "dastore",
+            Aastore => "aastore",
+            Bastore => "bastore",
+            Castore => "castore",
+            Sastore => "sastore",
+            Pop => "pop",
+            Pop2 => "pop2",
+            Dup => "dup",
+            DupX1 => "dup_x1",
+            DupX2 => "dup_x2",
+            Dup2 => "dup2",
+            Dup2X1 => "dup2_x1",
+            Dup2X2 => "dup2_x2",
+            Swap => "swap",
+            Iadd => "iadd",
+            Ladd => "ladd",
+            Fadd => "fadd",
+            Dadd => "dadd",
+            Isub => "isub",
+            Lsub => "lsub",
+            Fsub => "fsub",
+            Dsub => "dsub",
+            Imul => "imul",
+            Lmul => "lmul",
+            Fmul => "fmul",
+            Dmul => "dmul",
+            Idiv => "idiv",
+            Ldiv => "ldiv",
+            Fdiv => "fdiv",
+            Ddiv => "ddiv",
+            Irem => "irem",
+            Lrem => "lrem",
+            Frem => "frem",
+            Drem => "drem",
+            Ineg => "ineg",
+            Lneg => "lneg",
+            Fneg => "fneg",
+            Dneg => "dneg",
+            Ishl => "ishl",
+            Lshl => "lshl",
+            Ishr => "ishr",
+            Lshr => "lshr",
+            Iushr => "iushr",
+            Lushr => "lushr",
+            Iand => "iand",
+            Land => "land",
+            Ior => "ior",
+            Lor => "lor",
+            Ixor => "ixor",
+            Lxor => "lxor",
+            Iinc(..) => "iinc",
+            I2l => "i2l",
+            I2f => "i2f",
+            I2d => "i2d",
+            L2i => "l2i",
+            L2f => "l2f",
+            L2d => "l2d",
+            F2i => "f2i",
+            F2l => "f2l",
+            F2d => "f2d",
+            D2i => "d2i",
+            D2l => "d2l",
+            D2f => "d2f",
+            I2b => "i2b",
+            I2c => "i2c",
+            I2s => "i2s",
+            Lcmp => "lcmp",
+            Fcmpl => "fcmpl",
+            Fcmpg => "fcmpg",
+            Dcmpl => "dcmpl",
+            Dcmpg => "dcmpg",
+            Ifeq(..) => "ifeq",
+            Ifne(..) => "ifne",
+            Iflt(..) => "iflt",
+            Ifge(..) => "ifge",
+            Ifgt(..) => "ifgt",
+            Ifle(..) => "ifle",
+            IfIcmpeq(..) => "if_icmpeq",
+            IfIcmpne(..) => "if_icmpne",
+            IfIcmplt(..) => "if_icmplt",
+            IfIcmpge(..) => "if_icmpge",
+            IfIcmpgt(..) => "if_icmpgt",
+            IfIcmple(..) => "if_icmple",
+            IfAcmpeq(..) => "if_acmpeq",
+            IfAcmpne(..) => "if_acmpne",
+            Goto(..) => "goto",
+            Jsr(..) => "jsr",
+            Ret(..) => "ret",
+            Tableswitch(..) => "tableswitch",
+            Lookupswitch(..) => "lookupswitch",
+            Ireturn => "ireturn",
+            Lreturn => "lreturn",
+            Freturn => "freturn",
+            Dreturn => "dreturn",
+            Areturn => "areturn",
+            Return => "return",
+            Getstatic(..) => "getstatic",
+            Putstatic(..) => "putstatic",
+            Getfield(..) => "getfield",
+            Putfield(..) => "putfield",
+            Invokevirtual(..) => "invokevirtual",
+            Invokespecial(..) => "invokespecial",
+            Invokestatic(..) => "invokestatic",
+            Invokeinterface(..) => "invokeinterface",
+            Invokedynamic(..) => "invokedynamic",
+            New(..) => "new",
+            Newarray(..) => "newarray",
+            Anewarray(..) => "anewarray",
+            Arraylength => "arraylength",
+            Athrow => "athrow",
+            Checkcast(..) => "checkcast",
+            Instanceof(..) => "instanceof",
+            Monitorenter => "monitorenter",
+            Monitorexit => "monitorexit",
+            Multianewarray(..) => "multianewarray",
+            Ifnull(..) => "ifnull",
+            Ifnonnull(..) => "ifnonnull",
+            GotoW(..) => "goto_w",
+            JsrW(..) => "jsr_w",
+            Breakpoint => "breakpoint",
+            Impdep1 => "impdep1",
+            Impdep2 => "impdep2",
+            GetstaticQuick(..) => "getstatic_quick",
+            PutstaticQuick(..) => "putstatic_quick",
+            GetfieldQuick(..) => "getfield_quick",
+            PutfieldQuick(..) => "putfield_quick",
+            InvokevirtualQuick(..) => "invokevirtual_quick",
+            InvokespecialQuick(..) => "invokespecial_quick",
+            InvokestaticQuick(..) => "invokestatic_quick",
+            InvokeinterfaceQuick(..) => "invokeinterface_quick",
+            IaddLocals(..) => "iadd_locals",
+            IsubLocals(..) => "isub_locals",
+            ImulLocals(..) => "imul_locals",
+            IfIcmpLocals(..) => "if_icmp_locals",
+            IfIcmpConst(..) => "if_icmp_const",
+            AloadGetfield(..) => "aload_getfield",
+            AloadGetfieldQuick(..) => "aload_getfield_quick",
+        }
+    }
+
+    /// the instruction that a quick form was rewritten from, or this instruction if it isn't one
+    pub fn unquickened(self) -> Instruction {
+        use self::Instruction::*;
+
+        match self {
+            GetstaticQuick(n) => Getstatic(n),
+            PutstaticQuick(n) => Putstatic(n),
+            GetfieldQuick(n) => Getfield(n),
+            PutfieldQuick(n) => Putfield(n),
+            InvokevirtualQuick(n) => Invokevirtual(n),
+            InvokespecialQuick(n) => Invokespecial(n),
+            InvokestaticQuick(n) => Invokestatic(n),
+            InvokeinterfaceQuick(n, count) => Invokeinterface(n, count),
+            AloadGetfieldQuick(n, index) => AloadGetfield(n, index),
+            x => x,
+        }
+    }
+
+    /// decode `length` bytes of bytecode, returning the instructions, the switch tables they refer to,
+    /// and the byte offset of every instruction followed by `length`
+    pub fn read_from(buf: &[u8], length: u32, version: ClassVersion) -> Result<DecodedCode, BytecodeParseError> {
+        use self::Instruction::*;
+
         dbg!(buf);
 
         let mut code = vec![];
+        let mut switches = vec![];
 
-        let byte_offset_table: HashMap<u32, u16> = {
-            let mut m = HashMap::new();
-            let mut byte_offset: u32 = 0;
-            let mut instruction_index = 0;
-
-            // first pass: get offsets of instructions
-            while byte_offset < length {
-                m.insert(byte_offset, instruction_index);
-                let size = Instruction::raw_size(&buf[byte_offset as usize..]);
-                if size == 0 {
-                    return Err(InvalidOpcode(buf[byte_offset as usize]))
-                }
-                byte_offset += size;
-                instruction_index += 1;
-            }
-            m
-        };
-
-        let instruction_index_table = {
-            let mut m = HashMap::new();
-            for (k, v) in byte_offset_table.iter() {
-                m.insert(*v, *k);
+        // first pass: get offsets of instructions
+        let mut instruction_offsets = vec![];
+        let mut byte_offset_table: HashMap<u32, u16> = HashMap::new();
+        let mut byte_offset: u32 = 0;
+        while byte_offset < length {
+            byte_offset_table.insert(byte_offset, instruction_offsets.len() as u16);
+            instruction_offsets.push(byte_offset);
+            let size = Instruction::raw_size(&buf[byte_offset as usize..], byte_offset);
+            if size == 0 {
+                return Err(InvalidOpcode(buf[byte_offset as usize]))
             }
-            m
-        };
+            // an instruction can't run past the end of the code
+            byte_offset = byte_offset.checked_add(size)
+                .filter(|&end| end <= length)
+                .ok_or(EarlyEnd)?;
+        }
+        instruction_offsets.push(length);
 
         // second pass: do the parsing
         // :)
@@ -372,7 +730,7 @@ impl Instruction {
 
         macro_rules! read_u2 {
             () => {{
-                let x = u16::from_be_bytes(walker[0..2].try_into().map_err(|_| EarlyEnd)?);
+                let x = u16::from_be_bytes(walker.get(0..2).ok_or(EarlyEnd)?.try_into().unwrap());
                 walker = &walker[2..];
                 x
             }}
@@ -388,14 +746,23 @@ impl Instruction {
             }};
         }
 
+        macro_rules! read_i4 {
+            () => {{
+                let x = i32::from_be_bytes(walker.get(0..4).ok_or(EarlyEnd)?.try_into().unwrap());
+                walker = &walker[4..];
+                x
+            }}
+        }
+
         let mut pc = 0;
 
         macro_rules! index_from_relative {
             ($relative:expr) => {{
-                let raw = $relative as i32;
-                let current_byte_offset = *instruction_index_table
-                    .get(&pc).ok_or(InvalidCode)? as i32;
-                let byte_index = (raw + current_byte_offset) as u32;
+                let raw = $relative as i64;
+                let current_byte_offset = *instruction_offsets
+                    .get(pc).ok_or(InvalidCode)? as i64;
+                // a four byte offset can reach outside of any code, or overflow
+                let byte_index = u32::try_from(current_byte_offset + raw).map_err(|_| InvalidCode)?;
                 *byte_offset_table
                     .get(&byte_index)
                     .ok_or(InvalidCode)?
@@ -406,55 +773,723 @@ impl Instruction {
 
             let opcode = read_u1!();
 
+            match opcode {
+                // jsr, ret, jsr_w
+                0xa8 | 0xa9 | 0xc9 if !version.allows_subroutines() =>
+                    return Err(OpcodeNotAllowed(opcode, version)),
+                0xba if !version.allows_invokedynamic() =>
+                    return Err(OpcodeNotAllowed(opcode, version)),
+                _ => {}
+            }
+
             code.push(match opcode {
                 0x00 => Nop,
                 0x01 => AconstNull,
-                0x02 => Ipush(-1),
-                0x03 => Ipush(0),
-                0x04 => Ipush(1),
-                0x05 => Ipush(2),
-                0x06 => Ipush(3),
-                0x07 => Ipush(4),
-                0x08 => Ipush(5),
+                0x02..=0x08 => Ipush(opcode as i16 - 0x03),
+                0x09..=0x0a => Lconst(opcode - 0x09),
+                0x0b..=0x0d => Fconst(opcode - 0x0b),
+                0x0e..=0x0f => Dconst(opcode - 0x0e),
                 0x10 => Ipush(read_i1!() as i16),
                 0x11 => Ipush(read_i2!()),
-                0x1a => Iload(0),
-                0x1b => Iload(1),
-                0x2a => Aload(0),
-                0x3b => Istore(0),
-                0x3c => Istore(1),
-                0x3d => Istore(2),
-                0x3e => Istore(3),
+                0x12 => Ldc(read_u1!() as u32),
+                0x13 => Ldc(read_u2!() as u32),
+                0x14 => Ldc2W(read_u2!()),
+                0x15 => Iload(read_u1!() as u16),
+                0x16 => Lload(read_u1!() as u16),
+                0x17 => Fload(read_u1!() as u16),
+                0x18 => Dload(read_u1!() as u16),
+                0x19 => Aload(read_u1!() as u16),
+                // Xload_N are folded into Xload(N)
+                0x1a..=0x1d => Iload((opcode - 0x1a) as u16),
+                0x1e..=0x21 => Lload((opcode - 0x1e) as u16),
+                0x22..=0x25 => Fload((opcode - 0x22) as u16),
+                0x26..=0x29 => Dload((opcode - 0x26) as u16),
+                0x2a..=0x2d => Aload((opcode - 0x2a) as u16),
+                0x2e => Iaload,
+                0x2f => Laload,
+                0x30 => Faload,
+                0x31 => Daload,
+                0x32 => Aaload,
+                0x33 => Baload,
+                0x34 => Caload,
+                0x35 => Saload,
+                0x36 => Istore(read_u1!() as u16),
+                0x37 => Lstore(read_u1!() as u16),
+                0x38 => Fstore(read_u1!() as u16),
+                0x39 => Dstore(read_u1!() as u16),
+                0x3a => Astore(read_u1!() as u16),
+                // and so are Xstore_N
+                0x3b..=0x3e => Istore((opcode - 0x3b) as u16),
+                0x3f..=0x42 => Lstore((opcode - 0x3f) as u16),
+                0x43..=0x46 => Fstore((opcode - 0x43) as u16),
+                0x47..=0x4a => Dstore((opcode - 0x47) as u16),
+                0x4b..=0x4e => Astore((opcode - 0x4b) as u16),
+                0x4f => Iastore,
+                0x50 => Lastore,
+                0x51 => Fastore,
+                0x52 => Dastore,
+                0x53 => Aastore,
+                0x54 => Bastore,
+                0x55 => Castore,
+                0x56 => Sastore,
+                0x57 => Pop,
+                0x58 => Pop2,
+                0x59 => Dup,
+                0x5a => DupX1,
+                0x5b => DupX2,
+                0x5c => Dup2,
+                0x5d => Dup2X1,
+                0x5e => Dup2X2,
+                0x5f => Swap,
+                0x60 => Iadd,
+                0x61 => Ladd,
+                0x62 => Fadd,
+                0x63 => Dadd,
+                0x64 => Isub,
+                0x65 => Lsub,
+                0x66 => Fsub,
+                0x67 => Dsub,
                 0x68 => Imul,
-                0x84 => Iinc(read_u1!(), read_i1!()),
+                0x69 => Lmul,
+                0x6a => Fmul,
+                0x6b => Dmul,
+                0x6c => Idiv,
+                0x6d => Ldiv,
+                0x6e => Fdiv,
+                0x6f => Ddiv,
+                0x70 => Irem,
+                0x71 => Lrem,
+                0x72 => Frem,
+                0x73 => Drem,
+                0x74 => Ineg,
+                0x75 => Lneg,
+                0x76 => Fneg,
+                0x77 => Dneg,
+                0x78 => Ishl,
+                0x79 => Lshl,
+                0x7a => Ishr,
+                0x7b => Lshr,
+                0x7c => Iushr,
+                0x7d => Lushr,
+                0x7e => Iand,
+                0x7f => Land,
+                0x80 => Ior,
+                0x81 => Lor,
+                0x82 => Ixor,
+                0x83 => Lxor,
+                0x84 => Iinc(read_u1!() as u16, read_i1!() as i16),
+                0x85 => I2l,
+                0x86 => I2f,
+                0x87 => I2d,
+                0x88 => L2i,
+                0x89 => L2f,
+                0x8a => L2d,
+                0x8b => F2i,
+                0x8c => F2l,
+                0x8d => F2d,
+                0x8e => D2i,
+                0x8f => D2l,
+                0x90 => D2f,
+                0x91 => I2b,
+                0x92 => I2c,
+                0x93 => I2s,
+                0x94 => Lcmp,
+                0x95 => Fcmpl,
+                0x96 => Fcmpg,
+                0x97 => Dcmpl,
+                0x98 => Dcmpg,
+                0x99 => Ifeq(index_from_relative!(read_i2!())),
+                0x9a => Ifne(index_from_relative!(read_i2!())),
+                0x9b => Iflt(index_from_relative!(read_i2!())),
+                0x9c => Ifge(index_from_relative!(read_i2!())),
+                0x9d => Ifgt(index_from_relative!(read_i2!())),
+                0x9e => Ifle(index_from_relative!(read_i2!())),
                 0x9f => IfIcmpeq(index_from_relative!(read_i2!())),
                 0xa0 => IfIcmpne(index_from_relative!(read_i2!())),
                 0xa1 => IfIcmplt(index_from_relative!(read_i2!())),
                 0xa2 => IfIcmpge(index_from_relative!(read_i2!())),
                 0xa3 => IfIcmpgt(index_from_relative!(read_i2!())),
                 0xa4 => IfIcmple(index_from_relative!(read_i2!())),
+                0xa5 => IfAcmpeq(index_from_relative!(read_i2!())),
+                0xa6 => IfAcmpne(index_from_relative!(read_i2!())),
                 0xa7 => Goto(index_from_relative!(read_i2!())),
+                0xa8 => Jsr(index_from_relative!(read_i2!())),
+                0xa9 => Ret(read_u1!() as u16),
+                0xaa | 0xab => {
+                    // skip the padding that aligns the operands to four bytes
+                    let offset = buf.len() - walker.len();
+                    walker = walker.get((4 - offset % 4) % 4..).ok_or(EarlyEnd)?;
+                    let default = index_from_relative!(read_i4!());
+                    let table = if opcode == 0xaa {
+                        let low = read_i4!();
+                        let high = read_i4!();
+                        let mut targets = vec![];
+                        for _ in low..=high {
+                            targets.push(index_from_relative!(read_i4!()));
+                        }
+                        SwitchTable::Table { default, low, targets }
+                    } else {
+                        let npairs = read_i4!();
+                        let mut pairs: Vec<(i32, u16)> = vec![];
+                        for _ in 0..npairs {
+                            let key = read_i4!();
+                            if pairs.last().is_some_and(|(last, _)| *last >= key) {
+                                return Err(InvalidCode);
+                            }
+                            pairs.push((key, index_from_relative!(read_i4!())));
+                        }
+                        SwitchTable::Lookup { default, pairs }
+                    };
+                    let index = switches.len() as u16;
+                    switches.push(table);
+                    if opcode == 0xaa {
+                        Tableswitch(index)
+                    } else {
+                        Lookupswitch(index)
+                    }
+                }
                 0xac => Ireturn,
+                0xad => Lreturn,
+                0xae => Freturn,
+                0xaf => Dreturn,
+                0xb0 => Areturn,
                 0xb1 => Return,
+                0xb2 => Getstatic(read_u2!()),
+                0xb3 => Putstatic(read_u2!()),
+                0xb4 => Getfield(read_u2!()),
+                0xb5 => Putfield(read_u2!()),
+                0xb6 => Invokevirtual(read_u2!()),
                 0xb7 => Invokespecial(read_u2!()),
+                0xb8 => Invokestatic(read_u2!()),
+                0xb9 => {
+                    let index = read_u2!();
+                    let count = read_u1!();
+                    if count == 0 || read_u1!() != 0 {
+                        return Err(InvalidCode);
+                    }
+                    Invokeinterface(index, count)
+                }
+                0xba => {
+                    let index = read_u2!();
+                    if read_u2!() != 0 {
+                        return Err(InvalidCode);
+                    }
+                    Invokedynamic(index)
+                }
+                0xbb => New(read_u2!()),
+                0xbc => Newarray(read_u1!()),
+                0xbd => Anewarray(read_u2!()),
+                0xbe => Arraylength,
+                0xbf => Athrow,
+                0xc0 => Checkcast(read_u2!()),
+                0xc1 => Instanceof(read_u2!()),
+                0xc2 => Monitorenter,
+                0xc3 => Monitorexit,
+                0xc4 => {
+                    // wide versions have a two byte local variable index
+                    let opcode = read_u1!();
+                    match opcode {
+                        0x15 => Iload(read_u2!()),
+                        0x16 => Lload(read_u2!()),
+                        0x17 => Fload(read_u2!()),
+                        0x18 => Dload(read_u2!()),
+                        0x19 => Aload(read_u2!()),
+                        0x36 => Istore(read_u2!()),
+                        0x37 => Lstore(read_u2!()),
+                        0x38 => Fstore(read_u2!()),
+                        0x39 => Dstore(read_u2!()),
+                        0x3a => Astore(read_u2!()),
+                        0x84 => Iinc(read_u2!(), read_i2!()),
+                        0xa9 if !version.allows_subroutines() =>
+                            return Err(OpcodeNotAllowed(opcode, version)),
+                        0xa9 => Ret(read_u2!()),
+                        _ => return Err(InvalidOpcode(opcode)),
+                    }
+                }
+                0xc5 => Multianewarray(read_u2!(), read_u1!()),
+                0xc6 => Ifnull(index_from_relative!(read_i2!())),
+                0xc7 => Ifnonnull(index_from_relative!(read_i2!())),
+                0xc8 => GotoW(index_from_relative!(read_i4!())),
+                0xc9 => JsrW(index_from_relative!(read_i4!())),
+                0xca => Breakpoint,
+                0xfe => Impdep1,
+                0xff => Impdep2,
                 _ => return Err(InvalidOpcode(opcode))
             });
 
             pc += 1;
         }
 
-        Ok(code)
+        Ok((code, switches, instruction_offsets))
+    }
+
+    /// the opcode and target of a branch that has a two byte offset
+    fn short_branch(&self) -> Option<(u8, u16)> {
+        use self::Instruction::*;
+
+        Some(match *self {
+            Ifeq(target) => (0x99, target),
+            Ifne(target) => (0x9a, target),
+            Iflt(target) => (0x9b, target),
+            Ifge(target) => (0x9c, target),
+            Ifgt(target) => (0x9d, target),
+            Ifle(target) => (0x9e, target),
+            IfIcmpeq(target) => (0x9f, target),
+            IfIcmpne(target) => (0xa0, target),
+            IfIcmplt(target) => (0xa1, target),
+            IfIcmpge(target) => (0xa2, target),
+            IfIcmpgt(target) => (0xa3, target),
+            IfIcmple(target) => (0xa4, target),
+            IfAcmpeq(target) => (0xa5, target),
+            IfAcmpne(target) => (0xa6, target),
+            Goto(target) => (0xa7, target),
+            Jsr(target) => (0xa8, target),
+            Ifnull(target) => (0xc6, target),
+            Ifnonnull(target) => (0xc7, target),
+            _ => return None,
+        })
+    }
+
+    /// encode this instruction, which starts at byte `offset`.
+    /// `long` widens a two byte branch: `goto` and `jsr` become `goto_w` and `jsr_w`,
+    /// and conditional branches are inverted to jump over a `goto_w`.
+    /// `target` gives the byte offset of an instruction index
+    fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        offset: u32,
+        long: bool,
+        switches: &[SwitchTable],
+        target: &dyn Fn(u16) -> Result<u32, BytecodeWriteError>,
+    ) -> Result<(), BytecodeWriteError> {
+        use self::Instruction::*;
+
+        let relative = |from: u32, to: u16| -> Result<i32, BytecodeWriteError> {
+            Ok(target(to)? as i32 - from as i32)
+        };
+
+        if let Some((opcode, to)) = self.short_branch() {
+            if !long {
+                out.push(opcode);
+                // offsets that don't fit are only seen while sizing, before widening
+                out.extend_from_slice(&(relative(offset, to)? as i16).to_be_bytes());
+            } else if opcode == 0xa7 || opcode == 0xa8 {
+                out.push(opcode + 0x21);
+                out.extend_from_slice(&relative(offset, to)?.to_be_bytes());
+            } else {
+                // if<cond> and ifnull/ifnonnull come in pairs of opposites
+                let inverse = match opcode {
+                    0xc6 => 0xc7,
+                    0xc7 => 0xc6,
+                    x if (x - 0x99) % 2 == 0 => x + 1,
+                    x => x - 1,
+                };
+                out.push(inverse);
+                out.extend_from_slice(&8i16.to_be_bytes());
+                out.push(0xc8);
+                out.extend_from_slice(&relative(offset + 3, to)?.to_be_bytes());
+            }
+            return Ok(());
+        }
+
+        // loads and stores have one byte forms for the first four locals, and wide forms past 255
+        let local = |out: &mut Vec<u8>, opcode: u8, short: Option<u8>, n: u16| {
+            match (n, short) {
+                (0..=3, Some(base)) => out.push(base + n as u8),
+                (0..=255, _) => out.extend_from_slice(&[opcode, n as u8]),
+                _ => {
+                    out.extend_from_slice(&[0xc4, opcode]);
+                    out.extend_from_slice(&n.to_be_bytes());
+                }
+            }
+        };
+        let with_u2 = |out: &mut Vec<u8>, opcode: u8, n: u16| {
+            out.push(opcode);
+            out.extend_from_slice(&n.to_be_bytes());
+        };
+
+        match *self {
+            Nop => out.push(0x00),
+            AconstNull => out.push(0x01),
+            Ipush(x @ -1..=5) => out.push((x + 0x03) as u8),
+            Ipush(x @ -128..=127) => out.extend_from_slice(&[0x10, x as u8]),
+            Ipush(x) => with_u2(out, 0x11, x as u16),
+            Lconst(x) => out.push(0x09 + x),
+            Fconst(x) => out.push(0x0b + x),
+            Dconst(x) => out.push(0x0e + x),
+            Ldc(n @ 0..=255) => out.extend_from_slice(&[0x12, n as u8]),
+            Ldc(n) => with_u2(out, 0x13, u16::try_from(n).map_err(|_| BytecodeWriteError::BadIndex(n))?),
+            Ldc2W(n) => with_u2(out, 0x14, n),
+            Iload(n) => local(out, 0x15, Some(0x1a), n),
+            Lload(n) => local(out, 0x16, Some(0x1e), n),
+            Fload(n) => local(out, 0x17, Some(0x22), n),
+            Dload(n) => local(out, 0x18, Some(0x26), n),
+            Aload(n) => local(out, 0x19, Some(0x2a), n),
+            Iaload => out.push(0x2e),
+            Laload => out.push(0x2f),
+            Faload => out.push(0x30),
+            Daload => out.push(0x31),
+            Aaload => out.push(0x32),
+            Baload => out.push(0x33),
+            Caload => out.push(0x34),
+            Saload => out.push(0x35),
+            Istore(n) => local(out, 0x36, Some(0x3b), n),
+            Lstore(n) => local(out, 0x37, Some(0x3f), n),
+            Fstore(n) => local(out, 0x38, Some(0x43), n),
+            Dstore(n) => local(out, 0x39, Some(0x47), n),
+            Astore(n) => local(out, 0x3a, Some(0x4b), n),
+            Iastore => out.push(0x4f),
+            Lastore => out.push(0x50),
+            Fastore => out.push(0x51),
+            Dastore => out.push(0x52),
+            Aastore => out.push(0x53),
+            Bastore => out.push(0x54),
+            Castore => out.push(0x55),
+            Sastore => out.push(0x56),
+            Pop => out.push(0x57),
+            Pop2 => out.push(0x58),
+            Dup => out.push(0x59),
+            DupX1 => out.push(0x5a),
+            DupX2 => out.push(0x5b),
+            Dup2 => out.push(0x5c),
+            Dup2X1 => out.push(0x5d),
+            Dup2X2 => out.push(0x5e),
+            Swap => out.push(0x5f),
+            Iadd => out.push(0x60),
+            Ladd => out.push(0x61),
+            Fadd => out.push(0x62),
+            Dadd => out.push(0x63),
+            Isub => out.push(0x64),
+            Lsub => out.push(0x65),
+            Fsub => out.push(0x66),
+            Dsub => out.push(0x67),
+            Imul => out.push(0x68),
+            Lmul => out.push(0x69),
+            Fmul => out.push(0x6a),
+            Dmul => out.push(0x6b),
+            Idiv => out.push(0x6c),
+            Ldiv => out.push(0x6d),
+            Fdiv => out.push(0x6e),
+            Ddiv => out.push(0x6f),
+            Irem => out.push(0x70),
+            Lrem => out.push(0x71),
+            Frem => out.push(0x72),
+            Drem => out.push(0x73),
+            Ineg => out.push(0x74),
+            Lneg => out.push(0x75),
+            Fneg => out.push(0x76),
+            Dneg => out.push(0x77),
+            Ishl => out.push(0x78),
+            Lshl => out.push(0x79),
+            Ishr => out.push(0x7a),
+            Lshr => out.push(0x7b),
+            Iushr => out.push(0x7c),
+            Lushr => out.push(0x7d),
+            Iand => out.push(0x7e),
+            Land => out.push(0x7f),
+            Ior => out.push(0x80),
+            Lor => out.push(0x81),
+            Ixor => out.push(0x82),
+            Lxor => out.push(0x83),
+            Iinc(n @ 0..=255, by @ -128..=127) => out.extend_from_slice(&[0x84, n as u8, by as u8]),
+            Iinc(n, by) => {
+                out.extend_from_slice(&[0xc4, 0x84]);
+                out.extend_from_slice(&n.to_be_bytes());
+                out.extend_from_slice(&by.to_be_bytes());
+            }
+            I2l => out.push(0x85),
+            I2f => out.push(0x86),
+            I2d => out.push(0x87),
+            L2i => out.push(0x88),
+            L2f => out.push(0x89),
+            L2d => out.push(0x8a),
+            F2i => out.push(0x8b),
+            F2l => out.push(0x8c),
+            F2d => out.push(0x8d),
+            D2i => out.push(0x8e),
+            D2l => out.push(0x8f),
+            D2f => out.push(0x90),
+            I2b => out.push(0x91),
+            I2c => out.push(0x92),
+            I2s => out.push(0x93),
+            Lcmp => out.push(0x94),
+            Fcmpl => out.push(0x95),
+            Fcmpg => out.push(0x96),
+            Dcmpl => out.push(0x97),
+            Dcmpg => out.push(0x98),
+            Ret(n) => local(out, 0xa9, None, n),
+            Tableswitch(n) | Lookupswitch(n) => {
+                let table = switches.get(n as usize).ok_or(BytecodeWriteError::BadSwitch(n))?;
+                out.push(if let Tableswitch(_) = self { 0xaa } else { 0xab });
+                // pad so the operands start at a multiple of four bytes
+                out.resize(out.len() + (3 - offset % 4) as usize, 0);
+                out.extend_from_slice(&relative(offset, table.default())?.to_be_bytes());
+                match (self, table) {
+                    (Tableswitch(_), SwitchTable::Table { low, targets, .. }) if !targets.is_empty() => {
+                        let high = low.checked_add(targets.len() as i32 - 1)
+                            .ok_or(BytecodeWriteError::BadSwitch(n))?;
+                        out.extend_from_slice(&low.to_be_bytes());
+                        out.extend_from_slice(&high.to_be_bytes());
+                        for t in targets {
+                            out.extend_from_slice(&relative(offset, *t)?.to_be_bytes());
+                        }
+                    }
+                    (Lookupswitch(_), SwitchTable::Lookup { pairs, .. }) => {
+                        out.extend_from_slice(&(pairs.len() as i32).to_be_bytes());
+                        for (key, t) in pairs {
+                            out.extend_from_slice(&key.to_be_bytes());
+                            out.extend_from_slice(&relative(offset, *t)?.to_be_bytes());
+                        }
+                    }
+                    _ => return Err(BytecodeWriteError::BadSwitch(n)),
+                }
+            }
+            Ireturn => out.push(0xac),
+            Lreturn => out.push(0xad),
+            Freturn => out.push(0xae),
+            Dreturn => out.push(0xaf),
+            Areturn => out.push(0xb0),
+            Return => out.push(0xb1),
+            Getstatic(n) => with_u2(out, 0xb2, n),
+            Putstatic(n) => with_u2(out, 0xb3, n),
+            Getfield(n) => with_u2(out, 0xb4, n),
+            Putfield(n) => with_u2(out, 0xb5, n),
+            Invokevirtual(n) => with_u2(out, 0xb6, n),
+            Invokespecial(n) => with_u2(out, 0xb7, n),
+            Invokestatic(n) => with_u2(out, 0xb8, n),
+            Invokeinterface(n, count) => {
+                with_u2(out, 0xb9, n);
+                out.extend_from_slice(&[count, 0]);
+            }
+            Invokedynamic(n) => {
+                with_u2(out, 0xba, n);
+                out.extend_from_slice(&[0, 0]);
+            }
+            New(n) => with_u2(out, 0xbb, n),
+            Newarray(atype) => out.extend_from_slice(&[0xbc, atype]),
+            Anewarray(n) => with_u2(out, 0xbd, n),
+            Arraylength => out.push(0xbe),
+            Athrow => out.push(0xbf),
+            Checkcast(n) => with_u2(out, 0xc0, n),
+            Instanceof(n) => with_u2(out, 0xc1, n),
+            Monitorenter => out.push(0xc2),
+            Monitorexit => out.push(0xc3),
+            Multianewarray(n, dimensions) => {
+                with_u2(out, 0xc5, n);
+                out.push(dimensions);
+            }
+            GotoW(to) => {
+                out.push(0xc8);
+                out.extend_from_slice(&relative(offset, to)?.to_be_bytes());
+            }
+            JsrW(to) => {
+                out.push(0xc9);
+                out.extend_from_slice(&relative(offset, to)?.to_be_bytes());
+            }
+            Breakpoint => out.push(0xca),
+            Impdep1 => out.push(0xfe),
+            Impdep2 => out.push(0xff),
+            GetstaticQuick(..) | PutstaticQuick(..) | GetfieldQuick(..) | PutfieldQuick(..) |
+            InvokevirtualQuick(..) | InvokespecialQuick(..) | InvokestaticQuick(..) |
+            InvokeinterfaceQuick(..) => self.unquickened().encode(out, offset, long, switches, target)?,
+            IaddLocals(..) | IsubLocals(..) | ImulLocals(..) | IfIcmpLocals(..) | IfIcmpConst(..) |
+            AloadGetfield(..) | AloadGetfieldQuick(..) =>
+                return Err(BytecodeWriteError::Superinstruction(self.mnemonic())),
+            Ifeq(..) | Ifne(..) | Iflt(..) | Ifge(..) | Ifgt(..) | Ifle(..) |
+            IfIcmpeq(..) | IfIcmpne(..) | IfIcmplt(..) | IfIcmpge(..) | IfIcmpgt(..) | IfIcmple(..) |
+            IfAcmpeq(..) | IfAcmpne(..) | Goto(..) | Jsr(..) | Ifnull(..) | Ifnonnull(..) =>
+                unreachable!("two byte branches are encoded above"),
+        }
+        Ok(())
+    }
+
+    /// encode instructions with instruction index branch targets back into bytecode.
+    /// returns the bytes, and the byte offset of every instruction followed by the
+    /// length of the code, for translating other instruction indices
+    pub fn write_to(code: &[Instruction], switches: &[SwitchTable]) -> Result<(Vec<u8>, Vec<u32>), BytecodeWriteError> {
+        let mut long = vec![false; code.len()];
+
+        loop {
+            // lay the code out with the branches that have been widened so far
+            let mut offsets = Vec::with_capacity(code.len() + 1);
+            let mut offset = 0;
+            let mut scratch = vec![];
+            for (i, instruction) in code.iter().enumerate() {
+                offsets.push(offset);
+                scratch.clear();
+                instruction.encode(&mut scratch, offset, long[i], switches, &|_| Ok(0))?;
+                offset += scratch.len() as u32;
+            }
+            offsets.push(offset);
+
+            let target = |to: u16| -> Result<u32, BytecodeWriteError> {
+                if (to as usize) < code.len() {
+                    Ok(offsets[to as usize])
+                } else {
+                    Err(BytecodeWriteError::BadTarget(to))
+                }
+            };
+
+            // widening a branch only ever moves code further apart, so this settles
+            let mut widened = false;
+            for (i, instruction) in code.iter().enumerate() {
+                if let Some((_, to)) = instruction.short_branch() {
+                    let relative = target(to)? as i64 - offsets[i] as i64;
+                    if !long[i] && i16::try_from(relative).is_err() {
+                        long[i] = true;
+                        widened = true;
+                    }
+                }
+            }
+            if widened {
+                continue;
+            }
+
+            if offset > u16::MAX as u32 {
+                return Err(BytecodeWriteError::TooLong);
+            }
+            let mut bytes = Vec::with_capacity(offset as usize);
+            for (i, instruction) in code.iter().enumerate() {
+                instruction.encode(&mut bytes, offsets[i], long[i], switches, &target)?;
+            }
+            return Ok((bytes, offsets));
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::mem;
-    use crate::bytecode::Instruction;
+    use crate::bytecode::{BytecodeParseError, Instruction};
     use crate::bytecode::Instruction::GotoW;
 
     #[test]
     fn instruction_size() {
         assert!(8 >= mem::size_of::<Instruction>());
     }
+
+    #[test]
+    fn short_forms() {
+        use crate::class_version::ClassVersion;
+        use Instruction::*;
+
+        // iconst_m1, lconst_1, fconst_2, iload_3, astore_0, lstore 7, ldc_w #300, ifnull -> nop, nop
+        let code = [0x02, 0x0a, 0x0d, 0x1d, 0x4b, 0x37, 0x07, 0x13, 0x01, 0x2c, 0xc6, 0x00, 0x03, 0x00];
+        let (decoded, _, _) = Instruction::read_from(&code, code.len() as u32, ClassVersion::new(52, 0)).unwrap();
+        assert_eq!(decoded, vec![
+            Ipush(-1), Lconst(1), Fconst(2), Iload(3), Astore(0), Lstore(7), Ldc(300), Ifnull(8), Nop,
+        ]);
+        assert_eq!(Instruction::write_to(&decoded, &[]).unwrap().0, code);
+    }
+
+    #[test]
+    fn wide_and_subroutines() {
+        use crate::class_version::ClassVersion;
+        use Instruction::{Astore, Iinc, Iload, Jsr, Ret, Return};
+
+        // wide iload 300, wide iinc 300 -2, jsr -> astore_1, goto_w -> return, astore_1, wide ret 1, return
+        let code = [
+            0xc4, 0x15, 0x01, 0x2c,
+            0xc4, 0x84, 0x01, 0x2c, 0xff, 0xfe,
+            0xa8, 0x00, 0x08,
+            0xc8, 0x00, 0x00, 0x00, 0x0a,
+            0x4c,
+            0xc4, 0xa9, 0x00, 0x01,
+            0xb1,
+        ];
+        let (decoded, _, _) = Instruction::read_from(&code, code.len() as u32, ClassVersion::new(49, 0)).unwrap();
+        assert_eq!(decoded, vec![Iload(300), Iinc(300, -2), Jsr(4), GotoW(6), Astore(1), Ret(1), Return]);
+
+        // the wide prefix on ret 1 isn't needed
+        let (encoded, _) = Instruction::write_to(&decoded, &[]).unwrap();
+        assert_eq!(encoded.len(), code.len() - 2);
+        let (redecoded, _, _) = Instruction::read_from(&encoded, encoded.len() as u32, ClassVersion::new(49, 0)).unwrap();
+        assert_eq!(redecoded, decoded);
+
+        let e = Instruction::read_from(&code, code.len() as u32, ClassVersion::new(51, 0)).unwrap_err();
+        assert_eq!(e, BytecodeParseError::OpcodeNotAllowed(0xa8, ClassVersion::new(51, 0)));
+    }
+
+    #[test]
+    fn switches() {
+        use crate::bytecode::SwitchTable;
+        use crate::class_version::ClassVersion;
+        use Instruction::*;
+
+        let code = [
+            // 0: iload_0, 1: tableswitch with two bytes of padding, low 1, high 2
+            0x1a, 0xaa, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x2c,
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02,
+            0x00, 0x00, 0x00, 0x2b, 0x00, 0x00, 0x00, 0x2b,
+            // 24: lookupswitch with three bytes of padding, one pair
+            0xab, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x15,
+            0x00, 0x00, 0x00, 0x01,
+            0xff, 0xff, 0xff, 0xf6, 0x00, 0x00, 0x00, 0x14,
+            // 44: nop, 45: return
+            0x00, 0xb1,
+        ];
+        let code = &code[..];
+        let (decoded, switches, offsets) = Instruction::read_from(code, code.len() as u32, ClassVersion::new(52, 0)).unwrap();
+        assert_eq!(decoded, vec![Iload(0), Tableswitch(0), Lookupswitch(1), Nop, Return]);
+        assert_eq!(switches[0], SwitchTable::Table { default: 4, low: 1, targets: vec![3, 3] });
+        assert_eq!(switches[1], SwitchTable::Lookup { default: 4, pairs: vec![(-10, 3)] });
+        assert_eq!(switches[0].target(2), 3);
+        assert_eq!(switches[0].target(3), 4);
+        assert_eq!(switches[1].target(-10), 3);
+
+        // moving the switches moves their padding
+        assert_eq!(offsets, vec![0, 1, 24, 44, 45, 46]);
+        assert_eq!(Instruction::write_to(&decoded, &switches).unwrap(), (code.to_vec(), offsets));
+        let (encoded, _) = Instruction::write_to(&[&[Nop][..], &decoded].concat(), &[
+            SwitchTable::Table { default: 5, low: 1, targets: vec![4, 4] },
+            SwitchTable::Lookup { default: 5, pairs: vec![(-10, 4)] },
+        ]).unwrap();
+        assert_eq!(encoded[..4], [0x00, 0x1a, 0xaa, 0x00]);
+        // one less byte of padding makes up for the nop
+        assert_eq!(encoded.len(), code.len());
+
+        // a default that overflows is invalid
+        let mut bad = code.to_vec();
+        bad[4..8].copy_from_slice(&i32::MAX.to_be_bytes());
+        let e = Instruction::read_from(&bad, bad.len() as u32, ClassVersion::new(52, 0)).unwrap_err();
+        assert_eq!(e, BytecodeParseError::InvalidCode);
+
+        // and so is a lookupswitch with so many pairs that its size overflows
+        let mut bad = code.to_vec();
+        bad[32..36].copy_from_slice(&0x1ffffffe_i32.to_be_bytes());
+        let e = Instruction::read_from(&bad, bad.len() as u32, ClassVersion::new(52, 0)).unwrap_err();
+        assert_eq!(e, BytecodeParseError::EarlyEnd);
+    }
+
+    #[test]
+    fn long_branches() {
+        use crate::bytecode::BytecodeWriteError;
+        use crate::class_version::ClassVersion;
+        use Instruction::*;
+
+        // iload_0, ifeq -> return, 40000 nops, goto -> iload_0, return
+        let mut code = vec![Iload(0), Ifeq(40003)];
+        code.resize(40002, Nop);
+        code.extend_from_slice(&[Goto(0), Return]);
+
+        let (encoded, offsets) = Instruction::write_to(&code, &[]).unwrap();
+        // ifeq becomes ifne over a goto_w, and goto becomes goto_w
+        assert_eq!(encoded[1..8], [0x9a, 0x00, 0x08, 0xc8, 0x00, 0x00, 0x9c]);
+        assert_eq!(encoded[40009..], [0xc8, 0xff, 0xff, 0x63, 0xb7, 0xb1]);
+        assert_eq!(offsets[40003], 40014);
+
+        let (decoded, _, _) = Instruction::read_from(&encoded, encoded.len() as u32, ClassVersion::new(52, 0)).unwrap();
+        assert_eq!(decoded[..3], [Iload(0), Ifne(3), GotoW(40004)]);
+        assert_eq!(decoded[40003..], [GotoW(0), Return]);
+
+        // offsets that overflow are invalid, rather than wrapping around
+        let code = [0x00, 0xc8, 0x7f, 0xff, 0xff, 0xff];
+        let e = Instruction::read_from(&code, code.len() as u32, ClassVersion::new(52, 0)).unwrap_err();
+        assert_eq!(e, BytecodeParseError::InvalidCode);
+
+        assert_eq!(Instruction::write_to(&[Goto(1)], &[]), Err(BytecodeWriteError::BadTarget(1)));
+        assert_eq!(Instruction::write_to(&vec![Nop; 70000], &[]), Err(BytecodeWriteError::TooLong));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,390 @@
+//! Basic blocks and the control flow between them, for a method's code.
+
+use crate::attributes::code::Code;
+use crate::bytecode::Instruction;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    /// the index of the first instruction
+    pub start: u16,
+    /// one past the index of the last instruction
+    pub end: u16,
+    /// blocks that control goes to after this one, by falling through, branching,
+    /// switching, calling a subroutine or returning from one
+    pub successors: Vec<usize>,
+    /// handlers for exceptions thrown in this block
+    pub handlers: Vec<usize>,
+    /// blocks that have this one as a successor or a handler
+    pub predecessors: Vec<usize>,
+}
+
+/// The control flow graph of some code, with the dominator tree of its blocks.
+///
+/// Block 0 is the entry. Exception edges count as edges for dominators and loops.
+/// A `ret` may return to after any `jsr`, since which one isn't known until run time.
+#[derive(Debug, Clone)]
+pub struct ControlFlowGraph {
+    pub blocks: Vec<BasicBlock>,
+    /// the block each instruction is in
+    block_of: Vec<usize>,
+    /// the immediate dominator of each block. `None` for the entry and unreachable blocks
+    idom: Vec<Option<usize>>,
+    /// blocks reachable from the entry, in reverse postorder
+    order: Vec<usize>,
+}
+
+impl Instruction {
+    /// the instructions this one may branch to, not counting the next one.
+    /// `ret` has none, since its target comes from a local
+    pub fn branch_targets(&self, code: &Code) -> Vec<u16> {
+        use self::Instruction::*;
+
+        match *self {
+            Ifeq(t) | Ifne(t) | Iflt(t) | Ifge(t) | Ifgt(t) | Ifle(t) |
+            IfIcmpeq(t) | IfIcmpne(t) | IfIcmplt(t) | IfIcmpge(t) | IfIcmpgt(t) | IfIcmple(t) |
+            IfAcmpeq(t) | IfAcmpne(t) | Ifnull(t) | Ifnonnull(t) |
+            Goto(t) | GotoW(t) | Jsr(t) | JsrW(t) |
+            IfIcmpLocals(.., t) | IfIcmpConst(.., t) => vec![t],
+            Tableswitch(n) | Lookupswitch(n) => {
+                let table = &code.switches[n as usize];
+                let mut targets = table.targets();
+                targets.push(table.default());
+                targets
+            }
+            _ => vec![],
+        }
+    }
+
+    /// whether the next instruction can run straight after this one.
+    /// this isn't true of `jsr`, since the next instruction runs after the subroutine returns
+    pub fn falls_through(&self) -> bool {
+        use self::Instruction::*;
+
+        !matches!(self,
+            Goto(_) | GotoW(_) | Jsr(_) | JsrW(_) | Ret(_) |
+            Tableswitch(_) | Lookupswitch(_) |
+            Ireturn | Lreturn | Freturn | Dreturn | Areturn | Return | Athrow
+        )
+    }
+
+    /// whether this ends a basic block
+    fn ends_block(&self, code: &Code) -> bool {
+        !self.falls_through() || !self.branch_targets(code).is_empty()
+    }
+}
+
+impl ControlFlowGraph {
+    pub fn new(code: &Code) -> ControlFlowGraph {
+        let instructions = code.instructions();
+        let len = instructions.len();
+
+        // find the first instruction of every block
+        let mut leader = vec![false; len + 1];
+        leader[0] = true;
+        let mut return_sites = vec![];
+        for (i, instruction) in instructions.iter().enumerate() {
+            for t in instruction.branch_targets(code) {
+                leader[t as usize] = true;
+            }
+            if instruction.ends_block(code) {
+                leader[i + 1] = true;
+            }
+            if let Instruction::Jsr(_) | Instruction::JsrW(_) = instruction {
+                return_sites.push(i as u16 + 1);
+            }
+        }
+        // handlers past the end are left for the verifier to complain about
+        let exception_table: Vec<_> = code.exception_table.iter()
+            .filter(|item| (item.handler_pc as usize) < len && item.end_pc as usize <= len)
+            .collect();
+        // exception ranges start and end on block boundaries, so a whole block is in or out of one
+        for item in &exception_table {
+            leader[item.start_pc as usize] = true;
+            leader[item.end_pc as usize] = true;
+            leader[item.handler_pc as usize] = true;
+        }
+
+        let mut blocks = vec![];
+        let mut block_of = Vec::with_capacity(len);
+        for (i, is_leader) in leader[..len].iter().enumerate() {
+            if *is_leader {
+                blocks.push(BasicBlock {
+                    start: i as u16,
+                    end: i as u16,
+                    successors: vec![],
+                    handlers: vec![],
+                    predecessors: vec![],
+                });
+            }
+            blocks.last_mut().unwrap().end += 1;
+            block_of.push(blocks.len() - 1);
+        }
+
+        for (b, block) in blocks.iter_mut().enumerate() {
+            let last = block.end - 1;
+            let instruction = &instructions[last as usize];
+            let mut successors = vec![];
+            for t in instruction.branch_targets(code) {
+                successors.push(block_of[t as usize]);
+            }
+            if let Instruction::Ret(_) = instruction {
+                for pc in &return_sites {
+                    if (*pc as usize) < len {
+                        successors.push(block_of[*pc as usize]);
+                    }
+                }
+            }
+            if instruction.falls_through() && (last as usize + 1) < len {
+                successors.push(b + 1);
+            }
+            dedup(&mut successors);
+
+            let mut handlers = vec![];
+            for item in &exception_table {
+                if item.start_pc <= block.start && block.start < item.end_pc {
+                    handlers.push(block_of[item.handler_pc as usize]);
+                }
+            }
+            dedup(&mut handlers);
+
+            block.successors = successors;
+            block.handlers = handlers;
+        }
+
+        for b in 0..blocks.len() {
+            let edges: Vec<usize> = blocks[b].successors.iter().chain(&blocks[b].handlers).copied().collect();
+            for to in edges {
+                if !blocks[to].predecessors.contains(&b) {
+                    blocks[to].predecessors.push(b);
+                }
+            }
+        }
+
+        let mut cfg = ControlFlowGraph {
+            idom: vec![None; blocks.len()],
+            blocks,
+            block_of,
+            order: vec![],
+        };
+        cfg.order = cfg.reverse_postorder();
+        cfg.find_dominators();
+        cfg
+    }
+
+    /// every block control can go to from `b`, normally or by an exception
+    pub fn edges(&self, b: usize) -> impl Iterator<Item = usize> + '_ {
+        self.blocks[b].successors.iter().chain(&self.blocks[b].handlers).copied()
+    }
+
+    /// the block that the instruction at `pc` is in
+    pub fn block_of(&self, pc: u16) -> usize {
+        self.block_of[pc as usize]
+    }
+
+    /// blocks reachable from the entry, in reverse postorder. every block comes
+    /// before its successors, other than along back edges
+    pub fn order(&self) -> &[usize] {
+        &self.order
+    }
+
+    pub fn is_reachable(&self, b: usize) -> bool {
+        b == 0 || self.idom[b].is_some()
+    }
+
+    /// the closest block other than `b` that every path from the entry to `b` goes through
+    pub fn immediate_dominator(&self, b: usize) -> Option<usize> {
+        self.idom[b]
+    }
+
+    /// whether every path from the entry to `b` goes through `a`
+    pub fn dominates(&self, a: usize, b: usize) -> bool {
+        if !self.is_reachable(b) {
+            return false;
+        }
+        let mut b = Some(b);
+        while let Some(x) = b {
+            if x == a {
+                return true;
+            }
+            b = self.idom[x];
+        }
+        false
+    }
+
+    /// blocks that are the target of a back edge, from a block they dominate
+    pub fn loop_headers(&self) -> Vec<usize> {
+        let mut headers: Vec<usize> = (0..self.blocks.len())
+            .filter(|h| self.blocks[*h].predecessors.iter().any(|p| self.dominates(*h, *p)))
+            .collect();
+        headers.sort();
+        headers
+    }
+
+    fn reverse_postorder(&self) -> Vec<usize> {
+        if self.blocks.is_empty() {
+            return vec![];
+        }
+        let mut visited = vec![false; self.blocks.len()];
+        let mut postorder = vec![];
+        // (block, how many of its edges have been followed)
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some((b, next)) = stack.pop() {
+            match self.edges(b).nth(next) {
+                Some(to) => {
+                    stack.push((b, next + 1));
+                    if !visited[to] {
+                        visited[to] = true;
+                        stack.push((to, 0));
+                    }
+                }
+                None => postorder.push(b),
+            }
+        }
+        postorder.reverse();
+        postorder
+    }
+
+    /// Cooper, Harvey and Kennedy's "A Simple, Fast Dominance Algorithm"
+    fn find_dominators(&mut self) {
+        let mut position = vec![usize::MAX; self.blocks.len()];
+        for (i, b) in self.order.iter().enumerate() {
+            position[*b] = i;
+        }
+
+        // the entry is its own dominator while this runs
+        let mut idom: Vec<Option<usize>> = vec![None; self.blocks.len()];
+        if let Some(entry) = idom.first_mut() {
+            *entry = Some(0);
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in self.order.iter().skip(1) {
+                let mut new: Option<usize> = None;
+                for p in &self.blocks[*b].predecessors {
+                    if idom[*p].is_none() {
+                        continue;
+                    }
+                    new = Some(match new {
+                        None => *p,
+                        Some(mut x) => {
+                            let mut y = *p;
+                            while x != y {
+                                while position[x] > position[y] {
+                                    x = idom[x].unwrap();
+                                }
+                                while position[y] > position[x] {
+                                    y = idom[y].unwrap();
+                                }
+                            }
+                            x
+                        }
+                    });
+                }
+                if new.is_some() && idom[*b] != new {
+                    idom[*b] = new;
+                    changed = true;
+                }
+            }
+        }
+
+        if let Some(entry) = idom.first_mut() {
+            *entry = None;
+        }
+        self.idom = idom;
+    }
+}
+
+/// remove repeats, keeping the first of each
+fn dedup(v: &mut Vec<usize>) {
+    let mut seen = vec![];
+    v.retain(|x| {
+        let new = !seen.contains(x);
+        seen.push(*x);
+        new
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attributes::code::ExceptionTableItem;
+    use crate::bytecode::SwitchTable;
+    use crate::bytecode::Instruction::*;
+
+    fn code(code: Vec<Instruction>, switches: Vec<SwitchTable>, exception_table: Vec<ExceptionTableItem>) -> Code {
+        Code {
+            max_stack: 2,
+            max_locals: 2,
+            code: Code::cells(code),
+            switches,
+            byte_offsets: vec![],
+            exception_table,
+            attributes: vec![],
+        }
+    }
+
+    #[test]
+    fn loop_and_switch() {
+        let code = code(vec![
+            // 0: i = 0
+            Ipush(0), Istore(0),
+            // 2: while i < 10
+            Iload(0), Ipush(10), IfIcmpge(11),
+            // 5: switch i
+            Iload(0), Tableswitch(0),
+            // 7, 8: the cases
+            Iinc(0, 1), Goto(2),
+            Iinc(0, 2), Goto(2),
+            // 11
+            Return,
+            // 12: never reached
+            Return,
+        ], vec![SwitchTable::Table { default: 9, low: 0, targets: vec![7] }], vec![]);
+        let cfg = ControlFlowGraph::new(&code);
+
+        let starts: Vec<u16> = cfg.blocks.iter().map(|b| b.start).collect();
+        assert_eq!(starts, vec![0, 2, 5, 7, 9, 11, 12]);
+        assert_eq!(cfg.blocks[1].successors, vec![5, 2]);
+        assert_eq!(cfg.blocks[2].successors, vec![3, 4]);
+        assert_eq!(cfg.blocks[1].predecessors, vec![0, 3, 4]);
+        assert_eq!(cfg.block_of(8), 3);
+
+        assert_eq!(cfg.immediate_dominator(1), Some(0));
+        assert_eq!(cfg.immediate_dominator(3), Some(2));
+        assert_eq!(cfg.immediate_dominator(5), Some(1));
+        assert!(cfg.dominates(1, 4));
+        assert!(!cfg.dominates(3, 4));
+        assert!(!cfg.is_reachable(6));
+        assert_eq!(cfg.loop_headers(), vec![1]);
+        assert_eq!(cfg.order()[..2], [0, 1]);
+    }
+
+    #[test]
+    fn exceptions_and_subroutines() {
+        let code = code(vec![
+            // 0: try
+            Aload(0), Jsr(5),
+            // 2
+            Return,
+            // 3: catch
+            Astore(1), Return,
+            // 5: finally
+            Astore(1), Ret(1),
+        ], vec![], vec![ExceptionTableItem { start_pc: 0, end_pc: 2, catch_type: 0, handler_pc: 3 }]);
+        let cfg = ControlFlowGraph::new(&code);
+
+        let starts: Vec<u16> = cfg.blocks.iter().map(|b| b.start).collect();
+        assert_eq!(starts, vec![0, 2, 3, 5]);
+        assert_eq!(cfg.blocks[0].successors, vec![3]);
+        assert_eq!(cfg.blocks[0].handlers, vec![2]);
+        assert!(cfg.blocks[1].handlers.is_empty());
+        // the subroutine returns to after the jsr
+        assert_eq!(cfg.blocks[3].successors, vec![1]);
+        assert_eq!(cfg.immediate_dominator(2), Some(0));
+        assert_eq!(cfg.immediate_dominator(1), Some(3));
+        assert!(cfg.loop_headers().is_empty());
+    }
+}
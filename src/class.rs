@@ -1,11 +1,21 @@
 use std::collections::hash_map::Iter;
+use crate::assembler;
+use crate::attributes::attribute::Attribute;
 use crate::class_file::ClassFile;
-use crate::constant_pool::ConstantPoolInfo;
+use crate::class_reader::Structure;
+use crate::class_version::ClassVersion;
+use crate::constant_pool::ConstantPool;
+use crate::cp_cache::ConstantPoolCache;
 use crate::field_info::Field;
+use crate::format_check;
+use crate::peephole;
 use crate::method::{JavaMethod, Method};
 use crate::{ClassReader, Runtime};
 use std::collections::HashMap;
+use std::ptr::null_mut;
 use crate::class::Class::{Java, Native};
+use crate::runtime::LoadError;
+use crate::verifier;
 use crate::values::{Object, Value};
 
 pub(crate) mod access_flags {
@@ -64,6 +74,26 @@ impl Class {
             Native(c) => &c.instance_fields,
         }
     }
+    /// the field of this class, static or not, with this name and descriptor
+    pub fn field_mut(&mut self, name: &str, descriptor: &str) -> Option<&mut Field> {
+        let (static_fields, instance_fields) = match self {
+            Java(c) => (&mut c.static_fields, &mut c.instance_fields),
+            Native(c) => (&mut c.static_fields, &mut c.instance_fields),
+        };
+        static_fields.get_mut(name)
+            .filter(|f| f.descriptor == descriptor)
+            .or_else(|| instance_fields.get_mut(name).filter(|f| f.descriptor == descriptor))
+    }
+    /// how many slots an object of this class needs for its fields, counting inherited ones
+    pub fn instance_slots(&self) -> usize {
+        let super_class = self.super_class();
+        let inherited = if super_class.is_null() {
+            0
+        } else {
+            unsafe { &*super_class }.instance_slots()
+        };
+        inherited + self.instance_fields_raw().len()
+    }
     pub fn methods(&self) -> &HashMap<(String, String), Method> {
         match self {
             Java(c) => &c.methods,
@@ -78,6 +108,78 @@ impl Class {
         }
     }
 
+    pub fn vtable(&self) -> &Vec<VTableEntry> {
+        match self {
+            Java(c) => &c.vtable,
+            Native(c) => &c.vtable,
+        }
+    }
+
+    /// build the vtable from the super class's, replacing the entries that methods of this
+    /// class override and adding slots for the rest. the class has to be where it will
+    /// stay, since its entries point back at it
+    pub(crate) fn link(&mut self) -> Result<(), LoadError> {
+        let this = self as *mut Class;
+        let super_class = self.super_class();
+
+        // nothing is invoked virtually through an interface
+        let vtable = if self.access_flags() & access_flags::INTERFACE != 0 {
+            vec![]
+        } else {
+            let mut vtable = if super_class.is_null() {
+                vec![]
+            } else {
+                unsafe { &*super_class }.vtable().clone()
+            };
+
+            // sorted, so that the slots don't depend on the order of the hash map
+            let mut methods = self.methods().iter()
+                .filter(|(_, m)| !m.is_static() && !m.is_private() && m.name() != "<init>")
+                .collect::<Vec<_>>();
+            methods.sort_by_key(|(key, _)| *key);
+
+            for (_, method) in methods {
+                let overridden = vtable.iter().position(|&(class, m)| {
+                    let m = unsafe { &*m };
+                    m.name() == method.name()
+                        && m.descriptor_string() == method.descriptor_string()
+                        && unsafe { &*class }.can_be_overridden_by(m, self.name())
+                });
+                match overridden {
+                    Some(i) => {
+                        let (class, m) = vtable[i];
+                        let m = unsafe { &*m };
+                        if m.is_final() {
+                            return Err(LoadError::OverridesFinal {
+                                class: self.name().to_string(),
+                                method: format!("{}.{}{}", unsafe { &*class }.name(), m.name(), m.descriptor_string()),
+                            });
+                        }
+                        vtable[i] = (this, method);
+                    }
+                    None => vtable.push((this, method)),
+                }
+            }
+            vtable
+        };
+
+        match self {
+            Java(c) => c.vtable = vtable,
+            Native(c) => c.vtable = vtable,
+        }
+        Ok(())
+    }
+
+    /// if `method` of this class can be overridden by a class named `by`: public and
+    /// protected methods can be, and package private ones only from the same package
+    fn can_be_overridden_by(&self, method: &Method, by: &str) -> bool {
+        fn package(name: &str) -> &str {
+            name.rsplit_once('/').map_or("", |(package, _)| package)
+        }
+        // public or protected
+        method.access_flags() & 0x0005 != 0 || package(self.name()) == package(by)
+    }
+
     pub fn java(&self) -> Option<*const JavaClass> {
         match self {
             Java(c) => Some(c as *const JavaClass),
@@ -155,6 +257,9 @@ impl Class {
     }
 
 }
+/// a method that `invokevirtual` can select, and the class that declares it
+pub type VTableEntry = (*mut Class, *const Method);
+
 #[derive(Debug)]
 pub struct NativeClass {
     pub name: String,
@@ -164,73 +269,127 @@ pub struct NativeClass {
     pub static_fields: HashMap<String, Field>,
     pub instance_fields: HashMap<String, Field>,
     pub methods: HashMap<(String, String), Method>,
+    /// filled in when the class is added to the runtime
+    pub vtable: Vec<VTableEntry>,
 }
 
 #[derive(Debug)]
 pub struct JavaClass {
     pub name: String,
-    pub constant_pool: Vec<ConstantPoolInfo>,
+    pub version: ClassVersion,
+    pub constant_pool: ConstantPool,
     pub access_flags: u16,
     pub super_class: *mut Class,
     pub interfaces: Vec<*mut Class>, // sorted
     pub static_fields: HashMap<String, Field>,
     pub instance_fields: HashMap<String, Field>,
     pub methods: HashMap<(String, String), Method>, // (Name, Descriptor)
-    pub attributes: HashMap<String, Vec<u8>>,           // String is name, Vec is data
+    pub attributes: Vec<Attribute>,
+    /// what the field and method references in `constant_pool` resolved to, filled in as
+    /// the interpreter first uses them
+    pub cp_cache: ConstantPoolCache,
+    /// the methods that `invokevirtual` selects from, by slot. built when the class is
+    /// added to the runtime
+    pub vtable: Vec<VTableEntry>,
 }
 
 impl<'a> JavaClass {
-    pub fn from_filename(name: &str, runtime: &mut Runtime) -> Result<JavaClass, std::io::Error> {
-        return Ok(Self::from_classfile(
-            ClassReader::new(name)?.read_classfile(),
-            runtime
-        ));
+    /// load a class file, or assemble a `.j` file, and verify and optimize it if the settings say to
+    pub fn from_filename(name: &str, runtime: &mut Runtime) -> Result<JavaClass, LoadError> {
+        let cf = if name.ends_with(".j") {
+            assembler::assemble(&std::fs::read_to_string(name)?)?
+        } else {
+            ClassReader::new(name)?.read_classfile()?
+        };
+        Self::load(cf, name, runtime)
+    }
+
+    /// check, verify and optimize a class read from `path`, as the runtime's settings ask
+    pub fn load(cf: ClassFile, path: &str, runtime: &mut Runtime) -> Result<JavaClass, LoadError> {
+        let mut cls = Self::from_classfile(cf, runtime)?;
+        if runtime.settings.should_verify(path) {
+            verifier::verify_class(&cls, runtime)?;
+        }
+        if runtime.settings.peephole {
+            cls.optimize();
+        }
+        Ok(cls)
     }
 
-    pub fn from_classfile(mut c: ClassFile, runtime: &mut Runtime) -> JavaClass {
-        let cp = &mut c.constant_pool;
+    pub fn from_classfile(c: ClassFile, runtime: &mut Runtime) -> Result<JavaClass, LoadError> {
+        format_check::check(&c)?;
+        let cp = &c.constant_pool;
+
+        let name = cp.class_name(c.this_class)?;
+
+        // only java/lang/Object has no super class
+        let super_class = if c.super_class == 0 {
+            null_mut()
+        } else {
+            runtime.load(cp.class_name(c.super_class)?)?
+        };
+
+        let interfaces = c.interfaces.iter()
+            .enumerate()
+            .map(|(i, x)| {
+                let name = cp.class_name(*x)
+                    .map_err(|e| e.within(Structure::Interface(i as u16)))?;
+                runtime.load(name)
+            })
+            .collect::<Result<Vec<_>, LoadError>>()?;
 
         let mut cls = JavaClass {
             access_flags: c.access_flags,
-            name: cp[(c.this_class - 1) as usize].class_name(cp),
-            super_class: runtime.load(cp[(c.super_class - 1) as usize].class_name(cp))
-                .expect("loading super class failed!"),
-            interfaces: c.interfaces.iter()
-                .map(|x|
-                    runtime.load(
-                        cp[(x - 1) as usize].utf8().unwrap()
-                    ).unwrap()
-                ).collect(),
+            name,
+            super_class,
+            interfaces,
             static_fields: HashMap::new(),
             instance_fields: HashMap::new(),
             methods: HashMap::new(),
-            attributes: HashMap::new(),
+            attributes: vec![],
+            version: c.version(),
             constant_pool: cp.clone(),
+            cp_cache: ConstantPoolCache::new(cp),
+            vtable: vec![],
         };
 
-        for fi in &c.fields {
-            let f = Field::from_info(cp, fi);
+        // instance fields are laid out after the ones inherited from the super class
+        let mut offset = if super_class.is_null() {
+            0
+        } else {
+            unsafe { &*super_class }.instance_slots()
+        };
+        for (i, fi) in c.fields.iter().enumerate() {
+            let mut f = Field::from_info(cp, fi)
+                .map_err(|e| e.within(Structure::Field(i as u16)))?;
             if f.is_static() {
-                &mut cls.static_fields
+                cls.static_fields.insert(f.name.clone(), f);
             } else {
-                &mut cls.instance_fields
-            }.insert(f.name.clone(), f);
+                f.set_offset(offset);
+                offset += 1;
+                cls.instance_fields.insert(f.name.clone(), f);
+            }
         }
 
-        for mi in &c.methods {
-            let m = JavaMethod::from_info(cp, mi);
+        for (i, mi) in c.methods.iter().enumerate() {
+            let m = JavaMethod::from_info(cp, c.version(), mi)
+                .map_err(|e| e.within(Structure::Method(i as u16)))?;
             cls.methods
                 .insert((m.name.clone(), m.descriptor.clone()), Method::Java(m));
         }
         for a in &c.attributes {
-            cls.attributes.insert(
-                cp[(a.name_index - 1) as usize]
-                    .utf8()
-                    .expect("attribute name pointer to invalid index"),
-                a.clone().info,
-            );
+            cls.attributes.push(Attribute::from_info(cp, a)?);
+        }
+        Ok(cls)
+    }
+
+    /// run the code of every method through the peephole optimizer
+    pub fn optimize(&mut self) {
+        for method in self.methods.values_mut() {
+            if let Method::Java(JavaMethod { code: Some(code), .. }) = method {
+                peephole::optimize(code);
+            }
         }
-        cls
     }
 
     pub fn get_method(&'a self, name: String, descriptor: String) -> Result<&'a Method, ()> {
@@ -252,3 +411,39 @@ impl<'a> JavaClass {
         self.instance_fields.get(field).ok_or(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// load `VBase.j`, then link the class at `path`, which extends it
+    fn load_subclass(path: &str) -> (Runtime, Result<*mut Class, LoadError>) {
+        let mut runtime = Runtime::new("test/jasmin/VBase.j".to_string()).unwrap();
+        let cls = JavaClass::from_filename(path, &mut runtime).unwrap();
+        let linked = runtime.add_java_class(cls);
+        (runtime, linked)
+    }
+
+    #[test]
+    fn vtable_slots() {
+        let (_runtime, sub) = load_subclass("test/jasmin/VSub.j");
+        let sub = unsafe { &*sub.unwrap() };
+        let slots = sub.vtable().iter()
+            .map(|&(class, m)| unsafe { ((*class).name(), (*m).name()) })
+            .collect::<Vec<_>>();
+        // inherited slots keep their place, and new methods go after them. VBase.c is
+        // private and VBase.d static, so neither has a slot
+        assert_eq!(slots, vec![
+            ("java/lang/Object", "equals"), ("java/lang/Object", "hashCode"),
+            ("VSub", "a"), ("VBase", "b"), ("VBase", "callC"), ("VSub", "e"),
+            ("VSub", "c"), ("VSub", "f"),
+        ]);
+    }
+
+    #[test]
+    fn final_methods_cant_be_overridden() {
+        let (_runtime, linked) = load_subclass("test/jasmin/VFinal.j");
+        let e = linked.unwrap_err();
+        assert_eq!(e.to_string(), "java.lang.VerifyError: class VFinal overrides final method VBase.b()I");
+    }
+}
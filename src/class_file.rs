@@ -1,5 +1,6 @@
 use crate::attributes::attribute_info::AttributeInfo;
-use crate::constant_pool::ConstantPoolInfo;
+use crate::class_version::ClassVersion;
+use crate::constant_pool::ConstantPool;
 use crate::field_info::FieldInfo;
 use crate::method_info::MethodInfo;
 
@@ -9,7 +10,7 @@ pub struct ClassFile {
     pub minor_version: u16,
     pub major_version: u16,
     pub constant_pool_count: u16,
-    pub constant_pool: Vec<ConstantPoolInfo>,
+    pub constant_pool: ConstantPool,
     pub access_flags: u16,
     pub this_class: u16,
     pub super_class: u16,
@@ -22,3 +23,9 @@ pub struct ClassFile {
     pub attributes_count: u16,
     pub attributes: Vec<AttributeInfo>,
 }
+
+impl ClassFile {
+    pub fn version(&self) -> ClassVersion {
+        ClassVersion::new(self.major_version, self.minor_version)
+    }
+}
@@ -1,159 +1,351 @@
 use crate::attributes::attribute_info::AttributeInfo;
+use crate::attributes::attribute_reader::AttributeParseError;
+use crate::attributes::code_reader::CodeParseError;
 use crate::class_file::ClassFile;
+use crate::class_version::{ClassVersion, MAGIC};
 use crate::constant_pool::representations::*;
-use crate::constant_pool::ConstantPoolInfo;
+use crate::constant_pool::{ConstantPool, ConstantPoolInfo};
 use crate::field_info::FieldInfo;
 use crate::method_info::MethodInfo;
-use std::fs::File;
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
 use std::io::Read;
 
-pub struct ClassReader {
-    bytes: std::io::Bytes<File>,
+/// Reads a `ClassFile` out of a buffer of class file bytes.
+///
+/// The buffer is either borrowed, for classes that are already in memory,
+/// or owned, for classes read from a file or any other `Read` source.
+pub struct ClassReader<'a> {
+    bytes: Cow<'a, [u8]>,
+    offset: usize,
 }
 
-impl ClassReader {
-    pub fn new(path: &str) -> Result<ClassReader, std::io::Error> {
-        let f: File = File::open(path)?;
-        Ok(ClassReader { bytes: f.bytes() })
+/// The part of a class file that was being read when a `ClassFormatError` happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Structure {
+    Header,
+    ConstantPool(u16),
+    AccessFlags,
+    ThisClass,
+    SuperClass,
+    Interface(u16),
+    Field(u16),
+    Method(u16),
+    Attribute(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reason {
+    /// the input ended in the middle of a structure
+    EarlyEnd,
+    BadMagic(u32),
+    /// thrown as `java.lang.UnsupportedClassVersionError`
+    UnsupportedVersion(ClassVersion),
+    InvalidConstantPoolCount,
+    InvalidConstantPoolTag(u8),
+    /// a constant pool index that is zero or past the end of the pool
+    BadIndex(u16),
+    /// a constant pool index that points at the wrong kind of entry
+    WrongKind { index: u16, expected: &'static str },
+    BadUtf8(u16),
+    BadReferenceKind(u8),
+    IllegalFlags { flags: u16, why: &'static str },
+    BadName(String),
+    BadDescriptor(String),
+    BadSuperClass,
+    /// a field or method with the same name and descriptor as an earlier one
+    Duplicate(String),
+    BadCode(CodeParseError),
+    BadAttribute(AttributeParseError),
+}
+
+/// A class file that could not be parsed, and where it went wrong.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassFormatError {
+    /// byte offset into the class file, if the error was found while reading it
+    pub offset: Option<usize>,
+    /// the structures being parsed, outermost first
+    pub context: Vec<Structure>,
+    pub reason: Reason,
+}
+
+impl ClassFormatError {
+    pub fn new(reason: Reason) -> ClassFormatError {
+        ClassFormatError {
+            offset: None,
+            context: vec![],
+            reason,
+        }
+    }
+
+    pub fn at(offset: usize, reason: Reason) -> ClassFormatError {
+        ClassFormatError {
+            offset: Some(offset),
+            context: vec![],
+            reason,
+        }
     }
 
-    fn read_n<'a>(&mut self, n: usize) -> Vec<u8> {
-        let mut b = Vec::with_capacity(n);
-        for _ in 0..n {
-            b.push(self.read_u1())
+    /// record that the error happened inside of `structure`
+    pub fn within(mut self, structure: Structure) -> ClassFormatError {
+        self.context.insert(0, structure);
+        self
+    }
+}
+
+impl Display for Structure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Structure::Header => write!(f, "header"),
+            Structure::ConstantPool(n) => write!(f, "constant pool entry {n}"),
+            Structure::AccessFlags => write!(f, "access_flags"),
+            Structure::ThisClass => write!(f, "this_class"),
+            Structure::SuperClass => write!(f, "super_class"),
+            Structure::Interface(n) => write!(f, "interface {n}"),
+            Structure::Field(n) => write!(f, "field {n}"),
+            Structure::Method(n) => write!(f, "method {n}"),
+            Structure::Attribute(name) => write!(f, "attribute {name}"),
         }
-        b
+    }
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reason::EarlyEnd => write!(f, "unexpected end of file"),
+            Reason::BadMagic(magic) => write!(f, "incompatible magic value {magic:#x}"),
+            Reason::UnsupportedVersion(v) => write!(f,
+                "class file version {v} is not supported, only versions {} to {} are",
+                ClassVersion::oldest(), ClassVersion::latest()),
+            Reason::InvalidConstantPoolCount => write!(f, "constant pool count does not match its entries"),
+            Reason::InvalidConstantPoolTag(tag) => write!(f, "invalid constant pool tag {tag}"),
+            Reason::BadIndex(index) => write!(f, "invalid constant pool index {index}"),
+            Reason::WrongKind { index, expected } =>
+                write!(f, "constant pool entry {index} is not a {expected}"),
+            Reason::BadUtf8(index) => write!(f, "constant pool entry {index} is not valid utf8"),
+            Reason::BadReferenceKind(kind) => write!(f, "invalid method handle reference kind {kind}"),
+            Reason::IllegalFlags { flags, why } => write!(f, "illegal access flags {flags:#06x}: {why}"),
+            Reason::BadName(name) => write!(f, "invalid name {name:?}"),
+            Reason::BadDescriptor(descriptor) => write!(f, "invalid descriptor {descriptor:?}"),
+            Reason::BadSuperClass => write!(f, "invalid super class"),
+            Reason::Duplicate(member) => write!(f, "duplicate {member}"),
+            Reason::BadCode(e) => write!(f, "bad code: {e:?}"),
+            Reason::BadAttribute(e) => write!(f, "bad attribute: {e:?}"),
+        }
+    }
+}
+
+impl Display for ClassFormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.reason)?;
+        for (i, s) in self.context.iter().enumerate() {
+            f.write_str(if i == 0 { " in " } else { ", " })?;
+            write!(f, "{s}")?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, " (at byte offset {offset:#x})")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ClassFormatError {}
+
+type Result<T> = std::result::Result<T, ClassFormatError>;
+
+impl<'a> ClassReader<'a> {
+    pub fn new(path: &str) -> std::result::Result<ClassReader<'static>, std::io::Error> {
+        Ok(ClassReader::from_vec(std::fs::read(path)?))
+    }
+
+    /// read a class from any source, such as a jar entry or a socket
+    pub fn from_reader<R: Read>(mut reader: R) -> std::result::Result<ClassReader<'static>, std::io::Error> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        Ok(ClassReader::from_vec(bytes))
+    }
+
+    pub fn from_vec(bytes: Vec<u8>) -> ClassReader<'static> {
+        ClassReader { bytes: Cow::Owned(bytes), offset: 0 }
+    }
+
+    pub fn from_bytes(bytes: &'a [u8]) -> ClassReader<'a> {
+        ClassReader { bytes: Cow::Borrowed(bytes), offset: 0 }
+    }
+
+    fn read_n(&mut self, n: usize) -> Result<Vec<u8>> {
+        let b = self.bytes.get(self.offset..self.offset + n)
+            .ok_or(ClassFormatError::at(self.offset, Reason::EarlyEnd))?
+            .to_vec();
+        self.offset += n;
+        Ok(b)
     }
 
-    fn read_u1(&mut self) -> u8 {
-        self.bytes.next().unwrap().expect("read fail")
+    fn read_u1(&mut self) -> Result<u8> {
+        let b = *self.bytes.get(self.offset)
+            .ok_or(ClassFormatError::at(self.offset, Reason::EarlyEnd))?;
+        self.offset += 1;
+        Ok(b)
     }
 
-    fn read_u2(&mut self) -> u16 {
-        let hi = self.read_u1() as u16;
-        let lo = self.read_u1() as u16;
-        hi << 8 | lo
+    fn read_u2(&mut self) -> Result<u16> {
+        let b = self.bytes.get(self.offset..self.offset + 2)
+            .ok_or(ClassFormatError::at(self.offset, Reason::EarlyEnd))?;
+        self.offset += 2;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
     }
 
-    fn read_u4(&mut self) -> u32 {
-        let hi = self.read_u2() as u32;
-        let lo = self.read_u2() as u32;
-        hi << 16 | lo
+    fn read_u4(&mut self) -> Result<u32> {
+        let b = self.bytes.get(self.offset..self.offset + 4)
+            .ok_or(ClassFormatError::at(self.offset, Reason::EarlyEnd))?;
+        self.offset += 4;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
     }
 
-    fn read_cpinfo(&mut self) -> ConstantPoolInfo {
-        match self.read_u1() {
+    fn read_cpinfo(&mut self) -> Result<ConstantPoolInfo> {
+        let start = self.offset;
+        Ok(match self.read_u1()? {
             7 => ConstantPoolInfo::Class(Class {
-                name_index: self.read_u2(),
+                name_index: self.read_u2()?,
             }),
             9 => ConstantPoolInfo::Fieldref(Fieldref {
-                class_index: self.read_u2(),
-                name_and_type_index: self.read_u2(),
+                class_index: self.read_u2()?,
+                name_and_type_index: self.read_u2()?,
             }),
             10 => ConstantPoolInfo::Methodref(Methodref {
-                class_index: self.read_u2(),
-                name_and_type_index: self.read_u2(),
+                class_index: self.read_u2()?,
+                name_and_type_index: self.read_u2()?,
             }),
             11 => ConstantPoolInfo::InterfaceMethodref(InterfaceMethodref {
-                class_index: self.read_u2(),
-                name_and_type_index: self.read_u2(),
+                class_index: self.read_u2()?,
+                name_and_type_index: self.read_u2()?,
             }),
             8 => ConstantPoolInfo::JString(JString {
-                string_index: self.read_u2(),
+                string_index: self.read_u2()?,
             }),
             3 => ConstantPoolInfo::Integer(Integer {
-                bytes: self.read_u4(),
+                bytes: self.read_u4()?,
             }),
             4 => ConstantPoolInfo::Float(Float {
-                bytes: self.read_u4(),
+                bytes: self.read_u4()?,
             }),
             5 => ConstantPoolInfo::Long(Long {
-                high_bytes: self.read_u4(),
-                low_bytes: self.read_u4(),
+                high_bytes: self.read_u4()?,
+                low_bytes: self.read_u4()?,
             }),
             6 => ConstantPoolInfo::Double(Double {
-                high_bytes: self.read_u4(),
-                low_bytes: self.read_u4(),
+                high_bytes: self.read_u4()?,
+                low_bytes: self.read_u4()?,
             }),
             12 => ConstantPoolInfo::NameAndType(NameAndType {
-                name_index: self.read_u2(),
-                descriptor_index: self.read_u2(),
+                name_index: self.read_u2()?,
+                descriptor_index: self.read_u2()?,
             }),
             1 => {
-                let l = self.read_u2();
+                let l = self.read_u2()?;
                 ConstantPoolInfo::Utf8(Utf8 {
                     length: l,
-                    bytes: self.read_n(l as usize),
+                    bytes: self.read_n(l as usize)?,
                 })
             }
             15 => ConstantPoolInfo::MethodHandle(MethodHandle {
-                reference_kind: self.read_u1(),
-                reference_index: self.read_u2(),
+                reference_kind: self.read_u1()?,
+                reference_index: self.read_u2()?,
             }),
             16 => ConstantPoolInfo::MethodType(MethodType {
-                descriptor_index: self.read_u2(),
+                descriptor_index: self.read_u2()?,
             }),
             18 => ConstantPoolInfo::InvokeDynamic(InvokeDynamic {
-                bootstrap_method_attr_index: self.read_u2(),
-                name_and_type_index: self.read_u2(),
+                bootstrap_method_attr_index: self.read_u2()?,
+                name_and_type_index: self.read_u2()?,
             }),
-            x => panic!("invalid constant pool tag {}", x),
-        }
+            17 => ConstantPoolInfo::Dynamic(Dynamic {
+                bootstrap_method_attr_index: self.read_u2()?,
+                name_and_type_index: self.read_u2()?,
+            }),
+            19 => ConstantPoolInfo::Module(Module {
+                name_index: self.read_u2()?,
+            }),
+            20 => ConstantPoolInfo::Package(Package {
+                name_index: self.read_u2()?,
+            }),
+            x => return Err(ClassFormatError::at(start, Reason::InvalidConstantPoolTag(x))),
+        })
     }
 
-    fn read_fieldinfo(&mut self) -> FieldInfo {
+    fn read_fieldinfo(&mut self, cp: &ConstantPool) -> Result<FieldInfo> {
         let mut f = FieldInfo {
-            access_flags: self.read_u2(),
-            name_index: self.read_u2(),
-            descriptor_index: self.read_u2(),
-            attributes_count: self.read_u2(),
+            access_flags: self.read_u2()?,
+            name_index: self.read_u2()?,
+            descriptor_index: self.read_u2()?,
+            attributes_count: self.read_u2()?,
             attributes: vec![],
         };
 
         for _ in 0..f.attributes_count {
-            f.attributes.push(self.read_attributeinfo())
+            f.attributes.push(self.read_attributeinfo(cp)?)
         }
 
-        f
+        Ok(f)
     }
 
-    fn read_methodinfo(&mut self) -> MethodInfo {
+    fn read_methodinfo(&mut self, cp: &ConstantPool) -> Result<MethodInfo> {
         let mut m = MethodInfo {
-            access_flags: self.read_u2(),
-            name_index: self.read_u2(),
-            descriptor_index: self.read_u2(),
-            attributes_count: self.read_u2(),
+            access_flags: self.read_u2()?,
+            name_index: self.read_u2()?,
+            descriptor_index: self.read_u2()?,
+            attributes_count: self.read_u2()?,
             attributes: vec![],
         };
 
         for _ in 0..m.attributes_count {
-            m.attributes.push(self.read_attributeinfo())
+            m.attributes.push(self.read_attributeinfo(cp)?)
         }
 
-        m
+        Ok(m)
     }
 
-    fn read_attributeinfo(&mut self) -> AttributeInfo {
-        let mut a = AttributeInfo {
-            name_index: self.read_u2(),
-            attribute_length: self.read_u4(),
-            info: vec![],
-        };
+    fn read_attributeinfo(&mut self, cp: &ConstantPool) -> Result<AttributeInfo> {
+        let name_index = self.read_u2()?;
 
-        for _ in 0..a.attribute_length {
-            a.info.push(self.read_u1())
-        }
+        // the name is only used to say where we were if the attribute is cut short
+        let name = cp.utf8(name_index).unwrap_or_else(|_| format!("#{name_index}"));
+
+        let read_body = |reader: &mut Self| -> Result<AttributeInfo> {
+            let attribute_length = reader.read_u4()?;
+            Ok(AttributeInfo {
+                name_index,
+                attribute_length,
+                info: reader.read_n(attribute_length as usize)?,
+            })
+        };
 
-        a
+        read_body(self).map_err(|e| e.within(Structure::Attribute(name)))
     }
 
-    pub fn read_classfile(&mut self) -> ClassFile {
+    pub fn read_classfile(&mut self) -> Result<ClassFile> {
+        let header = |reader: &mut Self| -> Result<(u32, u16, u16, u16)> {
+            Ok((reader.read_u4()?, reader.read_u2()?, reader.read_u2()?, reader.read_u2()?))
+        };
+        let (magic, minor_version, major_version, constant_pool_count) = header(self)
+            .map_err(|e| e.within(Structure::Header))?;
+
+        if magic != MAGIC {
+            return Err(ClassFormatError::at(0, Reason::BadMagic(magic))
+                .within(Structure::Header));
+        }
+        let version = ClassVersion::new(major_version, minor_version);
+        if !version.is_supported() {
+            return Err(ClassFormatError::at(4, Reason::UnsupportedVersion(version))
+                .within(Structure::Header));
+        }
+
         let mut cf = ClassFile {
-            magic: self.read_u4(),
-            minor_version: self.read_u2(),
-            major_version: self.read_u2(),
-            constant_pool_count: self.read_u2(),
-            constant_pool: vec![],
+            magic,
+            minor_version,
+            major_version,
+            constant_pool_count,
+            constant_pool: ConstantPool::new(),
             access_flags: 0,
             this_class: 0,
             super_class: 0,
@@ -167,29 +359,145 @@ impl ClassReader {
             attributes: vec![],
         };
 
-        for _ in 0..(cf.constant_pool_count - 1) {
-            let c = self.read_cpinfo();
-            cf.constant_pool.push(c)
+        if cf.constant_pool_count == 0 {
+            return Err(ClassFormatError::at(self.offset - 2, Reason::InvalidConstantPoolCount)
+                .within(Structure::Header));
         }
-        cf.access_flags = self.read_u2();
-        cf.this_class = self.read_u2();
-        cf.super_class = self.read_u2();
-        cf.interfaces_count = self.read_u2();
-        for _ in 0..cf.interfaces_count {
-            cf.interfaces.push(self.read_u2())
+
+        while cf.constant_pool.count() < cf.constant_pool_count as usize {
+            let i = cf.constant_pool.count() as u16;
+            let c = self.read_cpinfo()
+                .map_err(|e| e.within(Structure::ConstantPool(i)))?;
+            cf.constant_pool.push(c);
+        }
+        // a long or double in the last slot would spill past the end of the pool
+        if cf.constant_pool.count() != cf.constant_pool_count as usize {
+            return Err(ClassFormatError::at(self.offset, Reason::InvalidConstantPoolCount));
         }
-        cf.fields_count = self.read_u2();
-        for _ in 0..cf.fields_count {
-            cf.fields.push(self.read_fieldinfo())
+        cf.access_flags = self.read_u2()?;
+        cf.this_class = self.read_u2()?;
+        cf.super_class = self.read_u2()?;
+        cf.interfaces_count = self.read_u2()?;
+        for i in 0..cf.interfaces_count {
+            let interface = self.read_u2()
+                .map_err(|e| e.within(Structure::Interface(i)))?;
+            cf.interfaces.push(interface)
         }
-        cf.methods_count = self.read_u2();
-        for _ in 0..cf.methods_count {
-            cf.methods.push(self.read_methodinfo())
+        cf.fields_count = self.read_u2()?;
+        for i in 0..cf.fields_count {
+            let field = self.read_fieldinfo(&cf.constant_pool)
+                .map_err(|e| e.within(Structure::Field(i)))?;
+            cf.fields.push(field)
         }
-        cf.attributes_count = self.read_u2();
+        cf.methods_count = self.read_u2()?;
+        for i in 0..cf.methods_count {
+            let method = self.read_methodinfo(&cf.constant_pool)
+                .map_err(|e| e.within(Structure::Method(i)))?;
+            cf.methods.push(method)
+        }
+        cf.attributes_count = self.read_u2()?;
         for _ in 0..cf.attributes_count {
-            cf.attributes.push(self.read_attributeinfo())
+            let attribute = self.read_attributeinfo(&cf.constant_pool)?;
+            cf.attributes.push(attribute)
         }
-        cf
+        Ok(cf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `class A extends java/lang/Object {}`, with no members
+    const EMPTY_CLASS: &[u8] = &[
+        0xca, 0xfe, 0xba, 0xbe, 0x00, 0x00, 0x00, 0x34,
+        0x00, 0x05,
+        0x01, 0x00, 0x01, b'A',
+        0x07, 0x00, 0x01,
+        0x01, 0x00, 0x10, b'j', b'a', b'v', b'a', b'/', b'l', b'a', b'n', b'g', b'/',
+        b'O', b'b', b'j', b'e', b'c', b't',
+        0x07, 0x00, 0x03,
+        0x00, 0x21, 0x00, 0x02, 0x00, 0x04,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn read_from_bytes() {
+        let cf = ClassReader::from_bytes(EMPTY_CLASS).read_classfile().unwrap();
+        assert_eq!(cf.major_version, 52);
+        assert_eq!(cf.constant_pool.count(), 5);
+        assert_eq!(cf.constant_pool.class_name(cf.this_class).unwrap(), "A");
+        assert_eq!(cf.this_class, 2);
+        assert_eq!(cf.super_class, 4);
+
+        let from_reader = ClassReader::from_reader(std::io::Cursor::new(EMPTY_CLASS))
+            .unwrap()
+            .read_classfile()
+            .unwrap();
+        assert_eq!(from_reader.constant_pool.count(), 5);
+    }
+
+    #[test]
+    fn module_and_dynamic_entries() {
+        let bytes = [
+            0xca, 0xfe, 0xba, 0xbe, 0x00, 0x00, 0x00, 0x3d,
+            0x00, 0x06,
+            0x01, 0x00, 0x01, b'm',
+            0x13, 0x00, 0x01,
+            0x14, 0x00, 0x01,
+            0x0c, 0x00, 0x01, 0x00, 0x01,
+            0x11, 0x00, 0x00, 0x00, 0x04,
+            0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let cf = ClassReader::from_bytes(&bytes).read_classfile().unwrap();
+        let cp = &cf.constant_pool;
+        assert_eq!(cp.module_name(2).unwrap(), "m");
+        assert_eq!(cp.package_name(3).unwrap(), "m");
+        assert_eq!(cp.dynamic(5).unwrap().name_and_type_index, 4);
+        assert_eq!(cp.get(5).unwrap().clone().tag(), 17);
+    }
+
+    #[test]
+    fn truncated() {
+        let e = ClassReader::from_bytes(&EMPTY_CLASS[..20]).read_classfile().unwrap_err();
+        assert_eq!(e.reason, Reason::EarlyEnd);
+        assert_eq!(e.offset, Some(20));
+        assert_eq!(e.context, vec![Structure::ConstantPool(3)]);
+    }
+
+    #[test]
+    fn truncated_code() {
+        use crate::attributes::code_reader::CodeReader;
+
+        // a single return, but no exception table or attributes after it
+        let cf = ClassReader::from_bytes(EMPTY_CLASS).read_classfile().unwrap();
+        let bytes = [0, 1, 0, 1, 0, 0, 0, 1, 0xb1];
+        let e = CodeReader::new(&bytes, &cf.constant_pool, ClassVersion::new(52, 0)).read_code().unwrap_err();
+        assert_eq!(e, CodeParseError::EarlyEnd("exception table length".to_string()));
+    }
+
+    #[test]
+    fn bad_header() {
+        let mut bytes = EMPTY_CLASS.to_vec();
+        bytes[3] = 0xbf;
+        let e = ClassReader::from_bytes(&bytes).read_classfile().unwrap_err();
+        assert_eq!(e.reason, Reason::BadMagic(0xcafebabf));
+
+        let mut bytes = EMPTY_CLASS.to_vec();
+        bytes[7] = 0xff;
+        let e = ClassReader::from_bytes(&bytes).read_classfile().unwrap_err();
+        assert_eq!(e.reason, Reason::UnsupportedVersion(ClassVersion::new(0xff, 0)));
+        assert_eq!(e.offset, Some(4));
+    }
+
+    #[test]
+    fn invalid_tag() {
+        let mut bytes = EMPTY_CLASS.to_vec();
+        bytes[14] = 42;
+        let e = ClassReader::from_bytes(&bytes).read_classfile().unwrap_err();
+        assert_eq!(e.reason, Reason::InvalidConstantPoolTag(42));
+        assert_eq!(e.offset, Some(14));
+        assert_eq!(e.context, vec![Structure::ConstantPool(2)]);
     }
 }
@@ -0,0 +1,133 @@
+//! Class file versions this VM can load, and the behaviour that depends on them.
+
+/// the first four bytes of every class file
+pub const MAGIC: u32 = 0xcafe_babe;
+
+/// the minor version of a class file that uses preview features (JVMS §4.1)
+pub const PREVIEW_MINOR_VERSION: u16 = 0xffff;
+
+/// every supported major version, with the java release that introduced it
+pub const SUPPORTED_VERSIONS: &[(u16, &str)] = &[
+    (45, "1.1"),
+    (46, "1.2"),
+    (47, "1.3"),
+    (48, "1.4"),
+    (49, "5"),
+    (50, "6"),
+    (51, "7"),
+    (52, "8"),
+    (53, "9"),
+    (54, "10"),
+    (55, "11"),
+    (56, "12"),
+    (57, "13"),
+    (58, "14"),
+    (59, "15"),
+    (60, "16"),
+    (61, "17"),
+    (62, "18"),
+    (63, "19"),
+    (64, "20"),
+    (65, "21"),
+    (66, "22"),
+    (67, "23"),
+    (68, "24"),
+    (69, "25"),
+    (70, "26"),
+    (71, "27"),
+];
+
+/// the first major version where the minor version must be 0 or `PREVIEW_MINOR_VERSION`
+const STRICT_MINOR_VERSIONS: u16 = 56;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ClassVersion {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl ClassVersion {
+    pub const fn new(major: u16, minor: u16) -> ClassVersion {
+        ClassVersion { major, minor }
+    }
+
+    pub fn oldest() -> ClassVersion {
+        ClassVersion::new(SUPPORTED_VERSIONS[0].0, 0)
+    }
+
+    pub fn latest() -> ClassVersion {
+        ClassVersion::new(SUPPORTED_VERSIONS[SUPPORTED_VERSIONS.len() - 1].0, 0)
+    }
+
+    /// the java release this version belongs to, if we know about it
+    pub fn release(&self) -> Option<&'static str> {
+        SUPPORTED_VERSIONS.iter()
+            .find(|(major, _)| *major == self.major)
+            .map(|(_, release)| *release)
+    }
+
+    pub fn is_preview(&self) -> bool {
+        self.major >= STRICT_MINOR_VERSIONS && self.minor == PREVIEW_MINOR_VERSION
+    }
+
+    /// whether a class file with this version may be loaded.
+    /// preview features are only allowed for the latest release, like in the reference implementation
+    pub fn is_supported(&self) -> bool {
+        if self.release().is_none() {
+            return false;
+        }
+        if self.major < STRICT_MINOR_VERSIONS {
+            return true;
+        }
+        match self.minor {
+            0 => true,
+            PREVIEW_MINOR_VERSION => self.major == ClassVersion::latest().major,
+            _ => false,
+        }
+    }
+
+    /// `jsr`, `jsr_w` and `ret` may not appear in class files from java 7 on
+    pub fn allows_subroutines(&self) -> bool {
+        self.major < 51
+    }
+
+    /// `invokedynamic` was added in java 7
+    pub fn allows_invokedynamic(&self) -> bool {
+        self.major >= 51
+    }
+
+    /// whether methods are verified by type checking against a `StackMapTable`
+    /// instead of by type inference
+    pub fn uses_type_checking(&self) -> bool {
+        self.major >= 50
+    }
+
+    /// java 6 classes may fall back to type inference when type checking fails
+    pub fn allows_inference_fallback(&self) -> bool {
+        self.major == 50
+    }
+}
+
+impl std::fmt::Display for ClassVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supported() {
+        assert!(ClassVersion::new(45, 3).is_supported());
+        assert!(ClassVersion::new(52, 0).is_supported());
+        assert!(ClassVersion::latest().is_supported());
+        assert!(!ClassVersion::new(44, 0).is_supported());
+        assert!(!ClassVersion::new(ClassVersion::latest().major + 1, 0).is_supported());
+        // java 12 and up only allow minor versions 0 and 0xffff
+        assert!(!ClassVersion::new(61, 1).is_supported());
+        assert!(!ClassVersion::new(61, PREVIEW_MINOR_VERSION).is_supported());
+        assert!(ClassVersion::new(ClassVersion::latest().major, PREVIEW_MINOR_VERSION).is_supported());
+    }
+}
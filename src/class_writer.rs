@@ -0,0 +1,201 @@
+use crate::attributes::attribute_info::AttributeInfo;
+use crate::class_file::ClassFile;
+use crate::constant_pool::ConstantPoolInfo;
+use crate::field_info::FieldInfo;
+use crate::method_info::MethodInfo;
+
+/// Serializes a `ClassFile` back into class file bytes.
+///
+/// Counts and lengths are taken from the contents rather than the `*_count`
+/// fields, so a `ClassFile` can be edited without keeping them in sync.
+pub struct ClassWriter {
+    bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClassWriteError {
+    /// a table has more entries than its count can hold
+    TooMany(&'static str),
+    /// an attribute or utf8 constant is longer than its length can hold
+    TooLong(&'static str),
+}
+
+type Result<T> = std::result::Result<T, ClassWriteError>;
+
+impl ClassWriter {
+    pub fn new() -> ClassWriter {
+        ClassWriter { bytes: vec![] }
+    }
+
+    fn write_u1(&mut self, x: u8) {
+        self.bytes.push(x)
+    }
+
+    fn write_u2(&mut self, x: u16) {
+        self.bytes.extend_from_slice(&x.to_be_bytes())
+    }
+
+    fn write_u4(&mut self, x: u32) {
+        self.bytes.extend_from_slice(&x.to_be_bytes())
+    }
+
+    fn write_count(&mut self, n: usize, what: &'static str) -> Result<()> {
+        self.write_u2(u16::try_from(n).map_err(|_| ClassWriteError::TooMany(what))?);
+        Ok(())
+    }
+
+    fn write_cpinfo(&mut self, c: &ConstantPoolInfo) -> Result<()> {
+        self.write_u1(c.clone().tag());
+        match c {
+            ConstantPoolInfo::Class(c) => self.write_u2(c.name_index),
+            ConstantPoolInfo::Fieldref(r) => {
+                self.write_u2(r.class_index);
+                self.write_u2(r.name_and_type_index);
+            }
+            ConstantPoolInfo::Methodref(r) => {
+                self.write_u2(r.class_index);
+                self.write_u2(r.name_and_type_index);
+            }
+            ConstantPoolInfo::InterfaceMethodref(r) => {
+                self.write_u2(r.class_index);
+                self.write_u2(r.name_and_type_index);
+            }
+            ConstantPoolInfo::JString(s) => self.write_u2(s.string_index),
+            ConstantPoolInfo::Integer(i) => self.write_u4(i.bytes),
+            ConstantPoolInfo::Float(f) => self.write_u4(f.bytes),
+            ConstantPoolInfo::Long(l) => {
+                self.write_u4(l.high_bytes);
+                self.write_u4(l.low_bytes);
+            }
+            ConstantPoolInfo::Double(d) => {
+                self.write_u4(d.high_bytes);
+                self.write_u4(d.low_bytes);
+            }
+            ConstantPoolInfo::NameAndType(nt) => {
+                self.write_u2(nt.name_index);
+                self.write_u2(nt.descriptor_index);
+            }
+            ConstantPoolInfo::Utf8(u) => {
+                let length = u16::try_from(u.bytes.len())
+                    .map_err(|_| ClassWriteError::TooLong("utf8 constant"))?;
+                self.write_u2(length);
+                self.bytes.extend_from_slice(&u.bytes);
+            }
+            ConstantPoolInfo::MethodHandle(mh) => {
+                self.write_u1(mh.reference_kind);
+                self.write_u2(mh.reference_index);
+            }
+            ConstantPoolInfo::MethodType(mt) => self.write_u2(mt.descriptor_index),
+            ConstantPoolInfo::InvokeDynamic(id) => {
+                self.write_u2(id.bootstrap_method_attr_index);
+                self.write_u2(id.name_and_type_index);
+            }
+            ConstantPoolInfo::Dynamic(d) => {
+                self.write_u2(d.bootstrap_method_attr_index);
+                self.write_u2(d.name_and_type_index);
+            }
+            ConstantPoolInfo::Module(m) => self.write_u2(m.name_index),
+            ConstantPoolInfo::Package(p) => self.write_u2(p.name_index),
+        }
+        Ok(())
+    }
+
+    fn write_fieldinfo(&mut self, f: &FieldInfo) -> Result<()> {
+        self.write_u2(f.access_flags);
+        self.write_u2(f.name_index);
+        self.write_u2(f.descriptor_index);
+        self.write_attributes(&f.attributes)
+    }
+
+    fn write_methodinfo(&mut self, m: &MethodInfo) -> Result<()> {
+        self.write_u2(m.access_flags);
+        self.write_u2(m.name_index);
+        self.write_u2(m.descriptor_index);
+        self.write_attributes(&m.attributes)
+    }
+
+    fn write_attributes(&mut self, attributes: &[AttributeInfo]) -> Result<()> {
+        self.write_count(attributes.len(), "attributes")?;
+        for a in attributes {
+            self.write_u2(a.name_index);
+            let length = u32::try_from(a.info.len())
+                .map_err(|_| ClassWriteError::TooLong("attribute"))?;
+            self.write_u4(length);
+            self.bytes.extend_from_slice(&a.info);
+        }
+        Ok(())
+    }
+
+    pub fn write_classfile(mut self, cf: &ClassFile) -> Result<Vec<u8>> {
+        self.write_u4(cf.magic);
+        self.write_u2(cf.minor_version);
+        self.write_u2(cf.major_version);
+
+        self.write_count(cf.constant_pool.count(), "constant pool entries")?;
+        for (_, c) in cf.constant_pool.iter() {
+            self.write_cpinfo(c)?;
+        }
+
+        self.write_u2(cf.access_flags);
+        self.write_u2(cf.this_class);
+        self.write_u2(cf.super_class);
+
+        self.write_count(cf.interfaces.len(), "interfaces")?;
+        for i in &cf.interfaces {
+            self.write_u2(*i);
+        }
+        self.write_count(cf.fields.len(), "fields")?;
+        for f in &cf.fields {
+            self.write_fieldinfo(f)?;
+        }
+        self.write_count(cf.methods.len(), "methods")?;
+        for m in &cf.methods {
+            self.write_methodinfo(m)?;
+        }
+        self.write_attributes(&cf.attributes)?;
+
+        Ok(self.bytes)
+    }
+}
+
+impl Default for ClassWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::class_reader::ClassReader;
+
+    // `class A { static final long X = 1L; }`, with a long constant to check slot handling
+    const CLASS: &[u8] = &[
+        0xca, 0xfe, 0xba, 0xbe, 0x00, 0x00, 0x00, 0x34,
+        0x00, 0x0a,
+        0x01, 0x00, 0x01, b'A',
+        0x07, 0x00, 0x01,
+        0x01, 0x00, 0x10, b'j', b'a', b'v', b'a', b'/', b'l', b'a', b'n', b'g', b'/',
+        b'O', b'b', b'j', b'e', b'c', b't',
+        0x07, 0x00, 0x03,
+        0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+        0x01, 0x00, 0x01, b'X',
+        0x01, 0x00, 0x01, b'J',
+        0x01, 0x00, 0x0d, b'C', b'o', b'n', b's', b't', b'a', b'n', b't',
+        b'V', b'a', b'l', b'u', b'e',
+        0x00, 0x20, 0x00, 0x02, 0x00, 0x04,
+        0x00, 0x00,
+        0x00, 0x01,
+        0x00, 0x18, 0x00, 0x07, 0x00, 0x08, 0x00, 0x01,
+        0x00, 0x09, 0x00, 0x00, 0x00, 0x02, 0x00, 0x05,
+        0x00, 0x00,
+        0x00, 0x00,
+    ];
+
+    #[test]
+    fn round_trip() {
+        let cf = ClassReader::from_bytes(CLASS).read_classfile().unwrap();
+        assert_eq!(cf.constant_pool.utf8(7).unwrap(), "X");
+        assert_eq!(ClassWriter::new().write_classfile(&cf).unwrap(), CLASS);
+    }
+}
@@ -1,4 +1,6 @@
 use self::representations::*;
+use crate::class_reader::{ClassFormatError, Reason};
+use crate::mutf8;
 
 #[derive(Debug, Clone)]
 pub enum ConstantPoolInfo {
@@ -16,10 +18,14 @@ pub enum ConstantPoolInfo {
     MethodHandle(MethodHandle),
     MethodType(MethodType),
     InvokeDynamic(InvokeDynamic),
+    Dynamic(Dynamic),
+    Module(Module),
+    Package(Package),
 }
 
 pub mod representations {
-    use std::fmt::{Debug, Formatter, Write};
+    use std::fmt::{Debug, Formatter};
+    use crate::mutf8;
 
     #[derive(Debug, Clone)]
     pub struct Class {
@@ -87,12 +93,38 @@ pub mod representations {
         pub name_and_type_index: u16,
     }
 
+    #[derive(Debug, Clone)]
+    pub struct Dynamic {
+        pub bootstrap_method_attr_index: u16,
+        pub name_and_type_index: u16,
+    }
+    #[derive(Debug, Clone)]
+    pub struct Module {
+        pub name_index: u16,
+    }
+    #[derive(Debug, Clone)]
+    pub struct Package {
+        pub name_index: u16,
+    }
+
+    impl Utf8 {
+        /// the contents as a rust string
+        pub fn decode(&self) -> Result<String, mutf8::DecodeError> {
+            mutf8::decode(&self.bytes)
+        }
+
+        /// the contents as UTF-16 code units, the way a `java/lang/String` holds them
+        pub fn utf16(&self) -> Result<Vec<u16>, mutf8::DecodeError> {
+            mutf8::decode_utf16(&self.bytes)
+        }
+    }
+
     impl Debug for Utf8 {
         fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-            f.write_char('"')?;
-            f.write_str(std::str::from_utf8(self.bytes.as_slice()).expect("bad utf8"))?;
-            f.write_char('"')?;
-            Ok(())
+            match self.decode() {
+                Ok(s) => <String as Debug>::fmt(&s, f),
+                Err(_) => write!(f, "<bad modified utf8 {:?}>", self.bytes),
+            }
         }
     }
 
@@ -111,18 +143,12 @@ pub mod representations {
 impl ConstantPoolInfo {
     pub fn utf8(&self) -> Option<String> {
         if let ConstantPoolInfo::Utf8(u) = self {
-            String::from_utf8(Vec::from(u.bytes.as_slice())).ok()
+            u.decode().ok()
         } else {
             None
         }
     }
 
-    pub fn class_name(&self, cp: &Vec<ConstantPoolInfo>) -> String {
-        cp[(self.class().unwrap().name_index - 1) as usize]
-            .utf8()
-            .unwrap()
-    }
-
     pub fn class(&self) -> Option<Class> {
         if let ConstantPoolInfo::Class(c) = self {
             Some(c.clone())
@@ -171,6 +197,38 @@ impl ConstantPoolInfo {
         }
     }
 
+    pub fn invoke_dynamic(&self) -> Option<InvokeDynamic> {
+        if let ConstantPoolInfo::InvokeDynamic(id) = self {
+            Some(id.clone())
+        } else {
+            None
+        }
+    }
+
+    pub fn dynamic(&self) -> Option<Dynamic> {
+        if let ConstantPoolInfo::Dynamic(d) = self {
+            Some(d.clone())
+        } else {
+            None
+        }
+    }
+
+    pub fn module(&self) -> Option<Module> {
+        if let ConstantPoolInfo::Module(m) = self {
+            Some(m.clone())
+        } else {
+            None
+        }
+    }
+
+    pub fn package(&self) -> Option<Package> {
+        if let ConstantPoolInfo::Package(p) = self {
+            Some(p.clone())
+        } else {
+            None
+        }
+    }
+
     pub fn tag(self) -> u8 {
         use self::ConstantPoolInfo::*;
 
@@ -189,6 +247,157 @@ impl ConstantPoolInfo {
             MethodHandle(_) => 15,
             MethodType(_) => 16,
             InvokeDynamic(_) => 18,
+            Dynamic(_) => 17,
+            Module(_) => 19,
+            Package(_) => 20,
         }
     }
 }
+
+/// The constant pool of a class, indexed the way the class file indexes it.
+///
+/// Indices start at 1, and `CONSTANT_Long` and `CONSTANT_Double` entries take
+/// up two slots, the second of which is unusable.
+#[derive(Debug, Clone, Default)]
+pub struct ConstantPool {
+    // slot 0 and the upper halves of longs and doubles are `None`
+    entries: Vec<Option<ConstantPoolInfo>>,
+}
+
+impl ConstantPool {
+    pub fn new() -> ConstantPool {
+        ConstantPool { entries: vec![None] }
+    }
+
+    /// add an entry in the next free slot, returning its index
+    pub fn push(&mut self, info: ConstantPoolInfo) -> u16 {
+        let index = self.entries.len() as u16;
+        let wide = matches!(info, ConstantPoolInfo::Long(_) | ConstantPoolInfo::Double(_));
+        self.entries.push(Some(info));
+        if wide {
+            self.entries.push(None);
+        }
+        index
+    }
+
+    /// the index of a `CONSTANT_Utf8` entry holding `s`, adding one if there isn't any
+    pub fn utf8_index(&mut self, s: &str) -> u16 {
+        let bytes = mutf8::encode(s);
+        let existing = self.iter().find(|(_, e)| match e {
+            ConstantPoolInfo::Utf8(u) => u.bytes == bytes,
+            _ => false,
+        });
+        match existing {
+            Some((index, _)) => index,
+            None => self.push(ConstantPoolInfo::Utf8(Utf8 { length: bytes.len() as u16, bytes })),
+        }
+    }
+
+    /// the number of slots used, including slot 0.
+    /// this is what the class file calls `constant_pool_count`
+    pub fn count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// every usable entry along with its index
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &ConstantPoolInfo)> {
+        self.entries.iter()
+            .enumerate()
+            .filter_map(|(i, e)| Some((i as u16, e.as_ref()?)))
+    }
+
+    pub fn get(&self, index: u16) -> Result<&ConstantPoolInfo, ClassFormatError> {
+        self.entries.get(index as usize)
+            .and_then(Option::as_ref)
+            .ok_or(ClassFormatError::new(Reason::BadIndex(index)))
+    }
+
+    fn wrong_kind(index: u16, expected: &'static str) -> ClassFormatError {
+        ClassFormatError::new(Reason::WrongKind { index, expected })
+    }
+
+    /// the string stored in the `CONSTANT_Utf8` entry at `index`
+    pub fn utf8(&self, index: u16) -> Result<String, ClassFormatError> {
+        match self.get(index)? {
+            e @ ConstantPoolInfo::Utf8(_) => e.utf8().ok_or(ClassFormatError::new(Reason::BadUtf8(index))),
+            _ => Err(Self::wrong_kind(index, "Utf8")),
+        }
+    }
+
+    /// the UTF-16 contents of the `CONSTANT_String` entry at `index`
+    pub fn string(&self, index: u16) -> Result<Vec<u16>, ClassFormatError> {
+        let string_index = match self.get(index)? {
+            ConstantPoolInfo::JString(s) => s.string_index,
+            _ => return Err(Self::wrong_kind(index, "String")),
+        };
+        match self.get(string_index)? {
+            ConstantPoolInfo::Utf8(u) => u.utf16()
+                .map_err(|_| ClassFormatError::new(Reason::BadUtf8(string_index))),
+            _ => Err(Self::wrong_kind(string_index, "Utf8")),
+        }
+    }
+
+    pub fn class(&self, index: u16) -> Result<Class, ClassFormatError> {
+        self.get(index)?.class().ok_or(Self::wrong_kind(index, "Class"))
+    }
+
+    /// the name of the `CONSTANT_Class` entry at `index`
+    pub fn class_name(&self, index: u16) -> Result<String, ClassFormatError> {
+        self.utf8(self.class(index)?.name_index)
+    }
+
+    pub fn name_and_type(&self, index: u16) -> Result<NameAndType, ClassFormatError> {
+        self.get(index)?.name_and_type().ok_or(Self::wrong_kind(index, "NameAndType"))
+    }
+
+    pub fn dynamic(&self, index: u16) -> Result<Dynamic, ClassFormatError> {
+        self.get(index)?.dynamic().ok_or(Self::wrong_kind(index, "Dynamic"))
+    }
+
+    /// the name of the `CONSTANT_Module` entry at `index`
+    pub fn module_name(&self, index: u16) -> Result<String, ClassFormatError> {
+        let module = self.get(index)?.module().ok_or(Self::wrong_kind(index, "Module"))?;
+        self.utf8(module.name_index)
+    }
+
+    /// the name of the `CONSTANT_Package` entry at `index`
+    pub fn package_name(&self, index: u16) -> Result<String, ClassFormatError> {
+        let package = self.get(index)?.package().ok_or(Self::wrong_kind(index, "Package"))?;
+        self.utf8(package.name_index)
+    }
+
+    pub fn field_ref(&self, index: u16) -> Result<Fieldref, ClassFormatError> {
+        self.get(index)?.fieldref().ok_or(Self::wrong_kind(index, "Fieldref"))
+    }
+
+    pub fn method_ref(&self, index: u16) -> Result<Methodref, ClassFormatError> {
+        self.get(index)?.methodref().ok_or(Self::wrong_kind(index, "Methodref"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf8(s: &str) -> ConstantPoolInfo {
+        ConstantPoolInfo::Utf8(Utf8 { length: s.len() as u16, bytes: s.as_bytes().to_vec() })
+    }
+
+    #[test]
+    fn wide_entries_take_two_slots() {
+        let mut cp = ConstantPool::new();
+        assert_eq!(cp.push(ConstantPoolInfo::Long(Long { high_bytes: 0, low_bytes: 1 })), 1);
+        assert_eq!(cp.push(utf8("java/lang/Object")), 3);
+        assert_eq!(cp.push(ConstantPoolInfo::Class(Class { name_index: 3 })), 4);
+        assert_eq!(cp.count(), 5);
+
+        assert_eq!(cp.class_name(4).unwrap(), "java/lang/Object");
+        assert_eq!(cp.get(0).unwrap_err().reason, Reason::BadIndex(0));
+        assert_eq!(cp.get(2).unwrap_err().reason, Reason::BadIndex(2));
+        assert_eq!(cp.get(5).unwrap_err().reason, Reason::BadIndex(5));
+        assert_eq!(
+            cp.utf8(4).unwrap_err().reason,
+            Reason::WrongKind { index: 4, expected: "Utf8" }
+        );
+    }
+}
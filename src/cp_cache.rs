@@ -0,0 +1,289 @@
+//! The runtime constant pool cache of a class.
+//!
+//! The first time the interpreter runs a field access or an invoke, it resolves the
+//! constant pool entry the instruction refers to, keeps the result here under the same
+//! index, and rewrites the instruction into its quick form. Quick forms go straight to
+//! the resolved entry, without looking at names, loading classes or parsing descriptors.
+
+use std::fmt::{Display, Formatter};
+use crate::bytecode::Instruction;
+use crate::class::{access_flags, Class};
+use crate::class_reader::{ClassFormatError, Reason};
+use crate::constant_pool::{ConstantPool, ConstantPoolInfo};
+use crate::descriptor::Type;
+use crate::field_info::Field;
+use crate::method::Method;
+use crate::runtime::LoadError;
+use crate::Runtime;
+
+/// What a field or method reference resolved to.
+#[derive(Debug, Clone, Copy)]
+pub enum Resolved {
+    /// a static field, which holds its own value
+    StaticField { class: *mut Class, field: *mut Field },
+    /// an instance field, kept at `offset` in objects, and if it is a long or double
+    InstanceField { class: *mut Class, offset: u16, wide: bool },
+    /// a method, how many local slots its arguments take, counting the receiver, and its
+    /// slot in the vtable of `class`, if it has one
+    Method { class: *mut Class, method: *const Method, arg_slots: u16, vtable_index: Option<u16> },
+}
+
+/// Why a field or method reference could not be resolved.
+#[derive(Debug)]
+pub enum ResolveError {
+    Load(LoadError),
+    ClassFormat(ClassFormatError),
+    NoSuchField(String),
+    NoSuchMethod(String),
+    IncompatibleClassChange(String),
+}
+
+impl Display for ResolveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResolveError::Load(e) => write!(f, "{e}"),
+            ResolveError::ClassFormat(e) => write!(f, "java.lang.ClassFormatError: {e}"),
+            ResolveError::NoSuchField(name) => write!(f, "java.lang.NoSuchFieldError: {name}"),
+            ResolveError::NoSuchMethod(name) => write!(f, "java.lang.NoSuchMethodError: {name}"),
+            ResolveError::IncompatibleClassChange(why) => write!(f, "java.lang.IncompatibleClassChangeError: {why}"),
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+impl From<LoadError> for ResolveError {
+    fn from(e: LoadError) -> Self {
+        ResolveError::Load(e)
+    }
+}
+
+impl From<ClassFormatError> for ResolveError {
+    fn from(e: ClassFormatError) -> Self {
+        ResolveError::ClassFormat(e)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConstantPoolCache {
+    // indexed like the constant pool, `None` until resolved
+    entries: Vec<Option<Resolved>>,
+}
+
+impl ConstantPoolCache {
+    pub fn new(cp: &ConstantPool) -> ConstantPoolCache {
+        ConstantPoolCache { entries: vec![None; cp.count()] }
+    }
+
+    /// the entry a quick instruction refers to
+    pub fn get(&self, index: u16) -> Resolved {
+        self.entries[index as usize].expect("quick instruction with an unresolved entry")
+    }
+
+    /// the field that a quick `getstatic` or `putstatic` refers to
+    pub fn static_field(&self, index: u16) -> *mut Field {
+        match self.get(index) {
+            Resolved::StaticField { field, .. } => field,
+            x => panic!("{x:?} is not a static field"),
+        }
+    }
+
+    /// where the field that a quick `getfield` or `putfield` refers to is kept in objects,
+    /// and if it is a long or double
+    pub fn field_offset(&self, index: u16) -> (usize, bool) {
+        match self.get(index) {
+            Resolved::InstanceField { offset, wide, .. } => (offset as usize, wide),
+            x => panic!("{x:?} is not an instance field"),
+        }
+    }
+
+    /// the method that a quick invoke refers to, the class it was found in, and how many
+    /// slots its receiver and arguments take
+    pub fn method(&self, index: u16) -> (*mut Class, *const Method, u16) {
+        match self.get(index) {
+            Resolved::Method { class, method, arg_slots, .. } => (class, method, arg_slots),
+            x => panic!("{x:?} is not a method"),
+        }
+    }
+
+    /// the vtable slot of the method that a quick invoke refers to. static and private
+    /// methods, and ones only an interface declares, have none
+    pub fn vtable_index(&self, index: u16) -> Option<u16> {
+        match self.get(index) {
+            Resolved::Method { vtable_index, .. } => vtable_index,
+            x => panic!("{x:?} is not a method"),
+        }
+    }
+
+    /// resolve the entry that `instruction` refers to, if that hasn't happened yet,
+    /// and give the quick form to rewrite it into
+    pub fn quicken(
+        &mut self,
+        instruction: Instruction,
+        cp: &ConstantPool,
+        runtime: &mut Runtime,
+    ) -> Result<Instruction, ResolveError> {
+        use crate::bytecode::Instruction::*;
+
+        let (index, quick) = match instruction {
+            Getstatic(n) => (n, GetstaticQuick(n)),
+            Putstatic(n) => (n, PutstaticQuick(n)),
+            Getfield(n) => (n, GetfieldQuick(n)),
+            Putfield(n) => (n, PutfieldQuick(n)),
+            Invokevirtual(n) => (n, InvokevirtualQuick(n)),
+            Invokespecial(n) => (n, InvokespecialQuick(n)),
+            Invokestatic(n) => (n, InvokestaticQuick(n)),
+            Invokeinterface(n, count) => (n, InvokeinterfaceQuick(n, count)),
+            x => panic!("{} has no quick form", x.mnemonic()),
+        };
+
+        let resolved = match self.entries[index as usize] {
+            Some(resolved) => resolved,
+            None => {
+                let resolved = match instruction {
+                    Getstatic(_) | Putstatic(_) | Getfield(_) | Putfield(_) => resolve_field(cp, index, runtime)?,
+                    _ => resolve_method(cp, index, runtime)?,
+                };
+                self.entries[index as usize] = Some(resolved);
+                resolved
+            }
+        };
+
+        // the same entry can be used by instructions that want different kinds of member
+        let wants_static = matches!(instruction, Getstatic(_) | Putstatic(_) | Invokestatic(_));
+        let is_static = match resolved {
+            Resolved::StaticField { .. } => true,
+            Resolved::InstanceField { .. } => false,
+            Resolved::Method { method, .. } => unsafe { &*method }.is_static(),
+        };
+        if wants_static != is_static {
+            let (class, name, descriptor) = member(cp, index)?;
+            return Err(ResolveError::IncompatibleClassChange(format!(
+                "expected {} {class}.{name}{descriptor} to be {}static",
+                instruction.mnemonic(),
+                if wants_static { "" } else { "non-" },
+            )));
+        }
+        Ok(quick)
+    }
+}
+
+/// the class, name and descriptor of a field or method reference
+fn member(cp: &ConstantPool, index: u16) -> Result<(String, String, String), ClassFormatError> {
+    let (class_index, nat_index) = match cp.get(index)? {
+        ConstantPoolInfo::Fieldref(r) => (r.class_index, r.name_and_type_index),
+        ConstantPoolInfo::Methodref(r) => (r.class_index, r.name_and_type_index),
+        ConstantPoolInfo::InterfaceMethodref(r) => (r.class_index, r.name_and_type_index),
+        _ => return Err(ClassFormatError::new(Reason::WrongKind {
+            index,
+            expected: "Fieldref, Methodref or InterfaceMethodref",
+        })),
+    };
+    let nat = cp.name_and_type(nat_index)?;
+    Ok((cp.class_name(class_index)?, cp.utf8(nat.name_index)?, cp.utf8(nat.descriptor_index)?))
+}
+
+fn resolve_field(cp: &ConstantPool, index: u16, runtime: &mut Runtime) -> Result<Resolved, ResolveError> {
+    let (class_name, name, descriptor) = member(cp, index)?;
+    let class = runtime.load(class_name.clone())?;
+    let (class, field) = unsafe { find_field(class, &name, &descriptor) }
+        .ok_or_else(|| ResolveError::NoSuchField(format!("{class_name}.{name}")))?;
+    let field = unsafe { &mut *field };
+    Ok(if field.is_static() {
+        Resolved::StaticField { class, field }
+    } else {
+        Resolved::InstanceField { class, offset: field.offset() as u16, wide: field.is_wide() }
+    })
+}
+
+fn resolve_method(cp: &ConstantPool, index: u16, runtime: &mut Runtime) -> Result<Resolved, ResolveError> {
+    let (class_name, name, descriptor) = member(cp, index)?;
+    let class = runtime.load(class_name.clone())?;
+
+    let is_interface = unsafe { &*class }.access_flags() & access_flags::INTERFACE != 0;
+    let wants_interface = matches!(cp.get(index)?, ConstantPoolInfo::InterfaceMethodref(_));
+    if is_interface != wants_interface {
+        return Err(ResolveError::IncompatibleClassChange(format!(
+            "{class_name} is {}an interface",
+            if is_interface { "" } else { "not " },
+        )));
+    }
+
+    let (class, method) = unsafe { find_method(class, &name, &descriptor) }
+        .ok_or_else(|| ResolveError::NoSuchMethod(format!("{class_name}.{name}{descriptor}")))?;
+    let m = unsafe { &*method };
+    let arg_slots = m.descriptor().args.iter().map(Type::slots).sum::<u16>()
+        + if m.is_static() { 0 } else { 1 };
+    let vtable_index = unsafe { &*class }.vtable().iter()
+        .position(|&(_, m)| m == method)
+        .map(|i| i as u16);
+    Ok(Resolved::Method { class, method, arg_slots, vtable_index })
+}
+
+/// look for a field in `class`, then its superinterfaces, then its super class,
+/// giving the class it was found in
+unsafe fn find_field(class: *mut Class, name: &str, descriptor: &str) -> Option<(*mut Class, *mut Field)> {
+    if let Some(field) = (*class).field_mut(name, descriptor) {
+        return Some((class, field));
+    }
+    for &interface in (*class).interfaces() {
+        if let Some(found) = find_field(interface, name, descriptor) {
+            return Some(found);
+        }
+    }
+    let super_class = (*class).super_class();
+    if super_class.is_null() {
+        None
+    } else {
+        find_field(super_class, name, descriptor)
+    }
+}
+
+/// look for a method in `class` and its super classes, then in their superinterfaces,
+/// giving the class it was found in
+pub(crate) unsafe fn find_method(class: *mut Class, name: &str, descriptor: &str) -> Option<(*mut Class, *const Method)> {
+    let key = (name.to_string(), descriptor.to_string());
+    let mut c = class;
+    while !c.is_null() {
+        if let Some(method) = (*c).methods().get(&key) {
+            return Some((c, method));
+        }
+        c = (*c).super_class();
+    }
+
+    let mut c = class;
+    while !c.is_null() {
+        for &interface in (*c).interfaces() {
+            if let Some(found) = find_method(interface, name, descriptor) {
+                return Some(found);
+            }
+        }
+        c = (*c).super_class();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::Instruction::*;
+
+    #[test]
+    fn quicken_static_field() {
+        let mut runtime = Runtime::new("test/jasmin/Statics.j".to_string()).unwrap();
+        let class = unsafe { &mut *runtime.get_class("Statics").unwrap() }.java_mut().unwrap();
+        let class = unsafe { &mut *class };
+        let (index, _) = class.constant_pool.iter()
+            .find(|(_, e)| matches!(e, ConstantPoolInfo::Fieldref(_)))
+            .unwrap();
+
+        let quick = class.cp_cache.quicken(Getstatic(index), &class.constant_pool, &mut runtime).unwrap();
+        assert_eq!(quick, GetstaticQuick(index));
+        let field = unsafe { &*class.cp_cache.static_field(index) };
+        assert_eq!(field.name, "product");
+
+        // resolved already, but not the kind of field getfield wants
+        let e = class.cp_cache.quicken(Getfield(index), &class.constant_pool, &mut runtime).unwrap_err();
+        assert!(matches!(e, ResolveError::IncompatibleClassChange(_)), "{e}");
+    }
+}
@@ -1,4 +1,4 @@
-use crate::descriptor::Type::{Array, Int, Object, Void};
+use crate::descriptor::Type::{Array, Bool, Byte, Char, Double, Float, Int, Long, Object, Short, Void};
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum Type {
@@ -15,6 +15,17 @@ pub enum Type {
     Bool
 }
 
+impl Type {
+    /// how many local variable or operand stack slots a value of this type takes
+    pub fn slots(&self) -> u16 {
+        match self {
+            Void => 0,
+            Long | Double => 2,
+            _ => 1,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct DescriptorInfo {
     pub ret: Type,
@@ -29,61 +40,100 @@ fn array_if_nonzero(depth: u8, t: Type) -> Type {
     }
 }
 
-pub fn types_from(arg_str: &str) -> Vec<Type> {
+/// the field type at the start of `s`, and how many bytes of `s` it takes up
+fn field_type(s: &str) -> Option<(Type, usize)> {
+    let len = field_type_len(s)?;
+    let dimensions = s.bytes().take_while(|&c| c == b'[').count();
+    let t = match s.as_bytes()[dimensions] {
+        b'B' => Byte,
+        b'C' => Char,
+        b'D' => Double,
+        b'F' => Float,
+        b'I' => Int,
+        b'J' => Long,
+        b'S' => Short,
+        b'Z' => Bool,
+        _ => Object(Box::new(s[dimensions + 1..len - 1].to_string())),
+    };
+    // field_type_len doesn't allow more than 255 dimensions
+    Some((array_if_nonzero(dimensions as u8, t), len))
+}
+
+pub fn args(descriptor: &str) -> Option<Vec<Type>> {
+    info(descriptor).map(|info| info.args)
+}
+
+/// parse a method descriptor, or give `None` if it is not a valid one
+pub fn info(descriptor: &str) -> Option<DescriptorInfo> {
+    let mut rest = descriptor.strip_prefix('(')?;
     let mut args = vec![];
-    let mut array_depth: u8 = 0;
-    let mut index: usize = 0;
-    while let Some(c) = arg_str.chars().nth(index) {
-        index += 1;
-        args.push(array_if_nonzero(
-            array_depth,
-            match c {
-                '[' => {
-                    array_depth += 1;
-                    continue;
-                }
-                'I' => Int,
-                'V' => Void,
-                'L' => {
-                    let mut s = String::new();
-                    while arg_str.chars().nth(index) != Some(';') {
-                        s.push(arg_str.chars().nth(index).unwrap());
-                        index += 1;
-                    }
-                    index += 1;
-                    Object(Box::from(s))
-                },
-
-                c => panic!("invalid char {}", c),
-            },
-        ))
+    // class names can contain ')', so the arguments end where a type doesn't start
+    while !rest.starts_with(')') {
+        let (t, len) = field_type(rest)?;
+        args.push(t);
+        rest = &rest[len..];
     }
-
-    args
+    let ret = match &rest[1..] {
+        "V" => Void,
+        ret => match field_type(ret)? {
+            (t, len) if len == ret.len() => t,
+            _ => return None,
+        },
+    };
+    Some(DescriptorInfo { ret, args })
 }
 
-pub fn args(descriptor: &str) -> Vec<Type> {
-    let l_par = descriptor.find('(').unwrap();
-    let r_par = descriptor.find(')').unwrap();
-    let arg_str = &descriptor[l_par + 1..r_par];
+/// an unqualified name (JVMS §4.2.2), as used for fields, methods and local variables
+pub fn is_unqualified_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(['.', ';', '[', '/'])
+}
 
-    types_from(arg_str)
+/// a method name: an unqualified name without angle brackets, or one of the two special names
+pub fn is_method_name(name: &str) -> bool {
+    name == "<init>" || name == "<clinit>" || (is_unqualified_name(name) && !name.contains(['<', '>']))
 }
 
-pub fn info(descriptor: &str) -> DescriptorInfo {
-    let index: usize = descriptor.find(')').unwrap();
+/// a binary class name in internal form, like `java/lang/Object`
+pub fn is_class_name(name: &str) -> bool {
+    name.split('/').all(is_unqualified_name)
+}
 
-    DescriptorInfo {
-        ret: type_from(&descriptor[index + 1..]),
-        args: args(descriptor),
+/// the length of the field type at the start of `s`, if it starts with one
+pub(crate) fn field_type_len(s: &str) -> Option<usize> {
+    let dimensions = s.bytes().take_while(|&c| c == b'[').count();
+    if dimensions > 255 {
+        return None;
     }
+    let rest = &s[dimensions..];
+    let len = match rest.bytes().next()? {
+        b'B' | b'C' | b'D' | b'F' | b'I' | b'J' | b'S' | b'Z' => 1,
+        b'L' => {
+            let end = rest.find(';')?;
+            if !is_class_name(&rest[1..end]) {
+                return None;
+            }
+            end + 1
+        }
+        _ => return None,
+    };
+    Some(dimensions + len)
+}
+
+pub fn is_field_descriptor(descriptor: &str) -> bool {
+    field_type_len(descriptor) == Some(descriptor.len())
 }
 
-pub fn type_from(partial_descriptor: &str) -> Type {
-    let v = types_from(partial_descriptor);
-    return v[0].clone();
+pub fn is_method_descriptor(descriptor: &str) -> bool {
+    arg_slots(descriptor).is_some()
 }
 
+/// the number of local variable slots the arguments of a method descriptor take up,
+/// or `None` if it is not a valid method descriptor
+pub fn arg_slots(descriptor: &str) -> Option<usize> {
+    info(descriptor).map(|info| info.args.iter().map(|t| t.slots() as usize).sum())
+}
+
+#[cfg(test)]
 mod tests {
     use crate::descriptor::Type::*;
     use crate::descriptor::{info, DescriptorInfo};
@@ -92,25 +142,58 @@ mod tests {
     fn test_args() {
         use super::args;
 
-        assert_eq!(args("()V"), vec![]);
-        assert_eq!(args("(VI)V"), vec![Void, Int]);
+        assert_eq!(args("()V"), Some(vec![]));
         assert_eq!(
-            args("(VLjava/lang/String;I)V"),
-            vec![Void, Object(Box::from("java/lang/String".to_string())), Int]
+            args("(ILjava/lang/String;I)V"),
+            Some(vec![Int, Object(Box::from("java/lang/String".to_string())), Int])
         );
         assert_eq!(
             args("([Ljava/lang/String;)V"),
-            vec![Array(1, Box::new(Object(Box::from("java/lang/String".to_string()))))]
-        )
+            Some(vec![Array(1, Box::new(Object(Box::from("java/lang/String".to_string()))))])
+        );
+        // only the first argument is an array
+        assert_eq!(args("([IJ)V"), Some(vec![Array(1, Box::new(Int)), Long]));
+        // void is not an argument type
+        assert_eq!(args("(VI)V"), None);
+    }
+
+    #[test]
+    fn validation() {
+        use super::{arg_slots, is_class_name, is_field_descriptor};
+
+        assert!(is_field_descriptor("[[Ljava/lang/String;"));
+        assert!(is_field_descriptor("J"));
+        assert!(!is_field_descriptor("V"));
+        assert!(!is_field_descriptor("Ljava/lang/String"));
+        assert!(!is_field_descriptor("L;"));
+        assert!(!is_field_descriptor("II"));
+        assert_eq!(arg_slots("(IJLjava/lang/Object;[D)V"), Some(5));
+        assert_eq!(arg_slots("()[I"), Some(0));
+        assert_eq!(arg_slots("(V)V"), None);
+        assert_eq!(arg_slots("(I)"), None);
+        assert!(is_class_name("java/lang/Object"));
+        assert!(!is_class_name("java//Object"));
+        assert!(!is_class_name("java.lang.Object"));
     }
 
+    #[test]
     fn test_info() {
         assert_eq!(
             info("([Ljava/lang/String;)V"),
-            DescriptorInfo {
+            Some(DescriptorInfo {
                 ret: Void,
                 args: vec![Array(1, Box::new(Object(Box::from("java/lang/String".to_string()))))]
-            }
-        )
+            })
+        );
+        // a class name can contain ')', so the arguments don't end at the first one
+        assert_eq!(
+            info("(La)b;)La)b;"),
+            Some(DescriptorInfo {
+                ret: Object(Box::from("a)b".to_string())),
+                args: vec![Object(Box::from("a)b".to_string()))],
+            })
+        );
+        assert_eq!(info("(I)X"), None);
+        assert_eq!(info("(I"), None);
     }
 }
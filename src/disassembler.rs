@@ -0,0 +1,349 @@
+use std::fmt::Write;
+use crate::attributes::attribute::Attribute;
+use crate::attributes::attribute_info::AttributeInfo;
+use crate::attributes::code_reader::CodeReader;
+use crate::bytecode::{Instruction, SwitchTable};
+use crate::class_file::ClassFile;
+use crate::class_version::ClassVersion;
+use crate::class_reader::ClassFormatError;
+use crate::constant_pool::{ConstantPool, ConstantPoolInfo};
+
+const CLASS_FLAGS: &[(u16, &str)] = &[
+    (0x0001, "ACC_PUBLIC"),
+    (0x0010, "ACC_FINAL"),
+    (0x0020, "ACC_SUPER"),
+    (0x0200, "ACC_INTERFACE"),
+    (0x0400, "ACC_ABSTRACT"),
+    (0x1000, "ACC_SYNTHETIC"),
+    (0x2000, "ACC_ANNOTATION"),
+    (0x4000, "ACC_ENUM"),
+    (0x8000, "ACC_MODULE"),
+];
+
+const FIELD_FLAGS: &[(u16, &str)] = &[
+    (0x0001, "ACC_PUBLIC"),
+    (0x0002, "ACC_PRIVATE"),
+    (0x0004, "ACC_PROTECTED"),
+    (0x0008, "ACC_STATIC"),
+    (0x0010, "ACC_FINAL"),
+    (0x0040, "ACC_VOLATILE"),
+    (0x0080, "ACC_TRANSIENT"),
+    (0x1000, "ACC_SYNTHETIC"),
+    (0x4000, "ACC_ENUM"),
+];
+
+const METHOD_FLAGS: &[(u16, &str)] = &[
+    (0x0001, "ACC_PUBLIC"),
+    (0x0002, "ACC_PRIVATE"),
+    (0x0004, "ACC_PROTECTED"),
+    (0x0008, "ACC_STATIC"),
+    (0x0010, "ACC_FINAL"),
+    (0x0020, "ACC_SYNCHRONIZED"),
+    (0x0040, "ACC_BRIDGE"),
+    (0x0080, "ACC_VARARGS"),
+    (0x0100, "ACC_NATIVE"),
+    (0x0400, "ACC_ABSTRACT"),
+    (0x0800, "ACC_STRICT"),
+    (0x1000, "ACC_SYNTHETIC"),
+];
+
+fn flags(access_flags: u16, names: &[(u16, &str)]) -> String {
+    let set: Vec<&str> = names.iter()
+        .filter(|(bit, _)| access_flags & bit != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    format!("({:#06x}) {}", access_flags, set.join(", "))
+}
+
+fn kind(c: &ConstantPoolInfo) -> &'static str {
+    match c {
+        ConstantPoolInfo::Class(_) => "Class",
+        ConstantPoolInfo::Fieldref(_) => "Fieldref",
+        ConstantPoolInfo::Methodref(_) => "Methodref",
+        ConstantPoolInfo::InterfaceMethodref(_) => "InterfaceMethodref",
+        ConstantPoolInfo::JString(_) => "String",
+        ConstantPoolInfo::Integer(_) => "Integer",
+        ConstantPoolInfo::Float(_) => "Float",
+        ConstantPoolInfo::Long(_) => "Long",
+        ConstantPoolInfo::Double(_) => "Double",
+        ConstantPoolInfo::NameAndType(_) => "NameAndType",
+        ConstantPoolInfo::Utf8(_) => "Utf8",
+        ConstantPoolInfo::MethodHandle(_) => "MethodHandle",
+        ConstantPoolInfo::MethodType(_) => "MethodType",
+        ConstantPoolInfo::InvokeDynamic(_) => "InvokeDynamic",
+        ConstantPoolInfo::Dynamic(_) => "Dynamic",
+        ConstantPoolInfo::Module(_) => "Module",
+        ConstantPoolInfo::Package(_) => "Package",
+    }
+}
+
+fn reference_kind(kind: u8) -> &'static str {
+    match kind {
+        1 => "REF_getField",
+        2 => "REF_getStatic",
+        3 => "REF_putField",
+        4 => "REF_putStatic",
+        5 => "REF_invokeVirtual",
+        6 => "REF_invokeStatic",
+        7 => "REF_invokeSpecial",
+        8 => "REF_newInvokeSpecial",
+        9 => "REF_invokeInterface",
+        _ => "REF_invalid",
+    }
+}
+
+fn long_bits(high: u32, low: u32) -> u64 {
+    (high as u64) << 32 | low as u64
+}
+
+/// the entry's operands as they are stored, like `#2.#3`
+fn raw(c: &ConstantPoolInfo) -> String {
+    match c {
+        ConstantPoolInfo::Class(c) => format!("#{}", c.name_index),
+        ConstantPoolInfo::Fieldref(r) => format!("#{}.#{}", r.class_index, r.name_and_type_index),
+        ConstantPoolInfo::Methodref(r) => format!("#{}.#{}", r.class_index, r.name_and_type_index),
+        ConstantPoolInfo::InterfaceMethodref(r) =>
+            format!("#{}.#{}", r.class_index, r.name_and_type_index),
+        ConstantPoolInfo::JString(s) => format!("#{}", s.string_index),
+        ConstantPoolInfo::NameAndType(nt) => format!("#{}:#{}", nt.name_index, nt.descriptor_index),
+        ConstantPoolInfo::MethodHandle(mh) => format!("{}:#{}", mh.reference_kind, mh.reference_index),
+        ConstantPoolInfo::MethodType(mt) => format!("#{}", mt.descriptor_index),
+        ConstantPoolInfo::InvokeDynamic(id) =>
+            format!("#{}:#{}", id.bootstrap_method_attr_index, id.name_and_type_index),
+        ConstantPoolInfo::Dynamic(d) => format!("#{}:#{}", d.bootstrap_method_attr_index, d.name_and_type_index),
+        ConstantPoolInfo::Module(m) => format!("#{}", m.name_index),
+        ConstantPoolInfo::Package(p) => format!("#{}", p.name_index),
+        // constants have nothing to resolve, so they are shown as their values
+        _ => String::new(),
+    }
+}
+
+fn name_and_type(cp: &ConstantPool, index: u16) -> Result<String, ClassFormatError> {
+    let nt = cp.name_and_type(index)?;
+    let name = cp.utf8(nt.name_index)?;
+    let descriptor = cp.utf8(nt.descriptor_index)?;
+    if name.starts_with('<') {
+        Ok(format!("\"{name}\":{descriptor}"))
+    } else {
+        Ok(format!("{name}:{descriptor}"))
+    }
+}
+
+fn resolve(cp: &ConstantPool, index: u16) -> Result<String, ClassFormatError> {
+    Ok(match cp.get(index)? {
+        ConstantPoolInfo::Class(_) => cp.class_name(index)?,
+        ConstantPoolInfo::Fieldref(r) =>
+            format!("{}.{}", cp.class_name(r.class_index)?, name_and_type(cp, r.name_and_type_index)?),
+        ConstantPoolInfo::Methodref(r) =>
+            format!("{}.{}", cp.class_name(r.class_index)?, name_and_type(cp, r.name_and_type_index)?),
+        ConstantPoolInfo::InterfaceMethodref(r) =>
+            format!("{}.{}", cp.class_name(r.class_index)?, name_and_type(cp, r.name_and_type_index)?),
+        ConstantPoolInfo::JString(_) => format!("{:?}", String::from_utf16_lossy(&cp.string(index)?)),
+        ConstantPoolInfo::Integer(i) => (i.bytes as i32).to_string(),
+        ConstantPoolInfo::Float(f) => format!("{:?}f", f32::from_bits(f.bytes)),
+        ConstantPoolInfo::Long(l) => format!("{}l", long_bits(l.high_bytes, l.low_bytes) as i64),
+        ConstantPoolInfo::Double(d) => format!("{:?}d", f64::from_bits(long_bits(d.high_bytes, d.low_bytes))),
+        ConstantPoolInfo::NameAndType(_) => name_and_type(cp, index)?,
+        ConstantPoolInfo::Utf8(_) => cp.utf8(index)?,
+        ConstantPoolInfo::MethodHandle(mh) =>
+            format!("{} {}", reference_kind(mh.reference_kind), resolve(cp, mh.reference_index)?),
+        ConstantPoolInfo::MethodType(mt) => cp.utf8(mt.descriptor_index)?,
+        ConstantPoolInfo::InvokeDynamic(id) =>
+            format!("#{}:{}", id.bootstrap_method_attr_index, name_and_type(cp, id.name_and_type_index)?),
+        ConstantPoolInfo::Dynamic(d) =>
+            format!("#{}:{}", d.bootstrap_method_attr_index, name_and_type(cp, d.name_and_type_index)?),
+        ConstantPoolInfo::Module(_) => cp.module_name(index)?,
+        ConstantPoolInfo::Package(_) => cp.package_name(index)?,
+    })
+}
+
+/// a constant pool reference, resolved as far as the pool allows
+fn describe(cp: &ConstantPool, index: u16) -> String {
+    resolve(cp, index).unwrap_or_else(|_| format!("<invalid #{index}>"))
+}
+
+fn utf8_or_invalid(cp: &ConstantPool, index: u16) -> String {
+    cp.utf8(index).unwrap_or_else(|_| format!("<invalid #{index}>"))
+}
+
+fn array_type(atype: u8) -> &'static str {
+    match atype {
+        4 => "boolean",
+        5 => "char",
+        6 => "float",
+        7 => "double",
+        8 => "byte",
+        9 => "short",
+        10 => "int",
+        11 => "long",
+        _ => "<invalid>",
+    }
+}
+
+fn operands(instruction: &Instruction, cp: &ConstantPool) -> String {
+    use crate::bytecode::Instruction::*;
+
+    let reference = |n: u16| format!("#{n} // {}", describe(cp, n));
+
+    match instruction.unquickened() {
+        Ipush(x) if !(-1..=5).contains(&x) => x.to_string(),
+        Ldc(n) => reference(n as u16),
+        Ldc2W(n) => reference(n),
+        Getstatic(n) | Putstatic(n) | Getfield(n) | Putfield(n) |
+        Invokevirtual(n) | Invokespecial(n) | Invokestatic(n) |
+        Invokedynamic(n) |
+        New(n) | Anewarray(n) | Checkcast(n) | Instanceof(n) => reference(n),
+        Invokeinterface(n, count) => format!("#{n}, {count} // {}", describe(cp, n)),
+        Multianewarray(n, dimensions) => format!("#{n}, {dimensions} // {}", describe(cp, n)),
+        Iload(n) | Lload(n) | Fload(n) | Dload(n) | Aload(n) |
+        Istore(n) | Lstore(n) | Fstore(n) | Dstore(n) | Astore(n) => n.to_string(),
+        Iinc(index, by) => format!("{index}, {by}"),
+        // branch targets are instruction indices, the same numbers as the left column
+        Ifeq(target) | Ifne(target) | Iflt(target) | Ifge(target) | Ifgt(target) | Ifle(target) |
+        IfIcmpeq(target) | IfIcmpne(target) | IfIcmplt(target) |
+        IfIcmpge(target) | IfIcmpgt(target) | IfIcmple(target) |
+        IfAcmpeq(target) | IfAcmpne(target) | Goto(target) | GotoW(target) |
+        Jsr(target) | JsrW(target) |
+        Ifnull(target) | Ifnonnull(target) => target.to_string(),
+        Ret(n) => n.to_string(),
+        Newarray(atype) => array_type(atype).to_string(),
+        _ => String::new(),
+    }
+}
+
+/// a switch's jump table, laid out like javap does
+fn switch(table: &SwitchTable) -> String {
+    let (mut s, entries): (String, Vec<(i32, u16)>) = match table {
+        SwitchTable::Table { low, targets, .. } => (
+            format!("{{ // {} to {}", low, *low as i64 + targets.len() as i64 - 1),
+            targets.iter().enumerate().map(|(i, t)| (low + i as i32, *t)).collect(),
+        ),
+        SwitchTable::Lookup { pairs, .. } => (format!("{{ // {}", pairs.len()), pairs.clone()),
+    };
+    for (key, target) in entries {
+        s += &format!("\n{key:>24}: {target}");
+    }
+    s += &format!("\n{:>24}: {}\n            }}", "default", table.default());
+    s
+}
+
+fn code(out: &mut String, info: &[u8], cp: &ConstantPool, version: ClassVersion) -> std::fmt::Result {
+    writeln!(out, "    Code:")?;
+    let code = match CodeReader::new(info, cp, version).read_code() {
+        Ok(code) => code,
+        Err(e) => return writeln!(out, "      <could not decode: {e:?}>"),
+    };
+
+    writeln!(out, "      stack={}, locals={}", code.max_stack, code.max_locals)?;
+    for (i, instruction) in code.instructions().iter().enumerate() {
+        let operands = match *instruction {
+            Instruction::Tableswitch(n) | Instruction::Lookupswitch(n) => switch(&code.switches[n as usize]),
+            _ => operands(instruction, cp),
+        };
+        if operands.is_empty() {
+            writeln!(out, "      {:>4}: {}", i, instruction.mnemonic())?;
+        } else {
+            writeln!(out, "      {:>4}: {:<16}{}", i, instruction.mnemonic(), operands)?;
+        }
+    }
+
+    if !code.exception_table.is_empty() {
+        writeln!(out, "      Exception table:")?;
+        writeln!(out, "         from    to  target type")?;
+        for e in &code.exception_table {
+            let catch_type = if e.catch_type == 0 {
+                "any".to_string()
+            } else {
+                describe(cp, e.catch_type)
+            };
+            writeln!(out, "        {:>5} {:>5} {:>5}   {}", e.start_pc, e.end_pc, e.handler_pc, catch_type)?;
+        }
+    }
+
+    for a in &code.attributes {
+        writeln!(out, "      {}", a.name())?;
+    }
+    Ok(())
+}
+
+fn attributes(out: &mut String, attributes: &[AttributeInfo], cp: &ConstantPool, indent: &str) -> std::fmt::Result {
+    for ai in attributes {
+        match Attribute::from_info(cp, ai) {
+            Ok(Attribute::SourceFile(n)) => writeln!(out, "{indent}SourceFile: \"{}\"", utf8_or_invalid(cp, n))?,
+            Ok(Attribute::Signature(n)) => writeln!(out, "{indent}Signature: {}", utf8_or_invalid(cp, n))?,
+            Ok(Attribute::ConstantValue(n)) => writeln!(out, "{indent}ConstantValue: {}", describe(cp, n))?,
+            Ok(Attribute::Exceptions(e)) => {
+                let names: Vec<String> = e.iter().map(|n| describe(cp, *n)).collect();
+                writeln!(out, "{indent}Exceptions: throws {}", names.join(", "))?
+            }
+            Ok(a) => writeln!(out, "{indent}{}", a.name())?,
+            Err(e) => writeln!(out, "{indent}<bad attribute: {e}>")?,
+        }
+    }
+    Ok(())
+}
+
+/// render a class the way `javap -v` does, with resolved constant pool references
+/// and instruction indices in place of byte offsets
+pub fn disassemble(cf: &ClassFile) -> String {
+    let mut out = String::new();
+    write_class(&mut out, cf).expect("writing to a String can't fail");
+    out
+}
+
+fn write_class(out: &mut String, cf: &ClassFile) -> std::fmt::Result {
+    let cp = &cf.constant_pool;
+
+    writeln!(out, "class {}", describe(cp, cf.this_class))?;
+    writeln!(out, "  minor version: {}", cf.minor_version)?;
+    writeln!(out, "  major version: {}", cf.major_version)?;
+    writeln!(out, "  flags: {}", flags(cf.access_flags, CLASS_FLAGS))?;
+    writeln!(out, "  this_class: #{} // {}", cf.this_class, describe(cp, cf.this_class))?;
+    if cf.super_class == 0 {
+        writeln!(out, "  super_class: #0")?;
+    } else {
+        writeln!(out, "  super_class: #{} // {}", cf.super_class, describe(cp, cf.super_class))?;
+    }
+    writeln!(out, "  interfaces: {}", cf.interfaces.len())?;
+    for i in &cf.interfaces {
+        writeln!(out, "    #{} // {}", i, describe(cp, *i))?;
+    }
+
+    writeln!(out, "Constant pool:")?;
+    for (i, c) in cp.iter() {
+        let resolved = describe(cp, i);
+        let raw = raw(c);
+        if raw.is_empty() {
+            writeln!(out, "  {:>5} = {:<18} {}", format!("#{i}"), kind(c), resolved)?;
+        } else {
+            writeln!(out, "  {:>5} = {:<18} {:<14} // {}", format!("#{i}"), kind(c), raw, resolved)?;
+        }
+    }
+
+    writeln!(out, "{{")?;
+    for f in &cf.fields {
+        writeln!(out, "  {}", utf8_or_invalid(cp, f.name_index))?;
+        writeln!(out, "    descriptor: {}", utf8_or_invalid(cp, f.descriptor_index))?;
+        writeln!(out, "    flags: {}", flags(f.access_flags, FIELD_FLAGS))?;
+        attributes(out, &f.attributes, cp, "    ")?;
+        writeln!(out)?;
+    }
+    for m in &cf.methods {
+        writeln!(out, "  {}", utf8_or_invalid(cp, m.name_index))?;
+        writeln!(out, "    descriptor: {}", utf8_or_invalid(cp, m.descriptor_index))?;
+        writeln!(out, "    flags: {}", flags(m.access_flags, METHOD_FLAGS))?;
+        for ai in &m.attributes {
+            if cp.utf8(ai.name_index).as_deref() == Ok("Code") {
+                code(out, &ai.info, cp, cf.version())?;
+            }
+        }
+        let others: Vec<_> = m.attributes.iter()
+            .filter(|ai| cp.utf8(ai.name_index).as_deref() != Ok("Code"))
+            .cloned()
+            .collect();
+        attributes(out, &others, cp, "    ")?;
+        writeln!(out)?;
+    }
+    writeln!(out, "}}")?;
+    attributes(out, &cf.attributes, cp, "")
+}
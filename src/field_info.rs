@@ -1,6 +1,7 @@
+use crate::attributes::attribute::Attribute;
 use crate::attributes::attribute_info::AttributeInfo;
-use crate::constant_pool::ConstantPoolInfo;
-use std::collections::HashMap;
+use crate::class_reader::ClassFormatError;
+use crate::constant_pool::ConstantPool;
 use std::fmt::{Debug, Formatter};
 use crate::values::{Object, Value};
 
@@ -31,32 +32,23 @@ pub struct Field {
     pub access_flags: u16,
     pub name: String,
     pub descriptor: String,
-    pub attributes: HashMap<String, Vec<u8>>,
+    pub attributes: Vec<Attribute>,
     pub(crate) access_helper: AccessHelper,
 }
 
 impl Field {
-    pub(crate) fn from_info(cp: &Vec<ConstantPoolInfo>, fi: &FieldInfo) -> Field {
+    pub(crate) fn from_info(cp: &ConstantPool, fi: &FieldInfo) -> Result<Field, ClassFormatError> {
         let mut f = Field {
             access_flags: fi.access_flags,
-            name: cp[(fi.name_index - 1) as usize]
-                .utf8()
-                .expect("bad utf8 in field name"),
-            descriptor: cp[(fi.descriptor_index - 1) as usize]
-                .utf8()
-                .expect("bad utf8 in field descriptor"),
-            attributes: HashMap::new(),
+            name: cp.utf8(fi.name_index)?,
+            descriptor: cp.utf8(fi.descriptor_index)?,
+            attributes: vec![],
             access_helper: AccessHelper { offset: 0 }
         };
         for a in &fi.attributes {
-            f.attributes.insert(
-                cp[(a.name_index - 1) as usize]
-                    .utf8()
-                    .expect("bad utf8 in attribute name"),
-                a.info.clone(),
-            );
+            f.attributes.push(Attribute::from_info(cp, a)?);
         }
-        f
+        Ok(f)
     }
 
     pub fn get_static(&self) -> Value {
@@ -67,6 +59,15 @@ impl Field {
         self.access_helper = AccessHelper { value: val }
     }
 
+    /// where an instance field is kept in an object
+    pub fn offset(&self) -> usize {
+        unsafe { self.access_helper.offset }
+    }
+
+    pub(crate) fn set_offset(&mut self, offset: usize) {
+        self.access_helper = AccessHelper { offset }
+    }
+
     pub fn get_instance(&self, obj: *const Object) -> Value {
         unsafe {
             (*obj).get(self.access_helper.offset)
@@ -86,4 +87,9 @@ impl Field {
     pub fn is_object(&self) -> bool {
         self.descriptor.chars().nth(0).unwrap() == 'L'
     }
+
+    /// if the field is a long or double, which take two slots on the operand stack
+    pub fn is_wide(&self) -> bool {
+        matches!(self.descriptor.as_str(), "J" | "D")
+    }
 }
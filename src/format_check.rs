@@ -0,0 +1,463 @@
+//! Format checking (JVMS §4.8): the static constraints on a class file that can
+//! be checked without looking at any other class or at the bytecode.
+
+use std::collections::HashSet;
+use crate::class_file::ClassFile;
+use crate::class_reader::{ClassFormatError, Reason, Structure};
+use crate::constant_pool::{ConstantPool, ConstantPoolInfo};
+use crate::descriptor::{arg_slots, is_class_name, is_field_descriptor, is_method_descriptor, is_method_name, is_unqualified_name};
+use crate::field_info::FieldInfo;
+use crate::method_info::MethodInfo;
+
+const ACC_PUBLIC: u16 = 0x0001;
+const ACC_PRIVATE: u16 = 0x0002;
+const ACC_PROTECTED: u16 = 0x0004;
+const ACC_STATIC: u16 = 0x0008;
+const ACC_FINAL: u16 = 0x0010;
+const ACC_SUPER: u16 = 0x0020;
+const ACC_SYNCHRONIZED: u16 = 0x0020;
+const ACC_VOLATILE: u16 = 0x0040;
+const ACC_VARARGS: u16 = 0x0080;
+const ACC_NATIVE: u16 = 0x0100;
+const ACC_INTERFACE: u16 = 0x0200;
+const ACC_ABSTRACT: u16 = 0x0400;
+const ACC_STRICT: u16 = 0x0800;
+const ACC_SYNTHETIC: u16 = 0x1000;
+const ACC_ANNOTATION: u16 = 0x2000;
+const ACC_ENUM: u16 = 0x4000;
+const ACC_MODULE: u16 = 0x8000;
+
+type Result<T> = std::result::Result<T, ClassFormatError>;
+
+/// check `cf` for everything that would make it an invalid class file,
+/// apart from its bytecode, which is left to the verifier
+pub fn check(cf: &ClassFile) -> Result<()> {
+    for (i, c) in cf.constant_pool.iter() {
+        check_constant(cf, i, c).map_err(|e| e.within(Structure::ConstantPool(i)))?;
+    }
+
+    check_class(cf)?;
+
+    let mut fields = HashSet::new();
+    for (i, f) in cf.fields.iter().enumerate() {
+        check_field(cf, f, &mut fields).map_err(|e| e.within(Structure::Field(i as u16)))?;
+    }
+    let mut methods = HashSet::new();
+    for (i, m) in cf.methods.iter().enumerate() {
+        check_method(cf, m, &mut methods).map_err(|e| e.within(Structure::Method(i as u16)))?;
+    }
+    Ok(())
+}
+
+fn illegal(flags: u16, why: &'static str) -> ClassFormatError {
+    ClassFormatError::new(Reason::IllegalFlags { flags, why })
+}
+
+fn bad_name(name: &str) -> ClassFormatError {
+    ClassFormatError::new(Reason::BadName(name.to_string()))
+}
+
+fn bad_descriptor(descriptor: &str) -> ClassFormatError {
+    ClassFormatError::new(Reason::BadDescriptor(descriptor.to_string()))
+}
+
+/// at most one of `ACC_PUBLIC`, `ACC_PRIVATE` and `ACC_PROTECTED`
+fn check_visibility(flags: u16) -> Result<()> {
+    if (flags & (ACC_PUBLIC | ACC_PRIVATE | ACC_PROTECTED)).count_ones() > 1 {
+        return Err(illegal(flags, "more than one of public, private and protected"));
+    }
+    Ok(())
+}
+
+/// the name and descriptor of a `NameAndType`
+fn name_and_type(cp: &ConstantPool, index: u16) -> Result<(String, String)> {
+    let nt = cp.name_and_type(index)?;
+    Ok((cp.utf8(nt.name_index)?, cp.utf8(nt.descriptor_index)?))
+}
+
+/// the target of a `Methodref` or `InterfaceMethodref`, returning its name
+fn check_method_ref(cp: &ConstantPool, class_index: u16, name_and_type_index: u16) -> Result<String> {
+    cp.class(class_index)?;
+    let (name, descriptor) = name_and_type(cp, name_and_type_index)?;
+    if !is_method_name(&name) || name == "<clinit>" {
+        return Err(bad_name(&name));
+    }
+    if !is_method_descriptor(&descriptor) || (name == "<init>" && !descriptor.ends_with(")V")) {
+        return Err(bad_descriptor(&descriptor));
+    }
+    Ok(name)
+}
+
+fn check_constant(cf: &ClassFile, index: u16, c: &ConstantPoolInfo) -> Result<()> {
+    let cp = &cf.constant_pool;
+    let version = cf.version();
+
+    // entries that were added after the class file's version
+    let since = match c {
+        ConstantPoolInfo::MethodHandle(_) |
+        ConstantPoolInfo::MethodType(_) |
+        ConstantPoolInfo::InvokeDynamic(_) => 51,
+        ConstantPoolInfo::Module(_) | ConstantPoolInfo::Package(_) => 53,
+        ConstantPoolInfo::Dynamic(_) => 55,
+        _ => 45,
+    };
+    if version.major < since {
+        return Err(ClassFormatError::new(Reason::InvalidConstantPoolTag(c.clone().tag())));
+    }
+
+    match c {
+        ConstantPoolInfo::Utf8(u) => {
+            u.decode().map_err(|_| ClassFormatError::new(Reason::BadUtf8(index)))?;
+        }
+        ConstantPoolInfo::Class(c) => {
+            let name = cp.utf8(c.name_index)?;
+            let valid = if name.starts_with('[') {
+                is_field_descriptor(&name)
+            } else {
+                is_class_name(&name)
+            };
+            if !valid {
+                return Err(bad_name(&name));
+            }
+        }
+        ConstantPoolInfo::Fieldref(r) => {
+            cp.class(r.class_index)?;
+            let (name, descriptor) = name_and_type(cp, r.name_and_type_index)?;
+            if !is_unqualified_name(&name) {
+                return Err(bad_name(&name));
+            }
+            if !is_field_descriptor(&descriptor) {
+                return Err(bad_descriptor(&descriptor));
+            }
+        }
+        ConstantPoolInfo::Methodref(r) => {
+            check_method_ref(cp, r.class_index, r.name_and_type_index)?;
+        }
+        ConstantPoolInfo::InterfaceMethodref(r) => {
+            let name = check_method_ref(cp, r.class_index, r.name_and_type_index)?;
+            if name == "<init>" {
+                return Err(bad_name(&name));
+            }
+        }
+        ConstantPoolInfo::JString(s) => {
+            cp.utf8(s.string_index)?;
+        }
+        ConstantPoolInfo::Integer(_) |
+        ConstantPoolInfo::Float(_) |
+        ConstantPoolInfo::Long(_) |
+        ConstantPoolInfo::Double(_) => {}
+        ConstantPoolInfo::NameAndType(nt) => {
+            let name = cp.utf8(nt.name_index)?;
+            let descriptor = cp.utf8(nt.descriptor_index)?;
+            if !is_method_name(&name) {
+                return Err(bad_name(&name));
+            }
+            if !is_field_descriptor(&descriptor) && !is_method_descriptor(&descriptor) {
+                return Err(bad_descriptor(&descriptor));
+            }
+        }
+        ConstantPoolInfo::MethodHandle(mh) => {
+            let wrong_kind = |expected| Err(ClassFormatError::new(Reason::WrongKind {
+                index: mh.reference_index,
+                expected,
+            }));
+            let name_and_type_index = match (mh.reference_kind, cp.get(mh.reference_index)?) {
+                // getField, getStatic, putField, putStatic
+                (1..=4, ConstantPoolInfo::Fieldref(_)) => return Ok(()),
+                (1..=4, _) => return wrong_kind("Fieldref"),
+                // invokeVirtual, newInvokeSpecial
+                (5 | 8, ConstantPoolInfo::Methodref(r)) => r.name_and_type_index,
+                // invokeStatic, invokeSpecial can refer to interface methods from java 8 on
+                (6 | 7, ConstantPoolInfo::Methodref(r)) => r.name_and_type_index,
+                (6 | 7, ConstantPoolInfo::InterfaceMethodref(r)) if version.major >= 52 =>
+                    r.name_and_type_index,
+                (5..=8, _) => return wrong_kind("Methodref"),
+                // invokeInterface
+                (9, ConstantPoolInfo::InterfaceMethodref(r)) => r.name_and_type_index,
+                (9, _) => return wrong_kind("InterfaceMethodref"),
+                (kind, _) => return Err(ClassFormatError::new(Reason::BadReferenceKind(kind))),
+            };
+            let (name, _) = name_and_type(cp, name_and_type_index)?;
+            // only newInvokeSpecial may, and must, refer to a constructor
+            if (name == "<init>") != (mh.reference_kind == 8) || name == "<clinit>" {
+                return Err(bad_name(&name));
+            }
+        }
+        ConstantPoolInfo::MethodType(mt) => {
+            let descriptor = cp.utf8(mt.descriptor_index)?;
+            if !is_method_descriptor(&descriptor) {
+                return Err(bad_descriptor(&descriptor));
+            }
+        }
+        ConstantPoolInfo::InvokeDynamic(id) => {
+            let (name, descriptor) = name_and_type(cp, id.name_and_type_index)?;
+            if !is_method_name(&name) || name.starts_with('<') {
+                return Err(bad_name(&name));
+            }
+            if !is_method_descriptor(&descriptor) {
+                return Err(bad_descriptor(&descriptor));
+            }
+        }
+        ConstantPoolInfo::Dynamic(d) => {
+            let (name, descriptor) = name_and_type(cp, d.name_and_type_index)?;
+            if !is_unqualified_name(&name) {
+                return Err(bad_name(&name));
+            }
+            if !is_field_descriptor(&descriptor) {
+                return Err(bad_descriptor(&descriptor));
+            }
+        }
+        ConstantPoolInfo::Module(m) => {
+            cp.utf8(m.name_index)?;
+        }
+        ConstantPoolInfo::Package(p) => {
+            cp.utf8(p.name_index)?;
+        }
+    }
+    Ok(())
+}
+
+fn check_class(cf: &ClassFile) -> Result<()> {
+    let cp = &cf.constant_pool;
+    let flags = cf.access_flags;
+
+    let this_class = cp.class_name(cf.this_class)
+        .map_err(|e| e.within(Structure::ThisClass))?;
+    if this_class.starts_with('[') {
+        return Err(bad_name(&this_class).within(Structure::ThisClass));
+    }
+
+    if flags & ACC_MODULE != 0 {
+        if flags != ACC_MODULE {
+            return Err(illegal(flags, "module-info may not have other flags")
+                .within(Structure::AccessFlags));
+        }
+        if cf.super_class != 0 {
+            return Err(ClassFormatError::new(Reason::BadSuperClass).within(Structure::SuperClass));
+        }
+        return Ok(());
+    }
+
+    let is_interface = flags & ACC_INTERFACE != 0;
+    let flag_error = if is_interface {
+        if flags & ACC_ABSTRACT == 0 {
+            Some("interfaces must be abstract")
+        } else if flags & (ACC_FINAL | ACC_SUPER | ACC_ENUM) != 0 {
+            Some("interfaces may not be final, super or enum")
+        } else {
+            None
+        }
+    } else if flags & ACC_ANNOTATION != 0 {
+        Some("annotations must be interfaces")
+    } else if flags & ACC_FINAL != 0 && flags & ACC_ABSTRACT != 0 {
+        Some("classes may not be both final and abstract")
+    } else {
+        None
+    };
+    if let Some(why) = flag_error {
+        return Err(illegal(flags, why).within(Structure::AccessFlags));
+    }
+
+    if cf.super_class == 0 {
+        if this_class != "java/lang/Object" {
+            return Err(ClassFormatError::new(Reason::BadSuperClass).within(Structure::SuperClass));
+        }
+    } else {
+        let super_class = cp.class_name(cf.super_class)
+            .map_err(|e| e.within(Structure::SuperClass))?;
+        if super_class.starts_with('[') || (is_interface && super_class != "java/lang/Object") {
+            return Err(ClassFormatError::new(Reason::BadSuperClass).within(Structure::SuperClass));
+        }
+    }
+
+    for (i, interface) in cf.interfaces.iter().enumerate() {
+        cp.class(*interface).map_err(|e| e.within(Structure::Interface(i as u16)))?;
+    }
+    Ok(())
+}
+
+fn check_field(cf: &ClassFile, f: &FieldInfo, seen: &mut HashSet<(String, String)>) -> Result<()> {
+    let cp = &cf.constant_pool;
+    let flags = f.access_flags;
+
+    let name = cp.utf8(f.name_index)?;
+    if !is_unqualified_name(&name) {
+        return Err(bad_name(&name));
+    }
+    let descriptor = cp.utf8(f.descriptor_index)?;
+    if !is_field_descriptor(&descriptor) {
+        return Err(bad_descriptor(&descriptor));
+    }
+
+    check_visibility(flags)?;
+    if flags & ACC_FINAL != 0 && flags & ACC_VOLATILE != 0 {
+        return Err(illegal(flags, "fields may not be both final and volatile"));
+    }
+    if cf.access_flags & ACC_INTERFACE != 0 {
+        let required = ACC_PUBLIC | ACC_STATIC | ACC_FINAL;
+        if flags & required != required || flags & !(required | ACC_SYNTHETIC) != 0 {
+            return Err(illegal(flags, "interface fields must be public static final"));
+        }
+    }
+
+    if !seen.insert((name.clone(), descriptor.clone())) {
+        return Err(ClassFormatError::new(Reason::Duplicate(format!("field {name}:{descriptor}"))));
+    }
+    Ok(())
+}
+
+fn check_method(cf: &ClassFile, m: &MethodInfo, seen: &mut HashSet<(String, String)>) -> Result<()> {
+    let cp = &cf.constant_pool;
+    let version = cf.version();
+    let flags = m.access_flags;
+    let is_interface = cf.access_flags & ACC_INTERFACE != 0;
+
+    let name = cp.utf8(m.name_index)?;
+    if !is_method_name(&name) {
+        return Err(bad_name(&name));
+    }
+    let descriptor = cp.utf8(m.descriptor_index)?;
+    let receiver = if flags & ACC_STATIC == 0 { 1 } else { 0 };
+    match arg_slots(&descriptor) {
+        Some(slots) if slots + receiver <= 255 => {}
+        _ => return Err(bad_descriptor(&descriptor)),
+    }
+
+    if !seen.insert((name.clone(), descriptor.clone())) {
+        return Err(ClassFormatError::new(Reason::Duplicate(format!("method {name}{descriptor}"))));
+    }
+
+    // flags on class initializers are ignored, apart from static from java 7 on
+    if name == "<clinit>" {
+        if descriptor != "()V" {
+            return Err(bad_descriptor(&descriptor));
+        }
+        if version.major >= 51 && flags & ACC_STATIC == 0 {
+            return Err(illegal(flags, "<clinit> must be static"));
+        }
+        return Ok(());
+    }
+
+    check_visibility(flags)?;
+
+    if name == "<init>" {
+        if is_interface {
+            return Err(bad_name(&name));
+        }
+        if !descriptor.ends_with(")V") {
+            return Err(bad_descriptor(&descriptor));
+        }
+        let allowed = ACC_PUBLIC | ACC_PRIVATE | ACC_PROTECTED | ACC_VARARGS | ACC_STRICT | ACC_SYNTHETIC;
+        if flags & !allowed != 0 {
+            return Err(illegal(flags, "constructors may only be public, private, protected, varargs, strict or synthetic"));
+        }
+        return Ok(());
+    }
+
+    if is_interface {
+        if version.major < 52 {
+            if flags & (ACC_PUBLIC | ACC_ABSTRACT) != ACC_PUBLIC | ACC_ABSTRACT {
+                return Err(illegal(flags, "interface methods must be public and abstract before java 8"));
+            }
+        } else if (flags & (ACC_PUBLIC | ACC_PRIVATE)).count_ones() != 1 {
+            return Err(illegal(flags, "interface methods must be exactly one of public and private"));
+        }
+        if flags & (ACC_PROTECTED | ACC_FINAL | ACC_SYNCHRONIZED | ACC_NATIVE) != 0 {
+            return Err(illegal(flags, "interface methods may not be protected, final, synchronized or native"));
+        }
+    }
+
+    if flags & ACC_ABSTRACT != 0 {
+        let mut forbidden = ACC_PRIVATE | ACC_STATIC | ACC_FINAL | ACC_SYNCHRONIZED | ACC_NATIVE;
+        // strictfp meant something between java 1.2 and 16
+        if (46..=60).contains(&version.major) {
+            forbidden |= ACC_STRICT;
+        }
+        if flags & forbidden != 0 {
+            return Err(illegal(flags, "abstract methods may not be private, static, final, synchronized, native or strict"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::class_reader::ClassReader;
+
+    // `public class A { public void m() {} }`, without a Code attribute
+    const CLASS: &[u8] = &[
+        0xca, 0xfe, 0xba, 0xbe, 0x00, 0x00, 0x00, 0x34,
+        0x00, 0x07,
+        0x01, 0x00, 0x01, b'A',
+        0x07, 0x00, 0x01,
+        0x01, 0x00, 0x10, b'j', b'a', b'v', b'a', b'/', b'l', b'a', b'n', b'g', b'/',
+        b'O', b'b', b'j', b'e', b'c', b't',
+        0x07, 0x00, 0x03,
+        0x01, 0x00, 0x01, b'm',
+        0x01, 0x00, 0x03, b'(', b')', b'V',
+        0x00, 0x21, 0x00, 0x02, 0x00, 0x04,
+        0x00, 0x00,
+        0x00, 0x00,
+        0x00, 0x01,
+        0x00, 0x01, 0x00, 0x05, 0x00, 0x06, 0x00, 0x00,
+        0x00, 0x00,
+    ];
+
+    fn check_bytes(bytes: &[u8]) -> Result<()> {
+        check(&ClassReader::from_bytes(bytes).read_classfile().unwrap())
+    }
+
+    #[test]
+    fn valid() {
+        check_bytes(CLASS).unwrap();
+    }
+
+    #[test]
+    fn interface_not_abstract() {
+        let mut bytes = CLASS.to_vec();
+        bytes[49] = 0x02;
+        let e = check_bytes(&bytes).unwrap_err();
+        assert_eq!(e.reason, Reason::IllegalFlags { flags: 0x0221, why: "interfaces must be abstract" });
+        assert_eq!(e.context, vec![Structure::AccessFlags]);
+    }
+
+    #[test]
+    fn final_abstract_method() {
+        let mut bytes = CLASS.to_vec();
+        bytes[61] = 0x04;
+        bytes[62] = 0x11;
+        let e = check_bytes(&bytes).unwrap_err();
+        assert!(matches!(e.reason, Reason::IllegalFlags { flags: 0x0411, .. }));
+        assert_eq!(e.context, vec![Structure::Method(0)]);
+    }
+
+    #[test]
+    fn bad_descriptor() {
+        let mut bytes = CLASS.to_vec();
+        bytes[48] = b'X';
+        let e = check_bytes(&bytes).unwrap_err();
+        assert_eq!(e.reason, Reason::BadDescriptor("()X".to_string()));
+    }
+
+    #[test]
+    fn wrong_kind() {
+        let mut bytes = CLASS.to_vec();
+        // this_class points at a Utf8
+        bytes[52] = 0x01;
+        let e = check_bytes(&bytes).unwrap_err();
+        assert_eq!(e.reason, Reason::WrongKind { index: 1, expected: "Class" });
+        assert_eq!(e.context, vec![Structure::ThisClass]);
+    }
+
+    #[test]
+    fn duplicate_method() {
+        let mut bytes = CLASS[..CLASS.len() - 2].to_vec();
+        bytes[60] = 0x02;
+        bytes.extend_from_slice(&[0x00, 0x01, 0x00, 0x05, 0x00, 0x06, 0x00, 0x00]);
+        bytes.extend_from_slice(&[0x00, 0x00]);
+        let e = check_bytes(&bytes).unwrap_err();
+        assert_eq!(e.reason, Reason::Duplicate("method m()V".to_string()));
+        assert_eq!(e.context, vec![Structure::Method(1)]);
+    }
+}
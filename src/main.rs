@@ -10,19 +10,30 @@ extern crate core;
 use crate::class::JavaClass;
 use crate::class_reader::ClassReader;
 use crate::runtime::Runtime;
+use crate::settings::{Settings, Verify};
 
+pub mod assembler;
 pub mod attributes;
 pub mod bytecode;
+pub mod cfg;
 pub mod class;
 pub mod class_file;
 pub mod class_reader;
+pub mod class_version;
+pub mod class_writer;
 pub mod constant_pool;
+pub mod cp_cache;
 pub mod descriptor;
+pub mod disassembler;
 pub mod field_info;
+pub mod format_check;
 pub mod method;
 pub mod method_info;
+pub mod mutf8;
+pub mod peephole;
 pub mod runtime;
 pub mod values;
+pub mod verifier;
 pub mod heap;
 pub mod base_classes;
 pub mod stack;
@@ -40,16 +51,63 @@ macro_rules! os_str {
 }
 
 #[cfg(not(feature = "multithreaded"))]
-fn main() -> Result<(), std::io::Error> {
-    let args: Vec<String> = std::env::args().collect();
+fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
     if args.len() < 2 {
         panic!("argument needed")
     }
 
-    let mut runtime = Runtime::new(args[1].clone())?;
+    if args[1] == "disassemble" {
+        disassemble(args.get(2).expect("class file argument needed"));
+        return;
+    }
+
+    let mut settings = Settings::default();
+    while args.len() > 2 && args[1].starts_with('-') {
+        match &*args.remove(1) {
+            "-Xverify:none" => settings.verify = Verify::None,
+            "-Xverify:remote" => settings.verify = Verify::Untrusted,
+            "-Xverify:all" => settings.verify = Verify::All,
+            "-XX:+Peephole" => settings.peephole = true,
+            "-XX:-Peephole" => settings.peephole = false,
+            option => panic!("unknown option {option}"),
+        }
+    }
+
+    let mut runtime = match Runtime::with_settings(args[1].clone(), settings) {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Error: could not load main class from {}", args[1]);
+            eprintln!("Caused by: {e}");
+            std::process::exit(1)
+        }
+    };
 
     let _ = &mut runtime.run_main();
-    Ok(())
+}
+
+/// print the class or `.j` file at `path` in a `javap`-like format
+fn disassemble(path: &str) {
+    let class_file = if path.ends_with(".j") {
+        std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|source| assembler::assemble(&source)
+                .map_err(|e| format!("could not assemble: {e}")))
+    } else {
+        ClassReader::new(path)
+            .map_err(|e| e.to_string())
+            .and_then(|mut reader| reader.read_classfile()
+                .map_err(|e| format!("java.lang.ClassFormatError: {e}")))
+    };
+
+    match class_file {
+        Ok(cf) => print!("{}", disassembler::disassemble(&cf)),
+        Err(e) => {
+            eprintln!("Error: could not read {path}");
+            eprintln!("Caused by: {e}");
+            std::process::exit(1)
+        }
+    }
 }
 
 pub trait OptionAssert {
@@ -1,15 +1,18 @@
 use std::cmp::Ordering;
 use crate::attributes::code::Code;
-use crate::attributes::code_reader::CodeReader;
-use crate::constant_pool::ConstantPoolInfo;
+use crate::attributes::attribute::Attribute;
+use crate::attributes::code_reader::{CodeParseError, CodeReader};
+use crate::class_reader::{ClassFormatError, Reason, Structure};
+use crate::class_version::ClassVersion;
+use crate::constant_pool::ConstantPool;
 use crate::method_info::MethodInfo;
+use crate::class::Class;
+use crate::cp_cache::find_method;
 use crate::stack::{Stack};
 use crate::values::Value;
 use crate::{descriptor, JavaClass, Runtime};
-use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
-use std::process::exit;
-use std::ptr::null_mut;
+use std::ptr::{addr_of, addr_of_mut, null_mut};
 use crate::bytecode::Instruction;
 
 use crate::descriptor::{DescriptorInfo, Type};
@@ -21,24 +24,56 @@ pub enum Method {
 }
 
 impl Method {
-    pub fn from_info(cp: &Vec<ConstantPoolInfo>, mi: &MethodInfo) -> Method {
-        Method::Java(JavaMethod::from_info(cp, mi))
+    pub fn from_info(cp: &ConstantPool, version: ClassVersion, mi: &MethodInfo) -> Result<Method, ClassFormatError> {
+        Ok(Method::Java(JavaMethod::from_info(cp, version, mi)?))
     }
 
     pub fn exec(
         &self,
         runtime: &mut Runtime,
         class: *mut JavaClass,
-    ) -> () {
+    ) -> Option<Value> {
         match self {
             Method::Native(m) => {
                 let f = &m.func;
-                f.call((m, runtime, class));
+                f.call((m, runtime, class, &[]))
             },
-            Method::Java(m) => {
-                m.exec(runtime, class);
-            }
-        };
+            Method::Java(m) => m.exec(runtime, class),
+        }
+    }
+
+    pub fn access_flags(&self) -> u16 {
+        match self {
+            Method::Native(m) => m.access_flags,
+            Method::Java(m) => m.access_flags,
+        }
+    }
+
+    pub fn is_static(&self) -> bool {
+        self.access_flags() & 0x0008 != 0
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.access_flags() & 0x0002 != 0
+    }
+
+    pub fn is_final(&self) -> bool {
+        self.access_flags() & 0x0010 != 0
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Method::Native(m) => &m.name,
+            Method::Java(m) => &m.name,
+        }
+    }
+
+    /// the descriptor as it is written in the class file
+    pub fn descriptor_string(&self) -> &str {
+        match self {
+            Method::Native(m) => &m.descriptor,
+            Method::Java(m) => &m.descriptor,
+        }
     }
 
     pub fn descriptor(&self) -> &DescriptorInfo {
@@ -54,7 +89,9 @@ pub struct NativeMethod {
     pub access_flags: u16,
     pub descriptor: String,
     pub parsed_descriptor: DescriptorInfo,
-    pub func: Box<dyn Fn(&NativeMethod, &mut Runtime, *mut JavaClass) -> Option<Value>>,
+    /// called with the class of the caller, and the receiver and arguments laid out
+    /// like they would be in locals
+    pub func: Box<dyn Fn(&NativeMethod, &mut Runtime, *mut JavaClass, &[Value]) -> Option<Value>>,
 }
 
 impl Debug for NativeMethod {
@@ -76,48 +113,48 @@ pub struct JavaMethod {
     pub access_flags: u16,
     pub descriptor: String,
     pub parsed_descriptor: DescriptorInfo,
-    pub attributes: HashMap<String, Vec<u8>>,
+    pub attributes: Vec<Attribute>,
     pub code: Option<Code>,
 }
 
 impl JavaMethod {
-    pub fn from_info(cp: &Vec<ConstantPoolInfo>, mi: &MethodInfo) -> JavaMethod {
-        let desc = cp[(mi.descriptor_index - 1) as usize]
-            .utf8().expect("bad utf8 for method descriptor");
+    pub fn from_info(cp: &ConstantPool, version: ClassVersion, mi: &MethodInfo) -> Result<JavaMethod, ClassFormatError> {
+        let desc = cp.utf8(mi.descriptor_index)?;
         let mut m = JavaMethod {
-            name: cp[(mi.name_index - 1) as usize]
-                .utf8()
-                .expect("bad utf8 for method name"),
+            name: cp.utf8(mi.name_index)?,
             access_flags: mi.access_flags,
-            attributes: HashMap::new(),
+            attributes: vec![],
             code: None,
-            parsed_descriptor: descriptor::info(&*desc),
+            parsed_descriptor: descriptor::info(&desc)
+                .ok_or_else(|| ClassFormatError::new(Reason::BadDescriptor(desc.clone())))?,
             descriptor: desc,
         };
         for ai in &mi.attributes {
-            m.attributes.insert(
-                cp[(ai.name_index - 1) as usize].utf8().expect(""),
-                ai.info.clone(),
-            );
-        }
-        if let Some(code) = m.attributes.get("Code") {
-            m.code = Some(CodeReader::new(code).read_code().expect("code read fail"))
+            if cp.utf8(ai.name_index)? == "Code" {
+                m.code = Some(CodeReader::new(&ai.info, cp, version).read_code().map_err(|e| match e {
+                    CodeParseError::BadAttribute(e) => *e,
+                    e => ClassFormatError::new(Reason::BadCode(e)),
+                }.within(Structure::Attribute("Code".to_string())))?)
+            } else {
+                m.attributes.push(Attribute::from_info(cp, ai)?);
+            }
         }
-        m
+        Ok(m)
     }
 
     pub fn is_static(&self) -> bool {
-        self.access_flags | 8 != 0
+        self.access_flags & 0x0008 != 0
     }
 
     // new exec impl that doesnt use the rust stack for the java stack
     // this lets us define a stack size and handle exceptions much more easily
+    // returns what the method returned
     pub fn exec(
         &self,
-        _runtime: &mut Runtime,
+        runtime: &mut Runtime,
         class: *mut JavaClass,
-    ) {
-        let my_class = unsafe { &mut *class };
+    ) -> Option<Value> {
+        let my_class = unsafe { &*class };
         println!("{}.{}:{} called; code={:?}",
                  my_class.name, self.name, self.descriptor, self.code);
 
@@ -134,14 +171,154 @@ impl JavaMethod {
 
         // restore previous execution context
         // cant put it in a closure because borrow checker
+        // there are no exception objects yet, so throwing one ends the program
+        macro_rules! throw {
+            ($class:literal) => {
+                panic!("Exception in thread \"main\" {}", $class)
+            };
+            ($class:literal, $message:expr) => {
+                panic!("Exception in thread \"main\" {}: {}", $class, $message)
+            };
+        }
+
+        // resolve `$instruction` to its quick form. `method` and `code` point into the class,
+        // so only borrow the two fields quickening needs, never the whole class
+        macro_rules! resolve {
+            ($instruction:expr) => {{
+                let cp_cache = unsafe { &mut *addr_of_mut!((*class).cp_cache) };
+                let constant_pool = unsafe { &*addr_of!((*class).constant_pool) };
+                cp_cache.quicken($instruction, constant_pool, runtime).unwrap_or_else(|e| panic!("{e}"))
+            }};
+        }
+
+        // pop two operands with `$get`, and push what `$e` makes of them with `$new`
+        macro_rules! binary {
+            ($get:ident, $new:ident, |$a:ident, $b:ident| $e:expr) => {{
+                let $b = current_frame.pop().$get();
+                let $a = current_frame.pop().$get();
+                current_frame.push(Value::$new($e))
+            }};
+        }
+
+        // the same, for longs and doubles
+        macro_rules! binary_wide {
+            ($get:ident, $new:ident, |$a:ident, $b:ident| $e:expr) => {{
+                let $b = current_frame.pop_wide().$get();
+                let $a = current_frame.pop_wide().$get();
+                current_frame.push_wide(Value::$new($e))
+            }};
+        }
+
+        // pop `$n` slots, and push them back in the order given, counting down from 0 at
+        // the top of the stack. this is how the jvms describes pop, dup and swap
+        macro_rules! shuffle {
+            ($n:literal => [$($i:literal),*]) => {{
+                let mut slots = [Value::NULL; $n];
+                for slot in &mut slots {
+                    *slot = current_frame.pop();
+                }
+                $(current_frame.push(slots[$i]);)*
+            }};
+        }
+
+        // branch if `$cond` holds
+        macro_rules! branch_if {
+            ($cond:expr, $target:expr) => {
+                if $cond {
+                    pc = $target;
+                    continue
+                }
+            };
+        }
+
+        // compare two ints popped off the stack with `$op`, and branch if it holds
+        macro_rules! if_icmp {
+            ($op:tt, $target:expr) => {{
+                let b = current_frame.pop().int();
+                let a = current_frame.pop().int();
+                branch_if!(a $op b, $target)
+            }};
+        }
+
+        // push a value of type `$t`, taking two slots if it is a long or double
+        macro_rules! push_as {
+            ($t:expr, $val:expr) => {
+                if $t.slots() == 2 {
+                    current_frame.push_wide($val)
+                } else {
+                    current_frame.push($val)
+                }
+            };
+        }
+
+        // call `$method` of `$class`, with the receiver and arguments in the top `$arg_slots`
+        // slots of the stack. java methods get a frame of their own, and run until their
+        // return switches back to this one
+        macro_rules! invoke {
+            ($class:expr, $method:expr, $arg_slots:expr) => {
+                match unsafe { &*$method } {
+                    Method::Java(m) => {
+                        if m.code.is_none() {
+                            throw!("java.lang.AbstractMethodError", format!("{}.{}{}", unsafe { &*$class }.name(), m.name, m.descriptor))
+                        }
+                        let callee_class = unsafe { &mut *$class }.java_mut().expect("java method in a native class");
+                        let new_frame = frame_stack.call(m, callee_class);
+                        if new_frame.is_null() {
+                            throw!("java.lang.StackOverflowError")
+                        }
+                        let new_frame = unsafe { &mut *new_frame };
+                        for i in (0..$arg_slots).rev() {
+                            new_frame.set(i, current_frame.pop());
+                        }
+
+                        // where to come back to
+                        current_frame.program_counter = pc;
+
+                        current_frame = new_frame;
+                        method = m;
+                        code = m.code.as_ref().unwrap();
+                        class = callee_class;
+                        pc = 0;
+                        continue
+                    }
+                    Method::Native(m) => {
+                        let mut args = vec![Value::NULL; $arg_slots as usize];
+                        for arg in args.iter_mut().rev() {
+                            *arg = current_frame.pop();
+                        }
+                        if let Some(val) = (m.func)(m, runtime, class, &args) {
+                            push_as!(m.parsed_descriptor.ret, val)
+                        }
+                    }
+                }
+            };
+        }
+
+        // call the method with the same name and descriptor as `$resolved` that the class
+        // of `$receiver` has or inherits
+        macro_rules! invoke_by_name {
+            ($receiver:expr, $resolved:expr, $arg_slots:expr) => {{
+                let resolved = unsafe { &*$resolved };
+                let receiver_class = unsafe { &*$receiver }.class as *mut Class;
+                let (cls, callee) = unsafe { find_method(receiver_class, resolved.name(), resolved.descriptor_string()) }
+                    .unwrap_or_else(|| throw!("java.lang.AbstractMethodError", format!(
+                        "{}.{}{}",
+                        unsafe { &*receiver_class }.name(),
+                        resolved.name(),
+                        resolved.descriptor_string(),
+                    )));
+                invoke!(cls, callee, $arg_slots)
+            }};
+        }
+
         macro_rules! ret {
             () => {
                 unsafe {
                     // get our return value if we want it
-                    let return_value = if method.parsed_descriptor.ret != Type::Void {
-                        Some(current_frame.pop())
-                    } else {
-                        None
+                    let return_value = match method.parsed_descriptor.ret.slots() {
+                        0 => None,
+                        1 => Some(current_frame.pop()),
+                        _ => Some(current_frame.pop_wide()),
                     };
 
                     // pop the frame
@@ -153,16 +330,21 @@ impl JavaMethod {
                         // we are returning from main
                         println!("returning from main!");
                         if let Some(v) = return_value {
-                            println!("return value = {}", v.int());
+                            match method.parsed_descriptor.ret {
+                                Type::Long => println!("return value = {}", v.long()),
+                                Type::Float => println!("return value = {}", v.float()),
+                                Type::Double => println!("return value = {}", v.double()),
+                                _ => println!("return value = {}", v.int()),
+                            }
                         }
-                        exit(0);
+                        return return_value;
                     }
 
                     current_frame = &mut *new_frame;
 
                     // push return value
                     if let Some(val) = return_value {
-                        current_frame.push(val)
+                        push_as!(method.parsed_descriptor.ret, val)
                     }
 
                     // restore execution context
@@ -178,44 +360,376 @@ impl JavaMethod {
             // ultra diagnostic information!
             // dbg!(&current_frame);
 
-            let instruction = code.code[pc as usize];
+            let instruction = code.instruction(pc);
             println!("{pc}: {:?}", &instruction);
             match instruction {
                 Instruction::Return |
                 Instruction::Ireturn |
+                Instruction::Lreturn |
                 Instruction::Areturn |
                 Instruction::Freturn |
                 Instruction::Dreturn => ret!(),
+                Instruction::AconstNull => current_frame.push(Value::NULL),
                 Instruction::Ipush(x) => current_frame.push(Value::nint(x as i32)),
-                Instruction::Istore(n) => {
+                Instruction::Lconst(x) => current_frame.push_wide(Value::nlong(x as i64)),
+                Instruction::Fconst(x) => current_frame.push(Value::nfloat(x as f32)),
+                Instruction::Dconst(x) => current_frame.push_wide(Value::ndouble(x as f64)),
+                Instruction::Ldc(index) => {
+                    let cp = &unsafe { &*class }.constant_pool;
+                    match Value::try_from(cp.get(index as u16).unwrap().clone()) {
+                        Ok(value) => current_frame.push(value),
+                        Err(x) => throw!("java.lang.InternalError", format!("can't load {x:?} yet")),
+                    }
+                }
+                Instruction::Ldc2W(index) => {
+                    let cp = &unsafe { &*class }.constant_pool;
+                    match Value::try_from(cp.get(index).unwrap().clone()) {
+                        Ok(value) => current_frame.push_wide(value),
+                        Err(x) => throw!("java.lang.InternalError", format!("can't load {x:?} yet")),
+                    }
+                }
+                // return addresses from jsr are stored with astore
+                Instruction::Istore(n) | Instruction::Fstore(n) | Instruction::Astore(n) => {
                     let val = current_frame.pop();
                     current_frame.set(n, val)
                 }
-                Instruction::Iload(n) => {
+                Instruction::Lstore(n) | Instruction::Dstore(n) => {
+                    let val = current_frame.pop_wide();
+                    current_frame.set_wide(n, val)
+                }
+                Instruction::Iload(n) | Instruction::Fload(n) | Instruction::Aload(n) => {
                     let val = current_frame.get(n);
                     current_frame.push(val)
                 }
-                Instruction::IfIcmpge(branch) => {
-                    let value2 = current_frame.pop();
-                    let value1 = current_frame.pop();
-                    if value1.int() >= value2.int() {
-                        pc = branch;
-                        continue
+                Instruction::Lload(n) | Instruction::Dload(n) => {
+                    let val = current_frame.get(n);
+                    current_frame.push_wide(val)
+                }
+
+                Instruction::Pop => shuffle!(1 => []),
+                Instruction::Pop2 => shuffle!(2 => []),
+                Instruction::Dup => shuffle!(1 => [0, 0]),
+                Instruction::DupX1 => shuffle!(2 => [0, 1, 0]),
+                Instruction::DupX2 => shuffle!(3 => [0, 2, 1, 0]),
+                Instruction::Dup2 => shuffle!(2 => [1, 0, 1, 0]),
+                Instruction::Dup2X1 => shuffle!(3 => [1, 0, 2, 1, 0]),
+                Instruction::Dup2X2 => shuffle!(4 => [1, 0, 3, 2, 1, 0]),
+                Instruction::Swap => shuffle!(2 => [0, 1]),
+
+                // int and long arithmetic wraps around on overflow, and division only fails
+                // on zero: MIN_VALUE / -1 is MIN_VALUE, and MIN_VALUE % -1 is 0
+                Instruction::Iadd => binary!(int, nint, |a, b| a.wrapping_add(b)),
+                Instruction::Ladd => binary_wide!(long, nlong, |a, b| a.wrapping_add(b)),
+                Instruction::Isub => binary!(int, nint, |a, b| a.wrapping_sub(b)),
+                Instruction::Lsub => binary_wide!(long, nlong, |a, b| a.wrapping_sub(b)),
+                Instruction::Imul => binary!(int, nint, |a, b| a.wrapping_mul(b)),
+                Instruction::Lmul => binary_wide!(long, nlong, |a, b| a.wrapping_mul(b)),
+                Instruction::Idiv | Instruction::Irem => {
+                    let b = current_frame.pop().int();
+                    let a = current_frame.pop().int();
+                    if b == 0 {
+                        throw!("java.lang.ArithmeticException", "/ by zero")
                     }
+                    current_frame.push(Value::nint(if instruction == Instruction::Idiv {
+                        a.wrapping_div(b)
+                    } else {
+                        a.wrapping_rem(b)
+                    }))
+                }
+                Instruction::Ldiv | Instruction::Lrem => {
+                    let b = current_frame.pop_wide().long();
+                    let a = current_frame.pop_wide().long();
+                    if b == 0 {
+                        throw!("java.lang.ArithmeticException", "/ by zero")
+                    }
+                    current_frame.push_wide(Value::nlong(if instruction == Instruction::Ldiv {
+                        a.wrapping_div(b)
+                    } else {
+                        a.wrapping_rem(b)
+                    }))
+                }
+                Instruction::Ineg => {
+                    let a = current_frame.pop().int();
+                    current_frame.push(Value::nint(a.wrapping_neg()))
+                }
+                Instruction::Lneg => {
+                    let a = current_frame.pop_wide().long();
+                    current_frame.push_wide(Value::nlong(a.wrapping_neg()))
+                }
+                // shift counts only use their low 5 bits for ints, and 6 for longs.
+                // the wrapping shifts mask them the same way
+                Instruction::Ishl => binary!(int, nint, |a, b| a.wrapping_shl(b as u32)),
+                Instruction::Ishr => binary!(int, nint, |a, b| a.wrapping_shr(b as u32)),
+                Instruction::Iushr => binary!(int, nint, |a, b| (a as u32).wrapping_shr(b as u32) as i32),
+                Instruction::Lshl | Instruction::Lshr | Instruction::Lushr => {
+                    let b = current_frame.pop().int() as u32;
+                    let a = current_frame.pop_wide().long();
+                    current_frame.push_wide(Value::nlong(match instruction {
+                        Instruction::Lshl => a.wrapping_shl(b),
+                        Instruction::Lshr => a.wrapping_shr(b),
+                        _ => (a as u64).wrapping_shr(b) as i64,
+                    }))
+                }
+                Instruction::Iand => binary!(int, nint, |a, b| a & b),
+                Instruction::Land => binary_wide!(long, nlong, |a, b| a & b),
+                Instruction::Ior => binary!(int, nint, |a, b| a | b),
+                Instruction::Lor => binary_wide!(long, nlong, |a, b| a | b),
+                Instruction::Ixor => binary!(int, nint, |a, b| a ^ b),
+                Instruction::Lxor => binary_wide!(long, nlong, |a, b| a ^ b),
+                Instruction::Iinc(var, by) => {
+                    let old = current_frame.get(var).int();
+                    current_frame.set(var, Value::nint(old.wrapping_add(by as i32)))
+                }
+                Instruction::Lcmp => {
+                    let b = current_frame.pop_wide().long();
+                    let a = current_frame.pop_wide().long();
+                    current_frame.push(Value::nint(a.cmp(&b).into_int()))
+                }
+
+                // floating point follows IEEE 754, which rust does too. `%` is C's fmod,
+                // which is what frem and drem want, rather than the IEEE remainder
+                Instruction::Fadd => binary!(float, nfloat, |a, b| a + b),
+                Instruction::Dadd => binary_wide!(double, ndouble, |a, b| a + b),
+                Instruction::Fsub => binary!(float, nfloat, |a, b| a - b),
+                Instruction::Dsub => binary_wide!(double, ndouble, |a, b| a - b),
+                Instruction::Fmul => binary!(float, nfloat, |a, b| a * b),
+                Instruction::Dmul => binary_wide!(double, ndouble, |a, b| a * b),
+                Instruction::Fdiv => binary!(float, nfloat, |a, b| a / b),
+                Instruction::Ddiv => binary_wide!(double, ndouble, |a, b| a / b),
+                Instruction::Frem => binary!(float, nfloat, |a, b| a % b),
+                Instruction::Drem => binary_wide!(double, ndouble, |a, b| a % b),
+                Instruction::Fneg => {
+                    let a = current_frame.pop().float();
+                    current_frame.push(Value::nfloat(-a))
+                }
+                Instruction::Dneg => {
+                    let a = current_frame.pop_wide().double();
+                    current_frame.push_wide(Value::ndouble(-a))
+                }
+                // the l variants take NaN as less than everything, and the g variants as greater
+                Instruction::Fcmpl | Instruction::Fcmpg => {
+                    let b = current_frame.pop().float();
+                    let a = current_frame.pop().float();
+                    let nan = if instruction == Instruction::Fcmpl { -1 } else { 1 };
+                    current_frame.push(Value::nint(a.partial_cmp(&b).map_or(nan, IntoInt::into_int)))
+                }
+                Instruction::Dcmpl | Instruction::Dcmpg => {
+                    let b = current_frame.pop_wide().double();
+                    let a = current_frame.pop_wide().double();
+                    let nan = if instruction == Instruction::Dcmpl { -1 } else { 1 };
+                    current_frame.push(Value::nint(a.partial_cmp(&b).map_or(nan, IntoInt::into_int)))
+                }
+
+                // `as` rounds to nearest when converting to floating point, and saturates
+                // when converting from it, with NaN becoming 0, just like java's casts
+                Instruction::I2l => {
+                    let a = current_frame.pop().int();
+                    current_frame.push_wide(Value::nlong(a as i64))
+                }
+                Instruction::I2f => {
+                    let a = current_frame.pop().int();
+                    current_frame.push(Value::nfloat(a as f32))
+                }
+                Instruction::I2d => {
+                    let a = current_frame.pop().int();
+                    current_frame.push_wide(Value::ndouble(a as f64))
+                }
+                Instruction::L2i => {
+                    let a = current_frame.pop_wide().long();
+                    current_frame.push(Value::nint(a as i32))
+                }
+                Instruction::L2f => {
+                    let a = current_frame.pop_wide().long();
+                    current_frame.push(Value::nfloat(a as f32))
+                }
+                Instruction::L2d => {
+                    let a = current_frame.pop_wide().long();
+                    current_frame.push_wide(Value::ndouble(a as f64))
+                }
+                Instruction::F2i => {
+                    let a = current_frame.pop().float();
+                    current_frame.push(Value::nint(a as i32))
+                }
+                Instruction::F2l => {
+                    let a = current_frame.pop().float();
+                    current_frame.push_wide(Value::nlong(a as i64))
+                }
+                Instruction::F2d => {
+                    let a = current_frame.pop().float();
+                    current_frame.push_wide(Value::ndouble(a as f64))
+                }
+                Instruction::D2i => {
+                    let a = current_frame.pop_wide().double();
+                    current_frame.push(Value::nint(a as i32))
+                }
+                Instruction::D2l => {
+                    let a = current_frame.pop_wide().double();
+                    current_frame.push_wide(Value::nlong(a as i64))
+                }
+                Instruction::D2f => {
+                    let a = current_frame.pop_wide().double();
+                    current_frame.push(Value::nfloat(a as f32))
+                }
+                Instruction::I2b => {
+                    let a = current_frame.pop().int();
+                    current_frame.push(Value::nint(a as i8 as i32))
+                }
+                Instruction::I2c => {
+                    let a = current_frame.pop().int();
+                    current_frame.push(Value::nint(a as u16 as i32))
                 }
-                Instruction::Goto(addr) => {
+                Instruction::I2s => {
+                    let a = current_frame.pop().int();
+                    current_frame.push(Value::nint(a as i16 as i32))
+                }
+
+                Instruction::Ifeq(branch) => branch_if!(current_frame.pop().int() == 0, branch),
+                Instruction::Ifne(branch) => branch_if!(current_frame.pop().int() != 0, branch),
+                Instruction::Iflt(branch) => branch_if!(current_frame.pop().int() < 0, branch),
+                Instruction::Ifge(branch) => branch_if!(current_frame.pop().int() >= 0, branch),
+                Instruction::Ifgt(branch) => branch_if!(current_frame.pop().int() > 0, branch),
+                Instruction::Ifle(branch) => branch_if!(current_frame.pop().int() <= 0, branch),
+                Instruction::IfIcmpeq(branch) => if_icmp!(==, branch),
+                Instruction::IfIcmpne(branch) => if_icmp!(!=, branch),
+                Instruction::IfIcmplt(branch) => if_icmp!(<, branch),
+                Instruction::IfIcmpge(branch) => if_icmp!(>=, branch),
+                Instruction::IfIcmpgt(branch) => if_icmp!(>, branch),
+                Instruction::IfIcmple(branch) => if_icmp!(<=, branch),
+                Instruction::Goto(addr) | Instruction::GotoW(addr) => {
                     pc = addr;
                     continue
                 }
-                Instruction::Imul => {
-                    let value1 = current_frame.pop().int();
-                    let value2 = current_frame.pop().int();
-                    current_frame.push(Value::nint(value1 * value2))
+                Instruction::Jsr(addr) | Instruction::JsrW(addr) => {
+                    current_frame.push(Value::nreturn_address(pc + 1));
+                    pc = addr;
+                    continue
                 }
-                Instruction::Iinc(var, by) => {
-                    let old = current_frame.get(var as u16).int();
-                    let new = old + (by as i32);
-                    current_frame.set(var as u16, Value::nint(new))
+                Instruction::Ret(n) => {
+                    pc = current_frame.get(n).return_address();
+                    continue
+                }
+                Instruction::Tableswitch(n) | Instruction::Lookupswitch(n) => {
+                    let key = current_frame.pop().int();
+                    pc = code.switches[n as usize].target(key);
+                    continue
+                }
+                // resolve the constant pool entry the first time, then run the quick form instead
+                Instruction::Getstatic(_) | Instruction::Putstatic(_) |
+                Instruction::Getfield(_) | Instruction::Putfield(_) |
+                Instruction::Invokevirtual(_) | Instruction::Invokespecial(_) |
+                Instruction::Invokestatic(_) | Instruction::Invokeinterface(..) => {
+                    code.quicken(pc, resolve!(instruction));
+                    continue
+                }
+                Instruction::GetstaticQuick(n) => {
+                    let field = unsafe { &*class }.cp_cache.static_field(n);
+                    let field = unsafe { &*field };
+                    if field.is_wide() {
+                        current_frame.push_wide(field.get_static())
+                    } else {
+                        current_frame.push(field.get_static())
+                    }
+                }
+                Instruction::PutstaticQuick(n) => {
+                    let field = unsafe { &*class }.cp_cache.static_field(n);
+                    let field = unsafe { &mut *field };
+                    let val = if field.is_wide() { current_frame.pop_wide() } else { current_frame.pop() };
+                    field.set_static(val)
+                }
+                Instruction::GetfieldQuick(n) => {
+                    let (offset, wide) = unsafe { &*class }.cp_cache.field_offset(n);
+                    let obj = current_frame.pop().object();
+                    if obj.is_null() {
+                        throw!("java.lang.NullPointerException")
+                    }
+                    let val = unsafe { &*obj }.get(offset);
+                    if wide { current_frame.push_wide(val) } else { current_frame.push(val) }
+                }
+                Instruction::PutfieldQuick(n) => {
+                    let (offset, wide) = unsafe { &*class }.cp_cache.field_offset(n);
+                    let val = if wide { current_frame.pop_wide() } else { current_frame.pop() };
+                    let obj = current_frame.pop().object();
+                    if obj.is_null() {
+                        throw!("java.lang.NullPointerException")
+                    }
+                    unsafe { &mut *obj }.set(offset, val)
+                }
+                Instruction::InvokestaticQuick(n) => {
+                    let (cls, callee, arg_slots) = unsafe { &*class }.cp_cache.method(n);
+                    invoke!(cls, callee, arg_slots)
+                }
+                Instruction::InvokespecialQuick(n) => {
+                    let (cls, callee, arg_slots) = unsafe { &*class }.cp_cache.method(n);
+                    if current_frame.peek(arg_slots - 1).is_nullptr() {
+                        throw!("java.lang.NullPointerException")
+                    }
+                    invoke!(cls, callee, arg_slots)
+                }
+                // the method to run is the one in the same vtable slot of the receiver's class
+                Instruction::InvokevirtualQuick(n) => {
+                    let cache = &unsafe { &*class }.cp_cache;
+                    let (cls, callee, arg_slots) = cache.method(n);
+                    let receiver = current_frame.peek(arg_slots - 1).object();
+                    if receiver.is_null() {
+                        throw!("java.lang.NullPointerException")
+                    }
+                    match cache.vtable_index(n) {
+                        Some(i) => {
+                            let (cls, callee) = unsafe { &*(&*receiver).class }.vtable()[i as usize];
+                            invoke!(cls, callee, arg_slots)
+                        }
+                        // private methods can't be overridden
+                        None if unsafe { &*callee }.is_private() => invoke!(cls, callee, arg_slots),
+                        // and default methods have no slot, so they are looked up by name
+                        None => invoke_by_name!(receiver, callee, arg_slots),
+                    }
+                }
+                Instruction::InvokeinterfaceQuick(n, _) => {
+                    let (_, callee, arg_slots) = unsafe { &*class }.cp_cache.method(n);
+                    let receiver = current_frame.peek(arg_slots - 1).object();
+                    if receiver.is_null() {
+                        throw!("java.lang.NullPointerException")
+                    }
+                    invoke_by_name!(receiver, callee, arg_slots)
+                }
+                // superinstructions from the peephole optimizer
+                Instruction::IaddLocals(a, b, to) => {
+                    let sum = current_frame.get(a).int().wrapping_add(current_frame.get(b).int());
+                    current_frame.set(to, Value::nint(sum))
+                }
+                Instruction::IsubLocals(a, b, to) => {
+                    let difference = current_frame.get(a).int().wrapping_sub(current_frame.get(b).int());
+                    current_frame.set(to, Value::nint(difference))
+                }
+                Instruction::ImulLocals(a, b, to) => {
+                    let product = current_frame.get(a).int().wrapping_mul(current_frame.get(b).int());
+                    current_frame.set(to, Value::nint(product))
+                }
+                Instruction::IfIcmpLocals(condition, a, b, branch) => {
+                    if condition.test(current_frame.get(a).int(), current_frame.get(b).int()) {
+                        pc = branch;
+                        continue
+                    }
+                }
+                Instruction::IfIcmpConst(condition, a, k, branch) => {
+                    if condition.test(current_frame.get(a).int(), k as i32) {
+                        pc = branch;
+                        continue
+                    }
+                }
+                Instruction::AloadGetfield(n, index) => {
+                    resolve!(Instruction::Getfield(index));
+                    code.quicken(pc, Instruction::AloadGetfieldQuick(n, index));
+                    continue
+                }
+                Instruction::AloadGetfieldQuick(n, index) => {
+                    let (offset, wide) = unsafe { &*class }.cp_cache.field_offset(index);
+                    let obj = current_frame.get(n).object();
+                    if obj.is_null() {
+                        throw!("java.lang.NullPointerException")
+                    }
+                    let val = unsafe { &*obj }.get(offset);
+                    if wide { current_frame.push_wide(val) } else { current_frame.push(val) }
                 }
                 x => { panic!("unimplemented instruction {:?}", x) }
             }
@@ -1000,3 +1514,118 @@ impl IntoInt for Ordering {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+    use crate::class::{access_flags, NativeClass};
+    use crate::values::Object;
+
+    fn run(path: &str) -> Option<Value> {
+        Runtime::new(path.to_string()).unwrap().run_main()
+    }
+
+    #[test]
+    fn int_arithmetic() {
+        assert_eq!(run("test/jasmin/IntArith.j").unwrap().int(), 0);
+    }
+
+    #[test]
+    fn float_arithmetic() {
+        assert_eq!(run("test/jasmin/FloatArith.j").unwrap().int(), 0);
+    }
+
+    #[test]
+    fn category_2_values() {
+        assert_eq!(run("test/jasmin/Category2.j").unwrap().int(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "java.lang.ArithmeticException: / by zero")]
+    fn divide_by_zero() {
+        run("test/jasmin/DivideByZero.j");
+    }
+
+    #[test]
+    #[should_panic(expected = "java.lang.InternalError: can't load JString")]
+    fn ldc_string() {
+        run("test/jasmin/LdcString.j");
+    }
+
+    #[test]
+    fn invoke_static() {
+        assert_eq!(run("test/jasmin/Invoke.j").unwrap().int(), 0);
+        assert_eq!(run("test/jasmin/Call.j").unwrap().int(), 10);
+    }
+
+    #[test]
+    fn descriptor_with_paren() {
+        // ')' is allowed in class names, so the format check accepts this
+        let cf = crate::assembler::assemble(
+            ".class public T\n.super java/lang/Object\n\
+             .method static m(La)b;)V\n.limit stack 0\n.limit locals 1\nreturn\n.end method\n",
+        ).unwrap();
+        crate::format_check::check(&cf).unwrap();
+        let m = JavaMethod::from_info(&cf.constant_pool, cf.version(), &cf.methods[0]).unwrap();
+        assert_eq!(m.parsed_descriptor.args, vec![Type::Object(Box::from("a)b".to_string()))]);
+    }
+
+    /// add a native class `Natives`, with one static method
+    fn add_natives(
+        runtime: &mut Runtime,
+        name: &str,
+        descriptor: &str,
+        func: impl Fn(&NativeMethod, &mut Runtime, *mut JavaClass, &[Value]) -> Option<Value> + 'static,
+    ) {
+        let method = NativeMethod {
+            name: name.to_string(),
+            access_flags: 0x0009,
+            descriptor: descriptor.to_string(),
+            parsed_descriptor: descriptor::info(descriptor).unwrap(),
+            func: Box::new(func),
+        };
+        let object = runtime.get_class("java/lang/Object").unwrap();
+        runtime.add_native_class(NativeClass {
+            name: "Natives".to_string(),
+            access_flags: access_flags::PUBLIC,
+            super_class: object,
+            interfaces: vec![],
+            static_fields: Default::default(),
+            instance_fields: Default::default(),
+            methods: HashMap::from([((name.to_string(), descriptor.to_string()), Method::Native(method))]),
+            vtable: vec![],
+        });
+    }
+
+    #[test]
+    fn invoke_native() {
+        let mut runtime = Runtime::new("test/jasmin/NativeCall.j".to_string()).unwrap();
+        // the long takes locals 1 and 2
+        add_natives(&mut runtime, "sum", "(IJ)J", |_method, _runtime, _class, args| {
+            Some(Value::nlong(args[0].int() as i64 + args[1].long()))
+        });
+        assert_eq!(runtime.run_main().unwrap().long(), 21);
+    }
+
+    #[test]
+    fn invoke_virtual() {
+        let mut runtime = Runtime::new("test/jasmin/VDispatch.j".to_string()).unwrap();
+        for path in ["test/jasmin/VBase.j", "test/jasmin/VSub.j"] {
+            let cls = JavaClass::from_filename(path, &mut runtime).unwrap();
+            runtime.add_java_class(cls).unwrap();
+        }
+        let sub = runtime.get_class("VSub").unwrap();
+        // there is no `new` yet, so the object comes from here
+        add_natives(&mut runtime, "make", "()LVBase;", move |_method, _runtime, _class, _args| {
+            Some(Value::nobject(Box::into_raw(Box::new(Object { class: sub, fields: [] }))))
+        });
+        assert_eq!(runtime.run_main().unwrap().int(), 5213);
+    }
+
+    #[test]
+    #[should_panic(expected = "java.lang.StackOverflowError")]
+    fn stack_overflow() {
+        run("test/jasmin/Overflow.j");
+    }
+}
@@ -0,0 +1,115 @@
+//! Modified UTF-8, the string encoding used by `CONSTANT_Utf8` entries.
+//!
+//! It differs from standard UTF-8 in two ways: NUL is written as the two bytes
+//! `0xC0 0x80`, and characters outside the basic multilingual plane are written
+//! as a surrogate pair with each half encoded as its own three byte sequence.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    /// index of the byte that could not be decoded
+    pub position: usize,
+}
+
+/// decode modified UTF-8 into UTF-16 code units, the representation java strings use
+pub fn decode_utf16(bytes: &[u8]) -> Result<Vec<u16>, DecodeError> {
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    let continuation = |i: usize| -> Result<u16, DecodeError> {
+        match bytes.get(i) {
+            Some(b) if b & 0xc0 == 0x80 => Ok((b & 0x3f) as u16),
+            _ => Err(DecodeError { position: i }),
+        }
+    };
+
+    while i < bytes.len() {
+        let b = bytes[i];
+        let unit = match b {
+            0x01..=0x7f => {
+                i += 1;
+                b as u16
+            }
+            0xc0..=0xdf => {
+                let unit = ((b & 0x1f) as u16) << 6 | continuation(i + 1)?;
+                i += 2;
+                unit
+            }
+            0xe0..=0xef => {
+                let unit = ((b & 0x0f) as u16) << 12
+                    | continuation(i + 1)? << 6
+                    | continuation(i + 2)?;
+                i += 3;
+                unit
+            }
+            // NUL is never a single byte, and there are no four byte forms
+            _ => return Err(DecodeError { position: i }),
+        };
+        units.push(unit);
+    }
+
+    Ok(units)
+}
+
+/// decode modified UTF-8 into a rust string.
+/// unpaired surrogates, which java allows but rust does not, become U+FFFD
+pub fn decode(bytes: &[u8]) -> Result<String, DecodeError> {
+    // most names and descriptors are plain ascii, which is the same in both encodings
+    if bytes.iter().all(|b| (0x01..=0x7f).contains(b)) {
+        return Ok(bytes.iter().map(|&b| b as char).collect());
+    }
+    Ok(String::from_utf16_lossy(&decode_utf16(bytes)?))
+}
+
+/// encode UTF-16 code units as modified UTF-8
+pub fn encode_utf16(units: &[u16]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(units.len());
+    for &u in units {
+        match u {
+            0x0001..=0x007f => bytes.push(u as u8),
+            0x0000 | 0x0080..=0x07ff => {
+                bytes.push(0xc0 | (u >> 6) as u8);
+                bytes.push(0x80 | (u & 0x3f) as u8);
+            }
+            _ => {
+                bytes.push(0xe0 | (u >> 12) as u8);
+                bytes.push(0x80 | ((u >> 6) & 0x3f) as u8);
+                bytes.push(0x80 | (u & 0x3f) as u8);
+            }
+        }
+    }
+    bytes
+}
+
+/// encode a rust string as modified UTF-8
+pub fn encode(s: &str) -> Vec<u8> {
+    encode_utf16(&s.encode_utf16().collect::<Vec<u16>>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nul_and_supplementary() {
+        // "a\0😀", as javac writes it
+        let bytes = [0x61, 0xc0, 0x80, 0xed, 0xa0, 0xbd, 0xed, 0xb8, 0x80];
+        assert_eq!(decode(&bytes).unwrap(), "a\0😀");
+        assert_eq!(decode_utf16(&bytes).unwrap(), vec![0x61, 0, 0xd83d, 0xde00]);
+        assert_eq!(encode("a\0😀"), bytes.to_vec());
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(decode(&[0x61, 0x00]), Err(DecodeError { position: 1 }));
+        assert_eq!(decode(&[0xe2, 0x82]), Err(DecodeError { position: 2 }));
+        // four byte standard utf8 forms are not allowed
+        assert_eq!(decode("😀".as_bytes()), Err(DecodeError { position: 0 }));
+    }
+
+    #[test]
+    fn lone_surrogate() {
+        let bytes = [0xed, 0xa0, 0xbd];
+        assert_eq!(decode_utf16(&bytes).unwrap(), vec![0xd83d]);
+        assert_eq!(decode(&bytes).unwrap(), "\u{fffd}");
+    }
+}
@@ -0,0 +1,247 @@
+//! A peephole optimizer for loaded code.
+//!
+//! Every instruction the interpreter runs costs a trip through its `match`, so this fuses
+//! common sequences into superinstructions that do the work of several, drops `nop`s,
+//! and points branches past chains of `goto`s. It runs after verification, since the
+//! verifier only knows the instructions that class files can contain, and it leaves
+//! code that can no longer be written back to a class file.
+
+use std::collections::HashSet;
+use crate::attributes::attribute::Attribute;
+use crate::attributes::code::Code;
+use crate::bytecode::{Condition, Instruction, SwitchTable};
+
+/// optimize `code` in place
+pub fn optimize(code: &mut Code) {
+    skip_goto_chains(code);
+
+    let leaders = leaders(code);
+    let instructions = code.instructions();
+    let len = instructions.len();
+
+    // what each old instruction index becomes. fused instructions map to the start of
+    // their superinstruction, and dropped ones to whatever ends up after them
+    let mut new_index = Vec::with_capacity(len + 1);
+    let mut optimized = Vec::with_capacity(len);
+    let mut i = 0;
+    while i < len {
+        let (instruction, used) = match fuse(&instructions[i..]) {
+            // control can't enter the middle of a superinstruction
+            Some((fused, used)) if (i + 1..i + used).all(|j| !leaders.contains(&(j as u16))) => (Some(fused), used),
+            _ => match instructions[i] {
+                Instruction::Nop => (None, 1),
+                Instruction::Goto(t) | Instruction::GotoW(t)
+                    if t as usize > i && instructions[i + 1..t as usize].iter().all(|x| *x == Instruction::Nop) =>
+                    (None, 1),
+                x => (Some(x), 1),
+            },
+        };
+        new_index.extend(std::iter::repeat_n(optimized.len() as u16, used));
+        optimized.extend(instruction);
+        i += used;
+    }
+    new_index.push(optimized.len() as u16);
+
+    for instruction in &mut optimized {
+        if let Some(t) = target_mut(instruction) {
+            *t = new_index[*t as usize];
+        }
+    }
+    for table in &mut code.switches {
+        for t in switch_targets_mut(table) {
+            *t = new_index[*t as usize];
+        }
+    }
+    code.code = Code::cells(optimized);
+
+    // the frames were only needed for verification, and dropping instructions can leave
+    // two of them on one instruction
+    code.attributes.retain(|a| !matches!(a, Attribute::StackMapTable(_)));
+    code.map_positions(&|pc| new_index.get(pc as usize).copied())
+        .expect("every position maps to an instruction");
+}
+
+/// point every branch and switch at the end of the `goto` chain it leads to
+fn skip_goto_chains(code: &mut Code) {
+    let instructions = code.instructions();
+    let follow = |mut t: u16| {
+        // a loop of gotos never ends, so stop after visiting every instruction
+        for _ in 0..instructions.len() {
+            match instructions[t as usize] {
+                Instruction::Goto(next) | Instruction::GotoW(next) => t = next,
+                _ => break,
+            }
+        }
+        t
+    };
+
+    for cell in &code.code {
+        let mut instruction = cell.get();
+        if let Some(t) = target_mut(&mut instruction) {
+            *t = follow(*t);
+        }
+        cell.set(instruction);
+    }
+    for table in &mut code.switches {
+        for t in switch_targets_mut(table) {
+            *t = follow(*t);
+        }
+    }
+}
+
+/// every instruction that control can reach other than from the one before it,
+/// and every instruction that an exception range starts or ends at
+fn leaders(code: &Code) -> HashSet<u16> {
+    let mut leaders = HashSet::new();
+    for (i, instruction) in code.instructions().iter().enumerate() {
+        leaders.extend(instruction.branch_targets(code));
+        if matches!(instruction, Instruction::Jsr(_) | Instruction::JsrW(_)) {
+            leaders.insert(i as u16 + 1);
+        }
+    }
+    for item in &code.exception_table {
+        leaders.extend([item.start_pc, item.end_pc, item.handler_pc]);
+    }
+    leaders
+}
+
+/// the superinstruction that the start of `code` can be fused into, and how many
+/// instructions it replaces
+fn fuse(code: &[Instruction]) -> Option<(Instruction, usize)> {
+    use crate::bytecode::Instruction::*;
+
+    Some(match *code {
+        [Iload(a), Iload(b), Iadd, Istore(c), ..] => (IaddLocals(a, b, c), 4),
+        [Iload(a), Iload(b), Isub, Istore(c), ..] => (IsubLocals(a, b, c), 4),
+        [Iload(a), Iload(b), Imul, Istore(c), ..] => (ImulLocals(a, b, c), 4),
+        [Iload(a), Iload(b), branch, ..] => {
+            let (condition, t) = icmp(branch)?;
+            (IfIcmpLocals(condition, a, b, t), 3)
+        }
+        [Iload(a), Ipush(k), branch, ..] => {
+            let (condition, t) = icmp(branch)?;
+            (IfIcmpConst(condition, a, k, t), 3)
+        }
+        [Iload(a), branch, ..] => {
+            let (condition, t) = if_zero(branch)?;
+            (IfIcmpConst(condition, a, 0, t), 2)
+        }
+        [Aload(n), Getfield(index), ..] => (AloadGetfield(n, index), 2),
+        _ => return None,
+    })
+}
+
+/// the condition and target of an `if_icmp<cond>`
+fn icmp(instruction: Instruction) -> Option<(Condition, u16)> {
+    use crate::bytecode::Instruction::*;
+
+    Some(match instruction {
+        IfIcmpeq(t) => (Condition::Eq, t),
+        IfIcmpne(t) => (Condition::Ne, t),
+        IfIcmplt(t) => (Condition::Lt, t),
+        IfIcmpge(t) => (Condition::Ge, t),
+        IfIcmpgt(t) => (Condition::Gt, t),
+        IfIcmple(t) => (Condition::Le, t),
+        _ => return None,
+    })
+}
+
+/// the condition and target of an `if<cond>`, which compares against 0
+fn if_zero(instruction: Instruction) -> Option<(Condition, u16)> {
+    use crate::bytecode::Instruction::*;
+
+    Some(match instruction {
+        Ifeq(t) => (Condition::Eq, t),
+        Ifne(t) => (Condition::Ne, t),
+        Iflt(t) => (Condition::Lt, t),
+        Ifge(t) => (Condition::Ge, t),
+        Ifgt(t) => (Condition::Gt, t),
+        Ifle(t) => (Condition::Le, t),
+        _ => return None,
+    })
+}
+
+/// the branch target of an instruction, other than a switch
+fn target_mut(instruction: &mut Instruction) -> Option<&mut u16> {
+    use crate::bytecode::Instruction::*;
+
+    match instruction {
+        Ifeq(t) | Ifne(t) | Iflt(t) | Ifge(t) | Ifgt(t) | Ifle(t) |
+        IfIcmpeq(t) | IfIcmpne(t) | IfIcmplt(t) | IfIcmpge(t) | IfIcmpgt(t) | IfIcmple(t) |
+        IfAcmpeq(t) | IfAcmpne(t) | Ifnull(t) | Ifnonnull(t) |
+        Goto(t) | GotoW(t) | Jsr(t) | JsrW(t) |
+        IfIcmpLocals(.., t) | IfIcmpConst(.., t) => Some(t),
+        _ => None,
+    }
+}
+
+fn switch_targets_mut(table: &mut SwitchTable) -> Vec<&mut u16> {
+    match table {
+        SwitchTable::Table { default, targets, .. } =>
+            targets.iter_mut().chain(std::iter::once(default)).collect(),
+        SwitchTable::Lookup { default, pairs } =>
+            pairs.iter_mut().map(|(_, t)| t).chain(std::iter::once(default)).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attributes::code::ExceptionTableItem;
+    use crate::bytecode::Instruction::*;
+
+    fn code(code: Vec<Instruction>, switches: Vec<SwitchTable>, exception_table: Vec<ExceptionTableItem>) -> Code {
+        Code {
+            max_stack: 2,
+            max_locals: 3,
+            byte_offsets: (0..=code.len() as u32).collect(),
+            code: Code::cells(code),
+            switches,
+            exception_table,
+            attributes: vec![],
+        }
+    }
+
+    #[test]
+    fn fuses_and_drops() {
+        let mut c = code(vec![
+            Nop, Goto(3), Return, Goto(5), Nop,
+            Iload(0), Iload(1), Iadd, Istore(2),
+            Iload(2), Ipush(10), IfIcmplt(3),
+            Aload(0), Getfield(7), Tableswitch(0),
+        ], vec![
+            SwitchTable::Table { default: 2, low: 0, targets: vec![3] },
+        ], vec![]);
+        optimize(&mut c);
+
+        // the second goto only jumps over a nop once the first one skips past it
+        assert_eq!(c.instructions(), vec![
+            Goto(2), Return,
+            IaddLocals(0, 1, 2),
+            IfIcmpConst(Condition::Lt, 2, 10, 2),
+            AloadGetfield(0, 7), Tableswitch(0),
+        ]);
+        assert_eq!(c.switches[0], SwitchTable::Table { default: 1, low: 0, targets: vec![2] });
+    }
+
+    #[test]
+    fn leaders_stop_fusion() {
+        // a branch into the middle of the first sequence, and an exception range ending in the last
+        let mut c = code(vec![
+            Iload(0), Iload(1), Iadd, Istore(2),
+            Iload(0), Ifeq(1), Return,
+            Aload(0), Getfield(7), Areturn,
+        ], vec![], vec![
+            ExceptionTableItem { start_pc: 7, end_pc: 8, catch_type: 0, handler_pc: 6 },
+        ]);
+        optimize(&mut c);
+
+        assert_eq!(c.instructions(), vec![
+            Iload(0), Iload(1), Iadd, Istore(2),
+            IfIcmpConst(Condition::Eq, 0, 0, 1), Return,
+            Aload(0), Getfield(7), Areturn,
+        ]);
+        let item = &c.exception_table[0];
+        assert_eq!((item.start_pc, item.end_pc, item.handler_pc), (6, 7, 5));
+    }
+}
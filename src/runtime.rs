@@ -3,9 +3,14 @@ use crate::method::{Method};
 use crate::{JavaClass, ClassReader};
 
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::ptr::{null_mut};
 
-use crate::class::{Class, NativeClass};
+use crate::assembler::AssembleError;
+use crate::class::{access_flags, Class, NativeClass};
+use crate::class_reader::{ClassFormatError, Reason};
+use crate::settings::Settings;
+use crate::verifier::{ClassInfo, Hierarchy, VerifyError};
 
 use crate::heap::Heap;
 use crate::values::Value;
@@ -15,20 +20,88 @@ pub const CLASSPATH: &[&str] = &[
     "std/class/",
 ];
 
+/// classpath entries whose classes aren't verified unless verification is asked for
+/// with `Verify::All`
+pub const TRUSTED_CLASSPATH: &[&str] = &[
+    "std/class/",
+];
+
+/// Why a class could not be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    ClassFormat(ClassFormatError),
+    NoClassDefFound(String),
+    /// a `.j` file that could not be assembled
+    Assembly(AssembleError),
+    Verify(VerifyError),
+    /// a class with a method that overrides a final one, found when building its vtable
+    OverridesFinal { class: String, method: String },
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{e}"),
+            LoadError::ClassFormat(e @ ClassFormatError { reason: Reason::UnsupportedVersion(_), .. }) =>
+                write!(f, "java.lang.UnsupportedClassVersionError: {e}"),
+            LoadError::ClassFormat(e) => write!(f, "java.lang.ClassFormatError: {e}"),
+            LoadError::NoClassDefFound(name) => write!(f, "java.lang.NoClassDefFoundError: {name}"),
+            LoadError::Assembly(e) => write!(f, "could not assemble: {e}"),
+            LoadError::Verify(e) => write!(f, "java.lang.VerifyError: {e}"),
+            LoadError::OverridesFinal { class, method } =>
+                write!(f, "java.lang.VerifyError: class {class} overrides final method {method}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<AssembleError> for LoadError {
+    fn from(e: AssembleError) -> Self {
+        LoadError::Assembly(e)
+    }
+}
+
+impl From<VerifyError> for LoadError {
+    fn from(e: VerifyError) -> Self {
+        LoadError::Verify(e)
+    }
+}
+
+impl From<ClassFormatError> for LoadError {
+    fn from(e: ClassFormatError) -> Self {
+        LoadError::ClassFormat(e)
+    }
+}
+
 pub struct Runtime {
     main_class: *mut JavaClass,
     path_to_main: String,
-    loaded_classes: HashMap<String, Class>, // name:class
+    // boxed, so that the pointers handed out stay put as more classes are loaded
+    loaded_classes: HashMap<String, Box<Class>>, // name:class
     pub(crate) heap: Heap,
+    pub settings: Settings,
 }
 
 impl Runtime {
-    pub fn new(main_class_path: String) -> Result<Runtime, std::io::Error> {
+    pub fn new(main_class_path: String) -> Result<Runtime, LoadError> {
+        Self::with_settings(main_class_path, Settings::default())
+    }
+
+    pub fn with_settings(main_class_path: String, settings: Settings) -> Result<Runtime, LoadError> {
         let mut runtime = Runtime {
             main_class: 0 as *mut JavaClass,
             path_to_main: main_class_path.clone(),
             loaded_classes: HashMap::new(),
             heap: Heap::new(),
+            settings,
         };
 
         println!("loading builtin classes");
@@ -36,11 +109,8 @@ impl Runtime {
 
         println!("loading class from {main_class_path}");
         let main_class = JavaClass::from_filename(&main_class_path, &mut runtime)?;
-        let name = main_class.name.clone();
-        runtime.loaded_classes.insert(name.clone(), Class::Java(main_class));
-        runtime.main_class = runtime.loaded_classes
-            .get_mut(&name).unwrap()
-            .java_mut().unwrap();
+        let main_class = runtime.add_java_class(main_class)?;
+        runtime.main_class = unsafe { &mut *main_class }.java_mut().unwrap();
 
         Ok(runtime)
     }
@@ -50,31 +120,29 @@ impl Runtime {
         Value::nobject(null_mut())
     }
 
-    pub fn load(&mut self, name: String) -> Result<*mut Class, ()> {
+    pub fn load(&mut self, name: String) -> Result<*mut Class, LoadError> {
         if let Some(cls) = self.loaded_classes.get_mut(&name) {
-            return Ok(cls as *mut Class);
+            return Ok(&mut **cls as *mut Class);
         }
 
         println!("searching for {name}.class");
 
-        fn get_reader(class_name: &str) -> Option<ClassReader> {
+        /// the reader and path of the first class file found for `class_name`
+        fn get_reader(class_name: &str) -> Option<(ClassReader<'static>, String)> {
             let paths = CLASSPATH.iter()
                 .map(|x| (x.to_string() + class_name + ".class"));
 
-            paths.map(|x| ClassReader::new(&*x)).filter_map(Result::ok).next()
+            paths.filter_map(|x| Some((ClassReader::new(&*x).ok()?, x))).next()
         }
 
-        let cls = JavaClass::from_classfile(
-            get_reader(&*name).ok_or(())?.read_classfile(),
-            self
-        );
-
-        self.add_java_class(cls);
-
-        Ok(self.loaded_classes.get_mut(&name).unwrap() as *mut Class)
+        let (mut reader, path) = get_reader(&*name)
+            .ok_or_else(|| LoadError::NoClassDefFound(name.clone()))?;
+        let cls = JavaClass::load(reader.read_classfile()?, &path, self)?;
+        self.add_java_class(cls)
     }
 
-    pub fn run_main<'a>(self: &mut Self) {
+    /// run the main method, giving what it returned
+    pub fn run_main<'a>(self: &mut Self) -> Option<Value> {
         let main_class: &'a JavaClass = unsafe { &*self.main_class };
         let main_string_args: Result<&'a Method, ()> = main_class.get_method(
             "main".to_string(),
@@ -87,7 +155,7 @@ impl Runtime {
         );
         let long_main = |_| main_class.get_method(
             "main".to_string(),
-            "()L".to_string(),
+            "()J".to_string(),
         );
 
         let main_method = main_string_args
@@ -108,17 +176,30 @@ impl Runtime {
     }
 
     pub fn add_native_class(&mut self, cls: NativeClass) {
-        self.loaded_classes.insert(cls.name.clone(), Class::Native(cls));
+        let name = cls.name.clone();
+        self.loaded_classes.insert(name.clone(), Box::new(Class::Native(cls)));
+        self.loaded_classes.get_mut(&name).unwrap()
+            .link()
+            .unwrap_or_else(|e| panic!("could not link native class {name}: {e}"));
     }
 
-    pub fn add_java_class(&mut self, cls: JavaClass) {
-        self.loaded_classes.insert(cls.name.clone(), Class::Java(cls));
+    /// add a class and link it, giving where it was put
+    pub fn add_java_class(&mut self, cls: JavaClass) -> Result<*mut Class, LoadError> {
+        let name = cls.name.clone();
+        self.loaded_classes.insert(name.clone(), Box::new(Class::Java(cls)));
+        let cls = &mut **self.loaded_classes.get_mut(&name).unwrap();
+        if let Err(e) = cls.link() {
+            self.loaded_classes.remove(&name);
+            return Err(e);
+        }
+        Ok(cls as *mut Class)
     }
 
     fn class_merge_in(&mut self, cls: Class, name: String) {
         // Generating JavaClass for this because it has more fields :)
 
         if let Some(first) = self.loaded_classes.get_mut(&name) {
+            let first = &mut **first;
             let mut second = cls;
             if let (&Class::Java(_), &Class::Java(_)) = (&first, &second) {
                 panic!("merging JavaClass with JavaClass not yet supported (ERR in loading class {name})");
@@ -127,17 +208,21 @@ impl Runtime {
                 (Class::Java(_), Class::Native(_)) |
                 (Class::Native(_), Class::Native(_)) => {
                     first.merge_methods(&mut second);
+                    first.link().unwrap_or_else(|e| panic!("could not link {name}: {e}"));
                     return;
                 },
                 (Class::Native(_), Class::Java(_)) => {
                     second.merge_methods(first);
-                    self.loaded_classes.insert(name, second);
+                    self.loaded_classes.insert(name.clone(), Box::new(second));
+                    self.loaded_classes.get_mut(&name).unwrap()
+                        .link()
+                        .unwrap_or_else(|e| panic!("could not link {name}: {e}"));
                     return;
                 },
                 _ => panic!("merging JavaClass with JavaClass not yet supported (ERR in loading class {name})"),
             }
         } else {
-            self.loaded_classes.insert(name, cls);
+            self.loaded_classes.insert(name, Box::new(cls));
         }
     }
 
@@ -145,7 +230,18 @@ impl Runtime {
 
     pub fn get_class(&mut self, name: &str) -> Result<*mut Class, String> {
         self.loaded_classes.get_mut(name)
-            .map(|x| x as *mut Class)
+            .map(|x| &mut **x as *mut Class)
             .ok_or_else(|| name.to_string())
     }
 }
+
+impl Hierarchy for Runtime {
+    fn class_info(&self, name: &str) -> Option<ClassInfo> {
+        let cls = self.loaded_classes.get(name)?;
+        let super_class = cls.super_class();
+        Some(ClassInfo {
+            super_class: (!super_class.is_null()).then(|| unsafe { (*super_class).name().to_string() }),
+            is_interface: cls.access_flags() & access_flags::INTERFACE != 0,
+        })
+    }
+}
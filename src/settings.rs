@@ -1,4 +1,34 @@
+/// Which classes get verified before they are used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verify {
+    /// no classes, like `-Xverify:none`
+    None,
+    /// every class except those from a trusted classpath entry
+    Untrusted,
+    /// every class, like `-Xverify:all`
+    All,
+}
 
+#[derive(Debug, Clone)]
 pub struct Settings {
+    pub verify: Verify,
+    /// whether loaded code goes through the peephole optimizer, like `-XX:+Peephole`
+    pub peephole: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings { verify: Verify::Untrusted, peephole: true }
+    }
+}
 
+impl Settings {
+    /// whether a class loaded from `path` should be verified
+    pub fn should_verify(&self, path: &str) -> bool {
+        match self.verify {
+            Verify::None => false,
+            Verify::Untrusted => !crate::runtime::TRUSTED_CLASSPATH.iter().any(|entry| path.starts_with(entry)),
+            Verify::All => true,
+        }
+    }
 }
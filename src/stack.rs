@@ -48,7 +48,8 @@ impl Stack {
     ) -> *mut StackFrame {
         let old = unsafe { &mut *self.current };
         // check for stack overflow
-        if old.above > self.top {
+        let end = unsafe { old.above.byte_add(StackFrame::size_for(method)) };
+        if end > self.top {
             null_mut()
         } else {
             self.current = old.above;
@@ -93,6 +94,12 @@ pub struct StackFrame {
 }
 
 impl StackFrame {
+    /// how many bytes a frame for `method` takes
+    fn size_for(method: *const JavaMethod) -> usize {
+        let code = unsafe { &*method }.code.as_ref().expect("called abstract method");
+        size_of::<Self>() + (code.max_stack as usize + code.max_locals as usize) * size_of::<Value>()
+    }
+
     /// initialize a new stack frame at the address
     pub unsafe fn initialize_at(
         // address to put the frame at
@@ -112,9 +119,8 @@ impl StackFrame {
         let frame = &mut *addr;
 
         // initialize references to frame above and below
-        let stride = size_of::<Self>() + max_stack_and_locals as usize * size_of::<Value>();
         frame.under = under;
-        frame.above = under.byte_add(stride);
+        frame.above = addr.byte_add(Self::size_for(method));
 
         // initialize execution context for method
         frame.program_counter = 0;
@@ -145,6 +151,35 @@ impl StackFrame {
         }
     }
 
+    // longs and doubles take two slots, on the operand stack and in the locals. both
+    // hold the value, so that instructions like dup2 can move slots around without
+    // knowing what is in them
+
+    /// push a long or double
+    pub fn push_wide(&mut self, value: Value) {
+        self.push(value);
+        self.push(value);
+    }
+
+    /// pop a long or double
+    pub fn pop_wide(&mut self) -> Value {
+        self.stack_ptr -= 2;
+        unsafe {
+            self.stack_and_locals.as_mut_ptr()
+                .add(self.stack_ptr as usize)
+                .read()
+        }
+    }
+
+    /// the value `depth` slots under the top of the operand stack, without popping it
+    pub fn peek(&self, depth: u16) -> Value {
+        unsafe {
+            self.stack_and_locals.as_ptr()
+                .add(self.stack_ptr as usize - 1 - depth as usize)
+                .read()
+        }
+    }
+
     pub fn get(&mut self, n: u16) -> Value {
         unsafe {
             self.stack_and_locals.as_mut_ptr()
@@ -161,6 +196,12 @@ impl StackFrame {
         }
     }
 
+    /// store a long or double in locals `n` and `n + 1`
+    pub fn set_wide(&mut self, n: u16, value: Value) {
+        self.set(n, value);
+        self.set(n + 1, value);
+    }
+
     pub fn stack_and_locals(&self) -> &[Value] {
         unsafe {
             slice::from_raw_parts(
@@ -18,6 +18,8 @@ pub union Value {
     pub(crate) object: *mut Object,
     array: *mut Array,
     boolean: bool,
+    /// the instruction index after a `jsr`, for `ret` to jump back to
+    return_address: u16,
 }
 
 pub struct Object {
@@ -86,6 +88,7 @@ impl Value {
 
     pub fn narray(a: *mut Array) -> Self { Self { array: a }}
     pub fn nobject(o: *mut Object) -> Self { Self { object: o }}
+    pub fn nreturn_address(pc: u16) -> Self { Self { return_address: pc }}
 
     pub fn byte(self) -> i8 { unsafe { self.byte } }
     pub fn short(self) -> i16 { unsafe { self.short } }
@@ -99,6 +102,7 @@ impl Value {
     pub fn array(self) -> *mut Array { unsafe { self.array } }
     pub fn object(self) -> *mut Object { unsafe { self.object } }
     pub fn boolean(self) -> bool { unsafe { self.boolean } }
+    pub fn return_address(self) -> u16 { unsafe { self.return_address } }
 
     pub const NULL: Value = Value { object: std::ptr::null_mut() };
 
@@ -126,9 +130,19 @@ impl Debug for Value {
   }
 }
 
-impl From<ConstantPoolInfo> for Value {
-    fn from(_value: ConstantPoolInfo) -> Self {
-        todo!()
+impl TryFrom<ConstantPoolInfo> for Value {
+    type Error = ConstantPoolInfo;
+
+    /// the value of a numeric constant. strings and the rest need objects, which can't be made
+    /// yet, so they give back the entry
+    fn try_from(value: ConstantPoolInfo) -> Result<Self, Self::Error> {
+        Ok(match value {
+            ConstantPoolInfo::Integer(i) => Value::nint(i.bytes as i32),
+            ConstantPoolInfo::Float(f) => Value::nfloat(f32::from_bits(f.bytes)),
+            ConstantPoolInfo::Long(l) => Value::nlong(((l.high_bytes as i64) << 32) | l.low_bytes as i64),
+            ConstantPoolInfo::Double(d) => Value::ndouble(f64::from_bits(((d.high_bytes as u64) << 32) | d.low_bytes as u64)),
+            x => return Err(x),
+        })
     }
 }
 impl PartialEq for Value {
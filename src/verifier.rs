@@ -0,0 +1,1486 @@
+//! Bytecode verification (JVMS §4.10).
+//!
+//! Classes of version 50 and up are type checked against the frames in their
+//! `StackMapTable` (§4.10.1). Older classes have no frames, so their frames are
+//! inferred instead (§4.10.2), by merging everything that flows into an instruction
+//! until nothing changes. A version 50 class that fails type checking gets a second
+//! chance with inference, like it would on the reference implementation.
+//!
+//! Class hierarchy questions go through `Hierarchy`. Classes are never loaded just
+//! to verify something, so a class it doesn't know about is assumed to be
+//! assignable to anything. Those uses are checked again when they are resolved.
+//! Protected member access isn't checked.
+
+use std::cell::Cell;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+
+use crate::attributes::attribute::{Attribute, VerificationType};
+use crate::attributes::code::Code;
+use crate::attributes::attribute::StackMapFrame;
+use crate::bytecode::Instruction;
+use crate::class::{access_flags, JavaClass};
+use crate::class_version::ClassVersion;
+use crate::constant_pool::{ConstantPool, ConstantPoolInfo};
+use crate::descriptor;
+use crate::method::{JavaMethod, Method};
+
+/// Why some code was rejected, raised as a `java.lang.VerifyError`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    pub class: String,
+    /// name and descriptor
+    pub method: String,
+    /// the instruction index where verification failed
+    pub pc: u16,
+    pub message: String,
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{} at instruction {}: {}", self.class, self.method, self.pc, self.message)
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// What the verifier needs to know about a class other than the one it is checking.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassInfo {
+    /// `None` for `java/lang/Object`
+    pub super_class: Option<String>,
+    pub is_interface: bool,
+}
+
+/// The classes the verifier can ask about.
+pub trait Hierarchy {
+    /// `None` if the class isn't known
+    fn class_info(&self, name: &str) -> Option<ClassInfo>;
+}
+
+/// The class that the verified methods belong to.
+#[derive(Debug, Clone, Copy)]
+pub struct ClassContext<'a> {
+    pub name: &'a str,
+    pub super_class: Option<&'a str>,
+    pub access_flags: u16,
+    pub version: ClassVersion,
+    pub constant_pool: &'a ConstantPool,
+}
+
+/// A verification type (JVMS §4.10.1.2).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum VType {
+    Top,
+    /// `boolean`, `byte`, `char`, `short` and `int`
+    Int,
+    Float,
+    Long,
+    Double,
+    Null,
+    /// `this` in a constructor, before the super or another constructor is called
+    UninitializedThis,
+    /// made by the `new` at this instruction index, and not initialized yet
+    Uninitialized(u16),
+    /// a class name, or the descriptor of an array
+    Reference(String),
+    /// pushed by `jsr`. only seen by inference, since newer classes can't use subroutines
+    ReturnAddress,
+}
+
+impl Display for VType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VType::Top => write!(f, "top"),
+            VType::Int => write!(f, "int"),
+            VType::Float => write!(f, "float"),
+            VType::Long => write!(f, "long"),
+            VType::Double => write!(f, "double"),
+            VType::Null => write!(f, "null"),
+            VType::UninitializedThis => write!(f, "uninitializedThis"),
+            VType::Uninitialized(pc) => write!(f, "uninitialized({pc})"),
+            VType::Reference(name) => write!(f, "'{name}'"),
+            VType::ReturnAddress => write!(f, "returnAddress"),
+        }
+    }
+}
+
+impl VType {
+    /// the type of a value with this field descriptor
+    pub fn from_descriptor(descriptor: &str) -> VType {
+        match descriptor.as_bytes().first() {
+            Some(b'B' | b'C' | b'I' | b'S' | b'Z') => VType::Int,
+            Some(b'F') => VType::Float,
+            Some(b'J') => VType::Long,
+            Some(b'D') => VType::Double,
+            Some(b'L') => VType::Reference(descriptor[1..descriptor.len() - 1].to_string()),
+            _ => VType::Reference(descriptor.to_string()),
+        }
+    }
+
+    /// the number of local variable slots it takes up
+    pub fn size(&self) -> usize {
+        match self {
+            VType::Long | VType::Double => 2,
+            _ => 1,
+        }
+    }
+
+    pub fn is_reference(&self) -> bool {
+        matches!(self, VType::Null | VType::UninitializedThis | VType::Uninitialized(_) | VType::Reference(_))
+    }
+}
+
+/// the descriptor of an array of the class or array `name`
+fn array_of(name: &str) -> String {
+    if name.starts_with('[') {
+        format!("[{name}")
+    } else {
+        format!("[L{name};")
+    }
+}
+
+/// the argument types and return type of a method descriptor. `None` is `void`
+fn method_types(descriptor: &str) -> Result<(Vec<VType>, Option<VType>), String> {
+    let bad = || format!("bad method descriptor {descriptor}");
+    let mut rest = descriptor.strip_prefix('(').ok_or_else(bad)?;
+    let mut args = vec![];
+    while !rest.starts_with(')') {
+        let len = descriptor::field_type_len(rest).ok_or_else(bad)?;
+        args.push(VType::from_descriptor(&rest[..len]));
+        rest = &rest[len..];
+    }
+    let ret = match &rest[1..] {
+        "V" => None,
+        ret if descriptor::is_field_descriptor(ret) => Some(VType::from_descriptor(ret)),
+        _ => return Err(bad()),
+    };
+    Ok((args, ret))
+}
+
+/// turn longs and doubles whose second half was overwritten into `Top`
+fn drop_broken_pairs(locals: &mut [VType]) {
+    for i in 0..locals.len() {
+        if locals[i].size() == 2 && locals.get(i + 1) != Some(&VType::Top) {
+            locals[i] = VType::Top;
+        }
+    }
+}
+
+/// The types in the local variables and on the operand stack before an instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Frame {
+    /// `max_locals` entries, with a `Top` after every long and double
+    locals: Vec<VType>,
+    /// longs and doubles take one entry here
+    stack: Vec<VType>,
+    /// `this` still has to be initialized, in a constructor
+    this_uninit: bool,
+}
+
+impl Frame {
+    fn local(&self, index: u16) -> Result<&VType, String> {
+        self.locals.get(index as usize)
+            .ok_or_else(|| format!("local variable {index} is past max_locals"))
+    }
+
+    fn set_local(&mut self, index: u16, t: VType) -> Result<(), String> {
+        let i = index as usize;
+        if i + t.size() > self.locals.len() {
+            return Err(format!("local variable {index} is past max_locals"));
+        }
+        // writing over the second half of a long or double breaks it
+        if i > 0 && self.locals[i - 1].size() == 2 {
+            self.locals[i - 1] = VType::Top;
+        }
+        if t.size() == 2 {
+            self.locals[i + 1] = VType::Top;
+        }
+        self.locals[i] = t;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<VType, String> {
+        self.stack.pop().ok_or_else(|| "operand stack underflow".to_string())
+    }
+
+    /// pop a value that isn't a long or a double
+    fn pop_category1(&mut self) -> Result<VType, String> {
+        match self.pop()? {
+            t if t.size() == 1 => Ok(t),
+            t => Err(format!("expected a category 1 value on the operand stack, found {t}")),
+        }
+    }
+
+    fn stack_size(&self) -> usize {
+        self.stack.iter().map(VType::size).sum()
+    }
+
+    /// replace every `from` in the locals and on the stack
+    fn replace(&mut self, from: &VType, to: &VType) {
+        for t in self.locals.iter_mut().chain(self.stack.iter_mut()) {
+            if t == from {
+                *t = to.clone();
+            }
+        }
+    }
+}
+
+/// check the code of every method in `class`
+pub fn verify_class(class: &JavaClass, hierarchy: &dyn Hierarchy) -> Result<(), VerifyError> {
+    let super_class = (!class.super_class.is_null())
+        .then(|| unsafe { (*class.super_class).name() });
+    let context = ClassContext {
+        name: &class.name,
+        super_class,
+        access_flags: class.access_flags,
+        version: class.version,
+        constant_pool: &class.constant_pool,
+    };
+    for method in class.methods.values() {
+        if let Method::Java(m) = method {
+            verify_method(&context, m, hierarchy)?;
+        }
+    }
+    Ok(())
+}
+
+/// check the code of `method`, if it has any
+pub fn verify_method(class: &ClassContext, method: &JavaMethod, hierarchy: &dyn Hierarchy) -> Result<(), VerifyError> {
+    let code = match &method.code {
+        Some(code) => code,
+        None => return Ok(()),
+    };
+    let error = |pc: u16, message: String| VerifyError {
+        class: class.name.to_string(),
+        method: format!("{}{}", method.name, method.descriptor),
+        pc,
+        message,
+    };
+    let (args, return_type) = method_types(&method.descriptor).map_err(|e| error(0, e))?;
+    let verifier = MethodVerifier { class, hierarchy, method, code, args, return_type };
+
+    if class.version.major < 50 {
+        return verifier.infer().map_err(|(pc, e)| error(pc, e));
+    }
+    match verifier.type_check() {
+        Err(_) if class.version.major == 50 => verifier.infer(),
+        result => result,
+    }.map_err(|(pc, e)| error(pc, e))
+}
+
+struct MethodVerifier<'a> {
+    class: &'a ClassContext<'a>,
+    hierarchy: &'a dyn Hierarchy,
+    method: &'a JavaMethod,
+    code: &'a Code,
+    args: Vec<VType>,
+    /// `None` for `void`
+    return_type: Option<VType>,
+}
+
+/// errors are a message, and the instruction it is about unless the caller knows
+type Result<T, E = (u16, String)> = std::result::Result<T, E>;
+
+/// The subroutines of some code, for inference.
+///
+/// Locals that a subroutine doesn't write keep the types they had at the `jsr`
+/// that called it, rather than a merge of every caller's types.
+#[derive(Debug, Default)]
+struct Subroutines {
+    /// the index of every `jsr`, and its target
+    calls: Vec<(u16, u16)>,
+    /// the `ret`s of each subroutine, by its first instruction
+    rets: HashMap<u16, Vec<u16>>,
+    /// the locals written by each subroutine or the subroutines it calls
+    written: HashMap<u16, Vec<bool>>,
+}
+
+impl MethodVerifier<'_> {
+    fn cp(&self) -> &ConstantPool {
+        self.class.constant_pool
+    }
+
+    fn class_info(&self, name: &str) -> Option<ClassInfo> {
+        if name == self.class.name {
+            Some(ClassInfo {
+                super_class: self.class.super_class.map(str::to_string),
+                is_interface: self.class.access_flags & access_flags::INTERFACE != 0,
+            })
+        } else {
+            self.hierarchy.class_info(name)
+        }
+    }
+
+    /// whether a value of class or array `from` can be used as a `to`
+    fn is_ref_assignable(&self, from: &str, to: &str) -> bool {
+        if from == to || to == "java/lang/Object" {
+            return true;
+        }
+        if let Some(to_component) = to.strip_prefix('[') {
+            return match from.strip_prefix('[') {
+                Some(from_component) => match (VType::from_descriptor(from_component), VType::from_descriptor(to_component)) {
+                    (VType::Reference(f), VType::Reference(t)) => self.is_ref_assignable(&f, &t),
+                    _ => from_component == to_component,
+                },
+                None => false,
+            };
+        }
+        if from.starts_with('[') {
+            return to == "java/lang/Cloneable" || to == "java/io/Serializable";
+        }
+        // anything can be passed where an interface is expected, and checked when it is used
+        match self.class_info(to) {
+            Some(ClassInfo { is_interface: false, .. }) => {}
+            _ => return true,
+        }
+        let mut class = from.to_string();
+        loop {
+            match self.class_info(&class) {
+                None => return true,
+                Some(ClassInfo { super_class: None, .. }) => return false,
+                Some(ClassInfo { super_class: Some(s), .. }) if s == to => return true,
+                Some(ClassInfo { super_class: Some(s), .. }) => class = s,
+            }
+        }
+    }
+
+    fn is_assignable(&self, from: &VType, to: &VType) -> bool {
+        match (from, to) {
+            _ if from == to => true,
+            (_, VType::Top) => true,
+            (VType::Null, VType::Reference(_)) => true,
+            (VType::Reference(f), VType::Reference(t)) => self.is_ref_assignable(f, t),
+            _ => false,
+        }
+    }
+
+    /// the most specific class that both `a` and `b` can be used as
+    fn common_super(&self, a: &str, b: &str) -> String {
+        if self.is_ref_assignable(b, a) {
+            return a.to_string();
+        }
+        if self.is_ref_assignable(a, b) {
+            return b.to_string();
+        }
+        if let (Some(ca), Some(cb)) = (a.strip_prefix('['), b.strip_prefix('[')) {
+            if let (VType::Reference(ca), VType::Reference(cb)) = (VType::from_descriptor(ca), VType::from_descriptor(cb)) {
+                return array_of(&self.common_super(&ca, &cb));
+            }
+        }
+        if !a.starts_with('[') && !b.starts_with('[') {
+            let mut class = b.to_string();
+            while let Some(ClassInfo { super_class: Some(s), .. }) = self.class_info(&class) {
+                if self.is_ref_assignable(a, &s) {
+                    return s;
+                }
+                class = s;
+            }
+        }
+        "java/lang/Object".to_string()
+    }
+
+    /// the type that both `a` and `b` can be used as, keeping `a` where possible
+    fn merge(&self, a: &VType, b: &VType) -> Option<VType> {
+        match (a, b) {
+            _ if a == b => Some(a.clone()),
+            (VType::Reference(_), VType::Null) => Some(a.clone()),
+            (VType::Null, VType::Reference(_)) => Some(b.clone()),
+            (VType::Reference(x), VType::Reference(y)) => Some(VType::Reference(self.common_super(x, y))),
+            _ => None,
+        }
+    }
+
+    fn is_frame_assignable(&self, from: &Frame, to: &Frame) -> bool {
+        from.stack.len() == to.stack.len()
+            && (!from.this_uninit || to.this_uninit)
+            && from.locals.iter().zip(&to.locals).all(|(f, t)| self.is_assignable(f, t))
+            && from.stack.iter().zip(&to.stack).all(|(f, t)| self.is_assignable(f, t))
+    }
+
+    fn merge_frames(&self, a: &Frame, b: &Frame) -> Result<Frame, String> {
+        if a.stack.len() != b.stack.len() {
+            return Err(format!("inconsistent stack height {} != {}", a.stack.len(), b.stack.len()));
+        }
+        let stack = a.stack.iter().zip(&b.stack)
+            .map(|(x, y)| self.merge(x, y).ok_or_else(|| format!("inconsistent stack types {x} and {y}")))
+            .collect::<Result<_, _>>()?;
+        let mut locals: Vec<VType> = a.locals.iter().zip(&b.locals)
+            .map(|(x, y)| self.merge(x, y).unwrap_or(VType::Top))
+            .collect();
+        drop_broken_pairs(&mut locals);
+        Ok(Frame { locals, stack, this_uninit: a.this_uninit || b.this_uninit })
+    }
+
+    fn is_init(&self) -> bool {
+        self.method.name == "<init>"
+    }
+
+    fn is_static(&self) -> bool {
+        self.method.access_flags & 0x0008 != 0
+    }
+
+    /// the locals a method starts with, with one entry per long or double
+    fn initial_locals(&self) -> Vec<VType> {
+        let mut locals = vec![];
+        if !self.is_static() {
+            locals.push(if self.is_init() && self.class.name != "java/lang/Object" {
+                VType::UninitializedThis
+            } else {
+                VType::Reference(self.class.name.to_string())
+            });
+        }
+        locals.extend(self.args.iter().cloned());
+        locals
+    }
+
+    /// a frame from locals with one entry per long or double
+    fn frame(&self, compact_locals: &[VType], stack: Vec<VType>) -> Result<Frame, String> {
+        let mut locals = vec![];
+        for t in compact_locals {
+            locals.push(t.clone());
+            if t.size() == 2 {
+                locals.push(VType::Top);
+            }
+        }
+        if locals.len() > self.code.max_locals as usize {
+            return Err(format!("{} locals are more than max_locals {}", locals.len(), self.code.max_locals));
+        }
+        locals.resize(self.code.max_locals as usize, VType::Top);
+        let frame = Frame {
+            this_uninit: locals.contains(&VType::UninitializedThis),
+            locals,
+            stack,
+        };
+        if frame.stack_size() > self.code.max_stack as usize {
+            return Err(format!("{} stack slots are more than max_stack {}", frame.stack_size(), self.code.max_stack));
+        }
+        Ok(frame)
+    }
+
+    fn verification_type(&self, t: &VerificationType) -> Result<VType, String> {
+        Ok(match t {
+            VerificationType::Top => VType::Top,
+            VerificationType::Integer => VType::Int,
+            VerificationType::Float => VType::Float,
+            VerificationType::Long => VType::Long,
+            VerificationType::Double => VType::Double,
+            VerificationType::Null => VType::Null,
+            VerificationType::UninitializedThis => VType::UninitializedThis,
+            VerificationType::Object(index) => VType::Reference(self.class_name(*index)?),
+            VerificationType::Uninitialized(pc) => match self.code.code.get(*pc as usize).map(Cell::get) {
+                Some(Instruction::New(_)) => VType::Uninitialized(*pc),
+                _ => return Err(format!("uninitialized({pc}) is not made by a new instruction")),
+            },
+        })
+    }
+
+    /// the frame at every instruction that has one in the `StackMapTable`
+    fn stack_map(&self) -> Result<Vec<Option<Frame>>> {
+        let mut frames = vec![None; self.code.code.len()];
+        let table = self.code.attributes.iter().find_map(|a| match a {
+            Attribute::StackMapTable(table) => Some(table),
+            _ => None,
+        });
+        let mut locals = self.initial_locals();
+        let mut previous: Option<usize> = None;
+        for frame in table.into_iter().flatten() {
+            let pc = match previous {
+                None => frame.offset_delta() as usize,
+                Some(p) => p + frame.offset_delta() as usize + 1,
+            };
+            let at = pc.min(u16::MAX as usize) as u16;
+            if pc >= frames.len() {
+                return Err((at, "stack map frame is past the end of the code".to_string()));
+            }
+            let vtype = |t| self.verification_type(t).map_err(|e| (at, e));
+            let stack = match frame {
+                StackMapFrame::Same { .. } => vec![],
+                StackMapFrame::SameLocals1StackItem { stack, .. } => vec![vtype(stack)?],
+                StackMapFrame::Chop { k, .. } => {
+                    let kept = locals.len().checked_sub(*k as usize)
+                        .ok_or_else(|| (at, format!("cannot chop {k} locals")))?;
+                    locals.truncate(kept);
+                    vec![]
+                }
+                StackMapFrame::Append { locals: appended, .. } => {
+                    for t in appended {
+                        locals.push(vtype(t)?);
+                    }
+                    vec![]
+                }
+                StackMapFrame::Full { locals: full, stack, .. } => {
+                    locals = full.iter().map(vtype).collect::<Result<_>>()?;
+                    stack.iter().map(vtype).collect::<Result<_>>()?
+                }
+            };
+            frames[pc] = Some(self.frame(&locals, stack).map_err(|e| (at, e))?);
+            previous = Some(pc);
+        }
+        Ok(frames)
+    }
+
+    /// checks that don't depend on the frames
+    fn check_code(&self) -> Result<()> {
+        let len = self.code.code.len();
+        if len == 0 {
+            return Err((0, "code is empty".to_string()));
+        }
+        for item in &self.code.exception_table {
+            if item.start_pc >= item.end_pc || item.end_pc as usize > len || item.handler_pc as usize >= len {
+                return Err((item.start_pc, "bad exception table entry".to_string()));
+            }
+        }
+        for (pc, ins) in self.code.instructions().iter().enumerate() {
+            if let Some(t) = ins.branch_targets(self.code).into_iter().find(|t| *t as usize >= len) {
+                return Err((pc as u16, format!("branch target {t} is past the end of the code")));
+            }
+        }
+        Ok(())
+    }
+
+    /// the type of the exceptions a handler catches
+    fn catch_type(&self, catch_type: u16) -> Result<VType, String> {
+        if catch_type == 0 {
+            return Ok(VType::Reference("java/lang/Throwable".to_string()));
+        }
+        let t = VType::Reference(self.class_name(catch_type)?);
+        if !self.is_assignable(&t, &VType::Reference("java/lang/Throwable".to_string())) {
+            return Err(format!("catch type {t} is not a Throwable"));
+        }
+        Ok(t)
+    }
+
+    /// the frames going into the handlers of an instruction, and where they go
+    fn handler_frames(&self, pc: u16, frame: &Frame) -> Result<Vec<(u16, Frame)>> {
+        self.code.exception_table.iter()
+            .filter(|item| (item.start_pc..item.end_pc).contains(&pc))
+            .map(|item| {
+                let t = self.catch_type(item.catch_type).map_err(|e| (pc, e))?;
+                let handler_frame = Frame { locals: frame.locals.clone(), stack: vec![t], this_uninit: frame.this_uninit };
+                Ok((item.handler_pc, handler_frame))
+            })
+            .collect()
+    }
+
+    /// type checking with the `StackMapTable` (JVMS §4.10.1)
+    fn type_check(&self) -> Result<()> {
+        self.check_code()?;
+        let frames = self.stack_map()?;
+        let mut current = Some(self.frame(&self.initial_locals(), vec![]).map_err(|e| (0, e))?);
+
+        for (i, ins) in self.code.instructions().iter().enumerate() {
+            let pc = i as u16;
+            if let Some(mapped) = &frames[i] {
+                if let Some(frame) = &current {
+                    if !self.is_frame_assignable(frame, mapped) {
+                        return Err((pc, "frame is not assignable to the stack map frame".to_string()));
+                    }
+                }
+                current = Some(mapped.clone());
+            }
+            let frame = current.take()
+                .ok_or_else(|| (pc, "expected a stack map frame after an unconditional branch".to_string()))?;
+
+            for (handler, handler_frame) in self.handler_frames(pc, &frame)? {
+                match &frames[handler as usize] {
+                    Some(mapped) if self.is_frame_assignable(&handler_frame, mapped) => {}
+                    Some(_) => return Err((pc, format!("frame is not assignable to the handler at {handler}"))),
+                    None => return Err((pc, format!("exception handler {handler} has no stack map frame"))),
+                }
+            }
+
+            if let Instruction::Jsr(_) | Instruction::JsrW(_) | Instruction::Ret(_) = ins {
+                return Err((pc, "subroutines can't be type checked".to_string()));
+            }
+            let mut next = frame;
+            self.execute(pc, &mut next).map_err(|e| (pc, e))?;
+
+            for target in ins.branch_targets(self.code) {
+                match &frames[target as usize] {
+                    Some(mapped) if self.is_frame_assignable(&next, mapped) => {}
+                    Some(_) => return Err((pc, format!("frame is not assignable to the one at branch target {target}"))),
+                    None => return Err((pc, format!("branch target {target} has no stack map frame"))),
+                }
+            }
+            if ins.falls_through() {
+                current = Some(next);
+            }
+        }
+        match current {
+            Some(_) => Err(((self.code.code.len() - 1) as u16, "execution falls off the end of the code".to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// the instructions of the subroutine starting at `start`, not counting the
+    /// subroutines it calls
+    fn subroutine_body(&self, start: u16) -> Vec<u16> {
+        let mut seen = vec![false; self.code.code.len()];
+        let mut todo = vec![start];
+        let mut body = vec![];
+        while let Some(pc) = todo.pop() {
+            if seen[pc as usize] {
+                continue;
+            }
+            seen[pc as usize] = true;
+            body.push(pc);
+            let ins = &self.code.instruction(pc);
+            match ins {
+                Instruction::Ret(_) => continue,
+                Instruction::Jsr(_) | Instruction::JsrW(_) => todo.push(pc + 1),
+                _ => {
+                    todo.extend(ins.branch_targets(self.code));
+                    if ins.falls_through() {
+                        todo.push(pc + 1);
+                    }
+                }
+            }
+            todo.retain(|t| (*t as usize) < seen.len());
+        }
+        body
+    }
+
+    fn subroutines(&self) -> Subroutines {
+        let mut subroutines = Subroutines::default();
+        let mut calls: HashMap<u16, Vec<u16>> = HashMap::new();
+        for (pc, ins) in self.code.instructions().iter().enumerate() {
+            if let Instruction::Jsr(target) | Instruction::JsrW(target) = ins {
+                subroutines.calls.push((pc as u16, *target));
+                calls.entry(*target).or_default();
+            }
+        }
+        // the locals each subroutine writes itself, and the subroutines it calls
+        for (&start, called) in &mut calls {
+            let mut written = vec![false; self.code.max_locals as usize];
+            let mut rets = vec![];
+            for pc in self.subroutine_body(start) {
+                let (index, size) = match self.code.instruction(pc) {
+                    Instruction::Istore(n) | Instruction::Fstore(n) | Instruction::Astore(n) => (n as usize, 1),
+                    Instruction::Lstore(n) | Instruction::Dstore(n) => (n as usize, 2),
+                    Instruction::Jsr(target) | Instruction::JsrW(target) => {
+                        called.push(target);
+                        continue;
+                    }
+                    Instruction::Ret(_) => {
+                        rets.push(pc);
+                        continue;
+                    }
+                    _ => continue,
+                };
+                for i in index..index + size {
+                    if let Some(w) = written.get_mut(i) {
+                        *w = true;
+                    }
+                }
+            }
+            subroutines.written.insert(start, written);
+            subroutines.rets.insert(start, rets);
+        }
+        // then add what the called subroutines write, until nothing changes
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (start, called) in &calls {
+                for callee in called {
+                    let callee_written = subroutines.written[callee].clone();
+                    let written = subroutines.written.get_mut(start).unwrap();
+                    for (w, c) in written.iter_mut().zip(callee_written) {
+                        if c && !*w {
+                            *w = true;
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+        subroutines
+    }
+
+    /// type inference (JVMS §4.10.2)
+    fn infer(&self) -> Result<()> {
+        self.check_code()?;
+        let len = self.code.code.len();
+        let subroutines = self.subroutines();
+        let mut frames: Vec<Option<Frame>> = vec![None; len];
+        frames[0] = Some(self.frame(&self.initial_locals(), vec![]).map_err(|e| (0, e))?);
+        let mut todo = BTreeSet::from([0u16]);
+
+        // merge `frame` into the one at `target`, and look at it again if it changed
+        let flow = |frames: &mut Vec<Option<Frame>>, todo: &mut BTreeSet<u16>, pc: u16, target: usize, frame: Frame| -> Result<()> {
+            if target >= len {
+                return Err((pc, "execution falls off the end of the code".to_string()));
+            }
+            let merged = match &frames[target] {
+                None => frame,
+                Some(old) => self.merge_frames(old, &frame).map_err(|e| (pc, format!("{e} at {target}")))?,
+            };
+            if frames[target].as_ref() != Some(&merged) {
+                frames[target] = Some(merged);
+                todo.insert(target as u16);
+            }
+            Ok(())
+        };
+
+        while let Some(pc) = todo.pop_first() {
+            let frame = frames[pc as usize].clone().unwrap();
+            for (handler, handler_frame) in self.handler_frames(pc, &frame)? {
+                flow(&mut frames, &mut todo, pc, handler as usize, handler_frame)?;
+            }
+
+            let ins = &self.code.instruction(pc);
+            let mut next = frame;
+            self.execute(pc, &mut next).map_err(|e| (pc, e))?;
+
+            match ins {
+                Instruction::Jsr(target) | Instruction::JsrW(target) => {
+                    flow(&mut frames, &mut todo, pc, *target as usize, next)?;
+                    // the locals at the return site come from this call too
+                    for ret in &subroutines.rets[target] {
+                        if frames[*ret as usize].is_some() {
+                            todo.insert(*ret);
+                        }
+                    }
+                }
+                Instruction::Ret(_) => {
+                    for (call, target) in &subroutines.calls {
+                        let (Some(caller), true) = (&frames[*call as usize], subroutines.rets[target].contains(&pc)) else {
+                            continue;
+                        };
+                        let written = &subroutines.written[target];
+                        let mut locals: Vec<VType> = caller.locals.iter().zip(&next.locals).zip(written)
+                            .map(|((c, r), w)| if *w { r.clone() } else { c.clone() })
+                            .collect();
+                        drop_broken_pairs(&mut locals);
+                        let returned = Frame { locals, stack: next.stack.clone(), this_uninit: next.this_uninit };
+                        flow(&mut frames, &mut todo, pc, *call as usize + 1, returned)?;
+                    }
+                }
+                _ => {
+                    for target in ins.branch_targets(self.code) {
+                        flow(&mut frames, &mut todo, pc, target as usize, next.clone())?;
+                    }
+                    if ins.falls_through() {
+                        flow(&mut frames, &mut todo, pc, pc as usize + 1, next)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn class_name(&self, index: u16) -> Result<String, String> {
+        self.cp().class_name(index).map_err(|e| e.to_string())
+    }
+
+    /// the name and descriptor of a `NameAndType`
+    fn name_and_type(&self, index: u16) -> Result<(String, String), String> {
+        let nat = self.cp().name_and_type(index).map_err(|e| e.to_string())?;
+        let name = self.cp().utf8(nat.name_index).map_err(|e| e.to_string())?;
+        let descriptor = self.cp().utf8(nat.descriptor_index).map_err(|e| e.to_string())?;
+        Ok((name, descriptor))
+    }
+
+    /// the class, name and descriptor of a field or method reference
+    fn member(&self, index: u16, expected: &[&str]) -> Result<(String, String, String), String> {
+        let (kind, class_index, nat_index) = match self.cp().get(index).map_err(|e| e.to_string())? {
+            ConstantPoolInfo::Fieldref(r) => ("Fieldref", r.class_index, r.name_and_type_index),
+            ConstantPoolInfo::Methodref(r) => ("Methodref", r.class_index, r.name_and_type_index),
+            ConstantPoolInfo::InterfaceMethodref(r) => ("InterfaceMethodref", r.class_index, r.name_and_type_index),
+            _ => ("", 0, 0),
+        };
+        if !expected.contains(&kind) {
+            return Err(format!("constant pool entry {index} is not a {}", expected.join(" or ")));
+        }
+        let (name, descriptor) = self.name_and_type(nat_index)?;
+        Ok((self.class_name(class_index)?, name, descriptor))
+    }
+
+    /// the type that `ldc` or `ldc2_w` pushes
+    fn constant_type(&self, index: u32, wide: bool) -> Result<VType, String> {
+        let index = u16::try_from(index).map_err(|_| format!("bad constant pool index {index}"))?;
+        let t = match self.cp().get(index).map_err(|e| e.to_string())? {
+            ConstantPoolInfo::Integer(_) => VType::Int,
+            ConstantPoolInfo::Float(_) => VType::Float,
+            ConstantPoolInfo::Long(_) => VType::Long,
+            ConstantPoolInfo::Double(_) => VType::Double,
+            ConstantPoolInfo::JString(_) => VType::Reference("java/lang/String".to_string()),
+            ConstantPoolInfo::Class(_) => VType::Reference("java/lang/Class".to_string()),
+            ConstantPoolInfo::MethodType(_) => VType::Reference("java/lang/invoke/MethodType".to_string()),
+            ConstantPoolInfo::MethodHandle(_) => VType::Reference("java/lang/invoke/MethodHandle".to_string()),
+            ConstantPoolInfo::Dynamic(d) => VType::from_descriptor(&self.name_and_type(d.name_and_type_index)?.1),
+            _ => return Err(format!("constant pool entry {index} can't be loaded")),
+        };
+        if (t.size() == 2) != wide {
+            return Err(format!("constant pool entry {index} is the wrong size for {}", if wide { "ldc2_w" } else { "ldc" }));
+        }
+        Ok(t)
+    }
+
+    fn push(&self, frame: &mut Frame, t: VType) -> Result<(), String> {
+        frame.stack.push(t);
+        if frame.stack_size() > self.code.max_stack as usize {
+            return Err(format!("operand stack overflow, max_stack is {}", self.code.max_stack));
+        }
+        Ok(())
+    }
+
+    /// pop a value that can be used as an `expected`
+    fn pop_as(&self, frame: &mut Frame, expected: &VType) -> Result<VType, String> {
+        let t = frame.pop()?;
+        if !self.is_assignable(&t, expected) {
+            return Err(format!("expected {expected} on the operand stack, found {t}"));
+        }
+        Ok(t)
+    }
+
+    fn pop_object(&self, frame: &mut Frame) -> Result<VType, String> {
+        self.pop_as(frame, &VType::Reference("java/lang/Object".to_string()))
+    }
+
+    /// pop an array whose component descriptor is one of `components`, or any
+    /// reference component if there are none. returns the component, or `None` for null
+    fn pop_array(&self, frame: &mut Frame, components: &[&str]) -> Result<Option<String>, String> {
+        let t = frame.pop()?;
+        let component = match &t {
+            VType::Null => return Ok(None),
+            VType::Reference(array) => array.strip_prefix('['),
+            _ => None,
+        };
+        match component {
+            Some(c) if components.contains(&c) => Ok(Some(c.to_string())),
+            Some(c) if components.is_empty() && (c.starts_with('L') || c.starts_with('[')) => Ok(Some(c.to_string())),
+            _ if components.is_empty() => Err(format!("expected an array of references on the operand stack, found {t}")),
+            _ => Err(format!("expected an array of {} on the operand stack, found {t}", components.join(" or "))),
+        }
+    }
+
+    fn load(&self, frame: &mut Frame, index: u16, t: VType) -> Result<(), String> {
+        let local = frame.local(index)?;
+        if *local != t || (t.size() == 2 && frame.local(index + 1).is_err()) {
+            return Err(format!("expected {t} in local variable {index}, found {local}"));
+        }
+        self.push(frame, t)
+    }
+
+    fn store(&self, frame: &mut Frame, index: u16, t: VType) -> Result<(), String> {
+        self.pop_as(frame, &t)?;
+        frame.set_local(index, t)
+    }
+
+    /// pop `a` and `b`, and push `result`
+    fn binary(&self, frame: &mut Frame, a: VType, b: VType, result: VType) -> Result<(), String> {
+        self.pop_as(frame, &b)?;
+        self.pop_as(frame, &a)?;
+        self.push(frame, result)
+    }
+
+    fn unary(&self, frame: &mut Frame, operand: VType, result: VType) -> Result<(), String> {
+        self.pop_as(frame, &operand)?;
+        self.push(frame, result)
+    }
+
+    /// run an instruction on the types in `frame`
+    fn execute(&self, pc: u16, frame: &mut Frame) -> Result<(), String> {
+        use Instruction::*;
+        use VType::{Double, Float, Int, Long, Null, Reference};
+
+        let object = || Reference("java/lang/Object".to_string());
+
+        let ins = self.code.instruction(pc);
+        match ins {
+            Nop => {}
+            AconstNull => self.push(frame, Null)?,
+            Ipush(_) => self.push(frame, Int)?,
+            Lconst(_) => self.push(frame, Long)?,
+            Fconst(_) => self.push(frame, Float)?,
+            Dconst(_) => self.push(frame, Double)?,
+            Ldc(index) => self.push(frame, self.constant_type(index, false)?)?,
+            Ldc2W(index) => self.push(frame, self.constant_type(index as u32, true)?)?,
+
+            Iload(n) => self.load(frame, n, Int)?,
+            Lload(n) => self.load(frame, n, Long)?,
+            Fload(n) => self.load(frame, n, Float)?,
+            Dload(n) => self.load(frame, n, Double)?,
+            Aload(n) => {
+                let t = frame.local(n)?.clone();
+                if !t.is_reference() {
+                    return Err(format!("expected a reference in local variable {n}, found {t}"));
+                }
+                self.push(frame, t)?
+            }
+            Istore(n) => self.store(frame, n, Int)?,
+            Lstore(n) => self.store(frame, n, Long)?,
+            Fstore(n) => self.store(frame, n, Float)?,
+            Dstore(n) => self.store(frame, n, Double)?,
+            Astore(n) => {
+                let t = frame.pop()?;
+                if !t.is_reference() && t != VType::ReturnAddress {
+                    return Err(format!("expected a reference or return address on the operand stack, found {t}"));
+                }
+                frame.set_local(n, t)?
+            }
+            Iinc(n, _) => {
+                if *frame.local(n)? != Int {
+                    return Err(format!("expected int in local variable {n}, found {}", frame.local(n)?));
+                }
+            }
+
+            Iaload | Baload | Caload | Saload | Laload | Faload | Daload | Aaload => {
+                self.pop_as(frame, &Int)?;
+                let (components, t): (&[&str], _) = match ins {
+                    Iaload => (&["I"], Int),
+                    Baload => (&["B", "Z"], Int),
+                    Caload => (&["C"], Int),
+                    Saload => (&["S"], Int),
+                    Laload => (&["J"], Long),
+                    Faload => (&["F"], Float),
+                    Daload => (&["D"], Double),
+                    _ => (&[], Null),
+                };
+                let component = self.pop_array(frame, components)?;
+                let t = match (t, component) {
+                    (Null, Some(c)) => VType::from_descriptor(&c),
+                    (t, _) => t,
+                };
+                self.push(frame, t)?
+            }
+            Iastore | Bastore | Castore | Sastore | Lastore | Fastore | Dastore | Aastore => {
+                let (components, t): (&[&str], _) = match ins {
+                    Iastore => (&["I"], Int),
+                    Bastore => (&["B", "Z"], Int),
+                    Castore => (&["C"], Int),
+                    Sastore => (&["S"], Int),
+                    Lastore => (&["J"], Long),
+                    Fastore => (&["F"], Float),
+                    Dastore => (&["D"], Double),
+                    // whether the value fits in the array is checked when it is stored
+                    _ => (&[], object()),
+                };
+                self.pop_as(frame, &t)?;
+                self.pop_as(frame, &Int)?;
+                self.pop_array(frame, components)?;
+            }
+
+            Pop => {
+                frame.pop_category1()?;
+            }
+            Pop2 => {
+                if frame.pop()?.size() == 1 {
+                    frame.pop_category1()?;
+                }
+            }
+            Dup => {
+                let v = frame.pop_category1()?;
+                frame.stack.extend([v.clone(), v]);
+            }
+            DupX1 => {
+                let v1 = frame.pop_category1()?;
+                let v2 = frame.pop_category1()?;
+                frame.stack.extend([v1.clone(), v2, v1]);
+            }
+            DupX2 => {
+                let v1 = frame.pop_category1()?;
+                let v2 = frame.pop()?;
+                if v2.size() == 2 {
+                    frame.stack.extend([v1.clone(), v2, v1]);
+                } else {
+                    let v3 = frame.pop_category1()?;
+                    frame.stack.extend([v1.clone(), v3, v2, v1]);
+                }
+            }
+            Dup2 => {
+                let v1 = frame.pop()?;
+                if v1.size() == 2 {
+                    frame.stack.extend([v1.clone(), v1]);
+                } else {
+                    let v2 = frame.pop_category1()?;
+                    frame.stack.extend([v2.clone(), v1.clone(), v2, v1]);
+                }
+            }
+            Dup2X1 => {
+                let v1 = frame.pop()?;
+                if v1.size() == 2 {
+                    let v2 = frame.pop_category1()?;
+                    frame.stack.extend([v1.clone(), v2, v1]);
+                } else {
+                    let v2 = frame.pop_category1()?;
+                    let v3 = frame.pop_category1()?;
+                    frame.stack.extend([v2.clone(), v1.clone(), v3, v2, v1]);
+                }
+            }
+            Dup2X2 => {
+                let v1 = frame.pop()?;
+                if v1.size() == 2 {
+                    let v2 = frame.pop()?;
+                    if v2.size() == 2 {
+                        frame.stack.extend([v1.clone(), v2, v1]);
+                    } else {
+                        let v3 = frame.pop_category1()?;
+                        frame.stack.extend([v1.clone(), v3, v2, v1]);
+                    }
+                } else {
+                    let v2 = frame.pop_category1()?;
+                    let v3 = frame.pop()?;
+                    if v3.size() == 2 {
+                        frame.stack.extend([v2.clone(), v1.clone(), v3, v2, v1]);
+                    } else {
+                        let v4 = frame.pop_category1()?;
+                        frame.stack.extend([v2.clone(), v1.clone(), v4, v3, v2, v1]);
+                    }
+                }
+            }
+            Swap => {
+                let v1 = frame.pop_category1()?;
+                let v2 = frame.pop_category1()?;
+                frame.stack.extend([v1, v2]);
+            }
+
+            Iadd | Isub | Imul | Idiv | Irem | Ishl | Ishr | Iushr | Iand | Ior | Ixor => self.binary(frame, Int, Int, Int)?,
+            Ladd | Lsub | Lmul | Ldiv | Lrem | Land | Lor | Lxor => self.binary(frame, Long, Long, Long)?,
+            Lshl | Lshr | Lushr => self.binary(frame, Long, Int, Long)?,
+            Fadd | Fsub | Fmul | Fdiv | Frem => self.binary(frame, Float, Float, Float)?,
+            Dadd | Dsub | Dmul | Ddiv | Drem => self.binary(frame, Double, Double, Double)?,
+            Ineg | I2b | I2c | I2s => self.unary(frame, Int, Int)?,
+            Lneg => self.unary(frame, Long, Long)?,
+            Fneg => self.unary(frame, Float, Float)?,
+            Dneg => self.unary(frame, Double, Double)?,
+            I2l => self.unary(frame, Int, Long)?,
+            I2f => self.unary(frame, Int, Float)?,
+            I2d => self.unary(frame, Int, Double)?,
+            L2i => self.unary(frame, Long, Int)?,
+            L2f => self.unary(frame, Long, Float)?,
+            L2d => self.unary(frame, Long, Double)?,
+            F2i => self.unary(frame, Float, Int)?,
+            F2l => self.unary(frame, Float, Long)?,
+            F2d => self.unary(frame, Float, Double)?,
+            D2i => self.unary(frame, Double, Int)?,
+            D2l => self.unary(frame, Double, Long)?,
+            D2f => self.unary(frame, Double, Float)?,
+            Lcmp => self.binary(frame, Long, Long, Int)?,
+            Fcmpl | Fcmpg => self.binary(frame, Float, Float, Int)?,
+            Dcmpl | Dcmpg => self.binary(frame, Double, Double, Int)?,
+
+            Ifeq(_) | Ifne(_) | Iflt(_) | Ifge(_) | Ifgt(_) | Ifle(_) |
+            Tableswitch(_) | Lookupswitch(_) => {
+                self.pop_as(frame, &Int)?;
+            }
+            IfIcmpeq(_) | IfIcmpne(_) | IfIcmplt(_) | IfIcmpge(_) | IfIcmpgt(_) | IfIcmple(_) => {
+                self.pop_as(frame, &Int)?;
+                self.pop_as(frame, &Int)?;
+            }
+            IfAcmpeq(_) | IfAcmpne(_) => {
+                for _ in 0..2 {
+                    let t = frame.pop()?;
+                    if !t.is_reference() {
+                        return Err(format!("expected a reference on the operand stack, found {t}"));
+                    }
+                }
+            }
+            Ifnull(_) | Ifnonnull(_) => {
+                let t = frame.pop()?;
+                if !t.is_reference() {
+                    return Err(format!("expected a reference on the operand stack, found {t}"));
+                }
+            }
+            Goto(_) | GotoW(_) => {}
+            Jsr(_) | JsrW(_) => self.push(frame, VType::ReturnAddress)?,
+            Ret(n) => {
+                if *frame.local(n)? != VType::ReturnAddress {
+                    return Err(format!("expected a return address in local variable {n}, found {}", frame.local(n)?));
+                }
+            }
+
+            Ireturn | Lreturn | Freturn | Dreturn | Areturn => {
+                let return_type = match (ins, &self.return_type) {
+                    (Ireturn, Some(t @ Int)) | (Lreturn, Some(t @ Long)) |
+                    (Freturn, Some(t @ Float)) | (Dreturn, Some(t @ Double)) => t,
+                    (Areturn, Some(t)) if t.is_reference() => t,
+                    _ => return Err(format!("wrong return instruction for {}", self.method.descriptor)),
+                };
+                self.pop_as(frame, return_type)?;
+            }
+            Return => {
+                if self.return_type.is_some() {
+                    return Err(format!("wrong return instruction for {}", self.method.descriptor));
+                }
+                if self.is_init() && frame.this_uninit {
+                    return Err("constructor returns before calling super() or this()".to_string());
+                }
+            }
+
+            Getstatic(index) => {
+                let (_, _, descriptor) = self.member(index, &["Fieldref"])?;
+                self.push(frame, VType::from_descriptor(&descriptor))?
+            }
+            Putstatic(index) => {
+                let (_, _, descriptor) = self.member(index, &["Fieldref"])?;
+                self.pop_as(frame, &VType::from_descriptor(&descriptor))?;
+            }
+            Getfield(index) => {
+                let (class, _, descriptor) = self.member(index, &["Fieldref"])?;
+                self.pop_as(frame, &Reference(class))?;
+                self.push(frame, VType::from_descriptor(&descriptor))?
+            }
+            Putfield(index) => {
+                let (class, _, descriptor) = self.member(index, &["Fieldref"])?;
+                self.pop_as(frame, &VType::from_descriptor(&descriptor))?;
+                // a constructor may set its own fields before calling super()
+                let t = frame.pop()?;
+                let own_field = t == VType::UninitializedThis && self.is_init() && class == self.class.name;
+                if !own_field && !self.is_assignable(&t, &Reference(class.clone())) {
+                    return Err(format!("expected '{class}' on the operand stack, found {t}"));
+                }
+            }
+            Invokevirtual(_) | Invokespecial(_) | Invokestatic(_) |
+            Invokeinterface(..) | Invokedynamic(_) => self.invoke(pc, frame)?,
+
+            New(index) => {
+                let class = self.class_name(index)?;
+                if class.starts_with('[') {
+                    return Err(format!("new of array class {class}"));
+                }
+                let t = VType::Uninitialized(pc);
+                if frame.stack.contains(&t) {
+                    return Err(format!("{t} is already on the operand stack"));
+                }
+                frame.replace(&t, &VType::Top);
+                self.push(frame, t)?
+            }
+            Newarray(atype) => {
+                let component = match atype {
+                    4 => "Z",
+                    5 => "C",
+                    6 => "F",
+                    7 => "D",
+                    8 => "B",
+                    9 => "S",
+                    10 => "I",
+                    11 => "J",
+                    _ => return Err(format!("bad newarray type {atype}")),
+                };
+                self.unary(frame, Int, Reference(format!("[{component}")))?
+            }
+            Anewarray(index) => {
+                let class = self.class_name(index)?;
+                self.unary(frame, Int, Reference(array_of(&class)))?
+            }
+            Multianewarray(index, dimensions) => {
+                let class = self.class_name(index)?;
+                if dimensions == 0 || class.bytes().take_while(|c| *c == b'[').count() < dimensions as usize {
+                    return Err(format!("{class} doesn't have {dimensions} dimensions"));
+                }
+                for _ in 0..dimensions {
+                    self.pop_as(frame, &Int)?;
+                }
+                self.push(frame, Reference(class))?
+            }
+            Arraylength => {
+                match frame.pop()? {
+                    Null => {}
+                    Reference(array) if array.starts_with('[') => {}
+                    t => return Err(format!("expected an array on the operand stack, found {t}")),
+                }
+                self.push(frame, Int)?
+            }
+            Athrow => {
+                self.pop_as(frame, &Reference("java/lang/Throwable".to_string()))?;
+            }
+            Checkcast(index) => {
+                let class = self.class_name(index)?;
+                self.unary(frame, object(), Reference(class))?
+            }
+            Instanceof(index) => {
+                self.class_name(index)?;
+                self.unary(frame, object(), Int)?
+            }
+            Monitorenter | Monitorexit => {
+                self.pop_object(frame)?;
+            }
+            Breakpoint | Impdep1 | Impdep2 => return Err("reserved opcode".to_string()),
+            GetstaticQuick(..) | PutstaticQuick(..) | GetfieldQuick(..) | PutfieldQuick(..) |
+            InvokevirtualQuick(..) | InvokespecialQuick(..) | InvokestaticQuick(..) |
+            InvokeinterfaceQuick(..) | AloadGetfieldQuick(..) => return Err("quickened instruction".to_string()),
+            IaddLocals(..) | IsubLocals(..) | ImulLocals(..) | IfIcmpLocals(..) | IfIcmpConst(..) |
+            AloadGetfield(..) => return Err("superinstruction".to_string()),
+        }
+        // the dups and swap don't go through `push`
+        if frame.stack_size() > self.code.max_stack as usize {
+            return Err(format!("operand stack overflow, max_stack is {}", self.code.max_stack));
+        }
+        Ok(())
+    }
+
+    /// `invokevirtual`, `invokespecial`, `invokestatic`, `invokeinterface` and `invokedynamic`
+    fn invoke(&self, pc: u16, frame: &mut Frame) -> Result<(), String> {
+        use Instruction::*;
+
+        let ins = self.code.instruction(pc);
+        let (class, name, descriptor) = match ins {
+            Invokedynamic(index) => {
+                let nat = match self.cp().get(index).map_err(|e| e.to_string())? {
+                    ConstantPoolInfo::InvokeDynamic(d) => d.name_and_type_index,
+                    _ => return Err(format!("constant pool entry {index} is not an InvokeDynamic")),
+                };
+                let (name, descriptor) = self.name_and_type(nat)?;
+                (String::new(), name, descriptor)
+            }
+            Invokevirtual(index) => self.member(index, &["Methodref"])?,
+            Invokeinterface(index, _) => self.member(index, &["InterfaceMethodref"])?,
+            Invokespecial(index) | Invokestatic(index) if self.class.version.major < 52 =>
+                self.member(index, &["Methodref"])?,
+            Invokespecial(index) | Invokestatic(index) =>
+                self.member(index, &["Methodref", "InterfaceMethodref"])?,
+            _ => unreachable!(),
+        };
+        let is_init = name == "<init>";
+        if name.starts_with('<') && !(is_init && matches!(ins, Invokespecial(_))) {
+            return Err(format!("can't call {name} with this instruction"));
+        }
+
+        let (args, return_type) = method_types(&descriptor)?;
+        if let Invokeinterface(_, count) = ins {
+            let slots: usize = args.iter().map(VType::size).sum();
+            if count as usize != slots + 1 {
+                return Err(format!("invokeinterface count {count} doesn't match {descriptor}"));
+            }
+        }
+        for t in args.iter().rev() {
+            self.pop_as(frame, t)?;
+        }
+
+        match ins {
+            Invokespecial(_) if is_init => {
+                if return_type.is_some() {
+                    return Err(format!("<init> must return void, not {descriptor}"));
+                }
+                match frame.pop()? {
+                    VType::UninitializedThis => {
+                        if class != self.class.name && Some(class.as_str()) != self.class.super_class {
+                            return Err(format!("{class}.<init> doesn't initialize this"));
+                        }
+                        frame.replace(&VType::UninitializedThis, &VType::Reference(self.class.name.to_string()));
+                        frame.this_uninit = false;
+                    }
+                    t @ VType::Uninitialized(at) => {
+                        let made = match self.code.instruction(at) {
+                            New(index) => self.class_name(index)?,
+                            _ => unreachable!(),
+                        };
+                        if made != class {
+                            return Err(format!("{class}.<init> called on a new {made}"));
+                        }
+                        frame.replace(&t, &VType::Reference(class));
+                    }
+                    t => return Err(format!("expected an uninitialized object on the operand stack, found {t}")),
+                }
+            }
+            Invokespecial(_) => {
+                // only methods of this class, its super classes and its interfaces
+                if !self.is_ref_assignable(self.class.name, &class) {
+                    return Err(format!("invokespecial of {class}.{name}, which isn't a super class of {}", self.class.name));
+                }
+                self.pop_as(frame, &VType::Reference(self.class.name.to_string()))?;
+            }
+            Invokevirtual(_) | Invokeinterface(..) => {
+                self.pop_as(frame, &VType::Reference(class))?;
+            }
+            _ => {}
+        }
+
+        if let Some(t) = return_type {
+            self.push(frame, t)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    /// a few classes from `java/lang`
+    struct Classes;
+
+    impl Hierarchy for Classes {
+        fn class_info(&self, name: &str) -> Option<ClassInfo> {
+            let (super_class, is_interface) = match name {
+                "java/lang/Object" => (None, false),
+                "java/lang/Runnable" => (Some("java/lang/Object"), true),
+                "java/lang/Number" | "java/lang/String" | "java/lang/Throwable" => (Some("java/lang/Object"), false),
+                "java/lang/Integer" => (Some("java/lang/Number"), false),
+                _ => return None,
+            };
+            Some(ClassInfo { super_class: super_class.map(str::to_string), is_interface })
+        }
+    }
+
+    /// verify every method of a class `T` with these methods
+    fn verify(version: &str, methods: &str) -> Result<(), VerifyError> {
+        let source = format!(".bytecode {version}\n.class public T\n.super java/lang/Object\n{methods}");
+        let cf = assembler::assemble(&source).unwrap();
+        let cp = &cf.constant_pool;
+        let context = ClassContext {
+            name: "T",
+            super_class: Some("java/lang/Object"),
+            access_flags: cf.access_flags,
+            version: cf.version(),
+            constant_pool: cp,
+        };
+        for mi in &cf.methods {
+            verify_method(&context, &JavaMethod::from_info(cp, cf.version(), mi).unwrap(), &Classes)?;
+        }
+        Ok(())
+    }
+
+    fn message(version: &str, methods: &str) -> String {
+        verify(version, methods).unwrap_err().message
+    }
+
+    const COUNT: &str = "
+        .method static count(I)I
+            .limit stack 2
+            .limit locals 2
+            iconst_0
+            istore_1
+        Loop:
+            .stack
+                locals Integer
+                locals Integer
+            .end stack
+            iload_0
+            ifle Done
+            iinc 1 1
+            iinc 0 -1
+            goto Loop
+        Done:
+            .stack
+                locals Integer
+                locals Integer
+            .end stack
+            iload_1
+            ireturn
+        .end method";
+
+    #[test]
+    fn stack_map_frames() {
+        assert_eq!(verify("52.0", COUNT), Ok(()));
+        // inferred, since version 49 classes can't have frames
+        assert_eq!(verify("49.0", COUNT), Ok(()));
+
+        let no_frame = COUNT.replacen(".stack\n                locals Integer\n                locals Integer\n            .end stack", "", 1);
+        let e = verify("52.0", &no_frame).unwrap_err();
+        assert_eq!((e.method.as_str(), e.pc, e.message.as_str()), ("count(I)I", 6, "branch target 2 has no stack map frame"));
+        // which inference can make up for, in a version 50 class
+        assert_eq!(verify("50.0", &no_frame), Ok(()));
+
+        let wrong_frame = COUNT.replacen("locals Integer\n            .end stack", "locals Float\n            .end stack", 1);
+        assert_eq!(message("52.0", &wrong_frame), "frame is not assignable to the stack map frame");
+        assert_eq!(message("52.0", &COUNT.replace("ifle Done", "pop\n            goto Done")),
+            "expected a stack map frame after an unconditional branch");
+    }
+
+    #[test]
+    fn types_and_limits() {
+        let method = |body: &str| format!(".method static f(IF)I\n.limit stack 2\n.limit locals 3\n{body}\n.end method");
+        assert_eq!(verify("52.0", &method("iload_0\niload_0\niadd\nireturn")), Ok(()));
+        assert_eq!(message("52.0", &method("iload_0\nfload_1\niadd\nireturn")), "expected int on the operand stack, found float");
+        assert_eq!(message("52.0", &method("iload_1\nireturn")), "expected int in local variable 1, found float");
+        assert_eq!(message("52.0", &method("iload_0\niload_0\niload_0\nireturn")), "operand stack overflow, max_stack is 2");
+        assert_eq!(message("52.0", &method("iload_0\nistore_3\niload_0\nireturn")), "local variable 3 is past max_locals");
+        assert_eq!(message("52.0", &method("iadd\nireturn")), "operand stack underflow");
+        assert_eq!(message("52.0", &method("iload_0\nfreturn")), "wrong return instruction for (IF)I");
+        assert_eq!(message("52.0", &method("iload_0\npop")), "execution falls off the end of the code");
+
+        // a long takes two locals, and storing over half of it breaks it
+        let long = ".method static g(J)J\n.limit stack 2\n.limit locals 2\nlload_0\nlreturn\n.end method";
+        assert_eq!(verify("52.0", long), Ok(()));
+        let broken = long.replace("lload_0", "iconst_0\nistore_1\nlload_0");
+        assert_eq!(message("52.0", &broken), "expected long in local variable 0, found top");
+
+        // references are checked against the hierarchy
+        let string = ".method static s(Ljava/lang/String;)Ljava/lang/Object;\n.limit stack 1\naload_0\nareturn\n.end method";
+        assert_eq!(verify("52.0", string), Ok(()));
+        let integer = string.replace(")Ljava/lang/Object;", ")Ljava/lang/Integer;");
+        assert_eq!(message("52.0", &integer), "expected 'java/lang/Integer' on the operand stack, found 'java/lang/String'");
+    }
+
+    #[test]
+    fn inference_merges() {
+        // x is an int on one path and a float on the other, so it can't be used after
+        let merge = "
+            .method static f(Z)I
+                .limit stack 1
+                .limit locals 2
+                iload_0
+                ifeq Float
+                iconst_1
+                istore_1
+                goto Done
+            Float:
+                fconst_1
+                fstore_1
+            Done:
+                iload_1
+                ireturn
+            .end method";
+        assert_eq!(message("49.0", merge), "expected int in local variable 1, found top");
+
+        // locals a subroutine doesn't touch keep their type from each call
+        let subroutine = "
+            .method static g()I
+                .limit stack 1
+                .limit locals 3
+                iconst_1
+                istore_0
+                jsr Sub
+                fconst_1
+                fstore_0
+                jsr Sub
+                iload_1
+                ireturn
+            Sub:
+                astore_2
+                iconst_2
+                istore_1
+                ret 2
+            .end method";
+        assert_eq!(verify("49.0", subroutine), Ok(()));
+        assert_eq!(message("49.0", &subroutine.replace("iload_1", "iload_0")), "expected int in local variable 0, found float");
+        // version 50 classes can't be type checked with subroutines, but fall back to inference
+        assert_eq!(verify("50.0", subroutine), Ok(()));
+    }
+
+    #[test]
+    fn initialization() {
+        let constructor = |body: &str| format!(".method <init>()V\n.limit stack 3\n{body}\nreturn\n.end method");
+        assert_eq!(verify("52.0", &constructor("aload_0\ninvokespecial java/lang/Object/<init>()V")), Ok(()));
+        assert_eq!(message("52.0", &constructor("")), "constructor returns before calling super() or this()");
+        assert_eq!(message("52.0", &constructor("aload_0\ninvokespecial java/lang/String/<init>()V")),
+            "java/lang/String.<init> doesn't initialize this");
+        assert_eq!(message("52.0", &constructor("aload_0\ninvokevirtual java/lang/Object/hashCode()I\npop")),
+            "expected 'java/lang/Object' on the operand stack, found uninitializedThis");
+
+        // a constructor can set its own fields before calling super()
+        let field = ".field x I\n".to_string() + &constructor("aload_0\niconst_1\nputfield T/x I\naload_0\ninvokespecial java/lang/Object/<init>()V");
+        assert_eq!(verify("52.0", &field), Ok(()));
+
+        let method = |body: &str| format!(".method static f()Ljava/lang/Object;\n.limit stack 3\n{body}\nareturn\n.end method");
+        assert_eq!(verify("52.0", &method("new java/lang/Object\ndup\ninvokespecial java/lang/Object/<init>()V")), Ok(()));
+        assert_eq!(message("52.0", &method("new java/lang/Object")),
+            "expected 'java/lang/Object' on the operand stack, found uninitialized(0)");
+        assert_eq!(message("52.0", &method("new java/lang/Object\ndup\ninvokespecial java/lang/String/<init>()V")),
+            "java/lang/String.<init> called on a new java/lang/Object");
+        assert_eq!(message("52.0", &method("aconst_null\ninvokespecial java/lang/Object/<init>()V\naconst_null")),
+            "expected an uninitialized object on the operand stack, found null");
+
+        // invokespecial of other methods needs a receiver of this class
+        let special = |class: &str| format!(
+            ".method f()I\n.limit stack 1\naload_0\ninvokespecial {class}/hashCode()I\nireturn\n.end method"
+        );
+        assert_eq!(verify("52.0", &special("java/lang/Object")), Ok(()));
+        assert_eq!(message("52.0", &special("java/lang/String")),
+            "invokespecial of java/lang/String.hashCode, which isn't a super class of T");
+    }
+}